@@ -0,0 +1,154 @@
+//! Compares the all-pairs ssdeep and CFG matching with the indexed one on synthetic signatures.
+//!
+//! `cargo bench --bench compare -- [TARGET_FUNCTIONS] [LIBRARY_FUNCTIONS]`
+
+use std::time::Instant;
+
+use ariane::sig::cfg::CfgHash;
+use ariane::sig::index::SignatureIndex;
+use ariane::sig::sig_generation::{FuzzyFunc, Hash};
+use fuzzyhash::FuzzyHash;
+
+/// Same threshold as `compare`.
+const MIN_SCORE: u32 = 25;
+
+/// xorshift, so every run compares the same functions.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+fn fuzzy_func(bytes: &[u8], name: String, hash: fn(&[u8]) -> Hash) -> FuzzyFunc {
+    FuzzyFunc {
+        rva: 0,
+        hash: hash(bytes),
+        name: Some(name),
+        origin: None,
+    }
+}
+
+/// Library functions, and target functions half of which are slightly modified library functions.
+fn generate(
+    targets: usize,
+    library: usize,
+    hash: fn(&[u8]) -> Hash,
+) -> (Vec<FuzzyFunc>, Vec<FuzzyFunc>) {
+    let mut rng = Rng(0x5eed);
+    let mut lib_bytes = vec![];
+    for _ in 0..library {
+        let len = 64 + (rng.next() % 4096) as usize;
+        lib_bytes.push(rng.bytes(len));
+    }
+
+    let mut target_functions = vec![];
+    for i in 0..targets {
+        let bytes = match i % 2 {
+            0 => {
+                let mut bytes = lib_bytes[rng.next() as usize % library].clone();
+                for _ in 0..4 {
+                    let pos = rng.next() as usize % bytes.len();
+                    bytes[pos] = rng.next() as u8;
+                }
+                bytes
+            }
+            _ => {
+                let len = 64 + (rng.next() % 4096) as usize;
+                rng.bytes(len)
+            }
+        };
+        target_functions.push(fuzzy_func(&bytes, format!("sub_{}", i), hash));
+    }
+    let lib_functions = lib_bytes
+        .iter()
+        .enumerate()
+        .map(|(i, bytes)| fuzzy_func(bytes, format!("lib_{}", i), hash))
+        .collect();
+
+    (target_functions, lib_functions)
+}
+
+fn score(target: &FuzzyFunc, lib: &FuzzyFunc) -> Option<u32> {
+    target
+        .hash
+        .compare(&lib.hash)
+        .filter(|score| *score > MIN_SCORE)
+}
+
+fn main() {
+    let args: Vec<usize> = std::env::args()
+        .skip(1)
+        .filter_map(|arg| arg.parse().ok())
+        .collect();
+    let targets = args.first().copied().unwrap_or(2_000);
+    let library = args.get(1).copied().unwrap_or(10_000);
+    println!(
+        "{} target functions, {} library functions",
+        targets, library
+    );
+
+    println!("ssdeep");
+    let (target_functions, lib_functions) = generate(targets, library, |bytes| {
+        Hash::Ssdeep(FuzzyHash::new(bytes))
+    });
+    check_index(&target_functions, &lib_functions);
+
+    println!("cfg");
+    let (target_functions, lib_functions) =
+        generate(targets, library, |bytes| Hash::Cfg(CfgHash::new(bytes)));
+    check_index(&target_functions, &lib_functions);
+}
+
+/// Matches `target_functions` with `lib_functions`, comparing every pair then using the index,
+/// which must find the same matches.
+fn check_index(target_functions: &[FuzzyFunc], lib_functions: &[FuzzyFunc]) {
+    let start = Instant::now();
+    let mut all_pairs = vec![];
+    for (t, target) in target_functions.iter().enumerate() {
+        for (l, lib) in lib_functions.iter().enumerate() {
+            if let Some(score) = score(target, lib) {
+                all_pairs.push((t, l, score));
+            }
+        }
+    }
+    let all_pairs_time = start.elapsed();
+    println!(
+        "all pairs: {:?} ({} matches)",
+        all_pairs_time,
+        all_pairs.len()
+    );
+
+    let start = Instant::now();
+    let index = SignatureIndex::new(lib_functions);
+    let build_time = start.elapsed();
+    let mut indexed = vec![];
+    for (t, target) in target_functions.iter().enumerate() {
+        for l in index.candidates(&target.hash) {
+            if let Some(score) = score(target, &lib_functions[l]) {
+                indexed.push((t, l, score));
+            }
+        }
+    }
+    let indexed_time = start.elapsed();
+    println!(
+        "indexed: {:?}, {:?} of which building the index ({} matches)",
+        indexed_time,
+        build_time,
+        indexed.len()
+    );
+
+    assert_eq!(all_pairs, indexed, "index missed or added matches");
+    println!(
+        "speedup: x{:.1}",
+        all_pairs_time.as_secs_f64() / indexed_time.as_secs_f64()
+    );
+}
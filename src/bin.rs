@@ -1,10 +1,16 @@
+use ariane::compilation::profile::BuildProfile;
+use ariane::output::{Format, NameStyle};
+use ariane::sig::comparaison::DEFAULT_THRESHOLD;
+use ariane::sig::sig_generation::Algorithm;
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 mod commands;
 
 use crate::commands::download::download_subcommand;
+use crate::commands::export::export_subcommand;
 use crate::commands::info::info_subcommand;
 use crate::commands::recover::recover_subcommand;
+use crate::commands::sig::sig_subcommand;
 
 #[derive(Subcommand, Debug)]
 enum SubCommand {
@@ -14,6 +20,10 @@ enum SubCommand {
     Download(DownloadArgs),
     /// Try to recover symbols
     Recover(RecoverArgs),
+    /// Build, inspect and merge signature databases
+    Sig(SigArgs),
+    /// Convert the JSON results of `recover` to another format
+    Export(ExportArgs),
 }
 
 #[derive(Parser, Debug)]
@@ -34,6 +44,52 @@ pub struct DownloadArgs {
     pub dest_directory: PathBuf,
 }
 
+/// How crates get built, shared by every command that builds signatures.
+#[derive(clap::Args, Debug)]
+pub struct BuildArgs {
+    /// Build profile used to compile dependencies, e.g. `opt-level=z,lto=fat,codegen-units=1,panic=abort`.
+    /// Can be given multiple times to build several variants of each crate.
+    #[clap(short, long = "profile")]
+    pub profiles: Vec<BuildProfile>,
+    /// Build every dependency with the most common release profiles
+    #[clap(long)]
+    pub profile_matrix: bool,
+    /// Compile dependencies in an isolated environment (Linux only, requires bubblewrap)
+    #[clap(long)]
+    pub sandbox: bool,
+    /// Kill the compilation of a crate after this many seconds
+    #[clap(long, value_name = "SECONDS")]
+    pub build_timeout: Option<u64>,
+    /// Maximum memory each compiler process may use when sandboxed
+    #[clap(long, value_name = "MIB")]
+    pub build_memory_limit: Option<u64>,
+    /// Where to write the JSON report of every crate build (defaults to the download directory)
+    #[clap(long)]
+    pub build_report: Option<PathBuf>,
+    /// Skip a crate as soon as it fails to build, instead of retrying with other features,
+    /// a newer toolchain or stubbed build scripts
+    #[clap(long)]
+    pub no_fallbacks: bool,
+    /// Use the toolchain installed under this prefix (containing `bin/cargo`) instead of rustup
+    #[clap(long, conflicts_with = "dist_mirror")]
+    pub toolchain_path: Option<PathBuf>,
+    /// Download toolchains from this mirror of static.rust-lang.org (url or local directory)
+    /// instead of using rustup. The mirror must be trusted: checksums are fetched from it too
+    #[clap(long, value_name = "URL_OR_DIR")]
+    pub dist_mirror: Option<String>,
+    /// Where toolchains downloaded from a mirror are unpacked
+    #[clap(long)]
+    pub toolchain_cache: Option<PathBuf>,
+    /// Also write the functions of every crate built as IDA FLIRT `.pat` files in this directory,
+    /// one per crate version
+    #[clap(long)]
+    pub pat_dir: Option<PathBuf>,
+    /// Also write the functions of every crate built in this directory, in a format
+    /// `tools/ghidra/ImportArianeFid.py` imports into a Ghidra Function ID database
+    #[clap(long)]
+    pub fid_dir: Option<PathBuf>,
+}
+
 #[derive(Parser, Debug)]
 pub struct RecoverArgs {
     pub target: String,
@@ -43,6 +99,116 @@ pub struct RecoverArgs {
     // pub dest_directory: Option<PathBuf>,
     #[clap(required = true)]
     result_file: String,
+    #[clap(flatten)]
+    build: BuildArgs,
+    /// Signature database to reuse and extend, so crates are only built once
+    /// (defaults to `signatures.json` in the download directory)
+    #[clap(long)]
+    sig_db: Option<PathBuf>,
+    /// Number of threads hashing and matching functions (defaults to the number of CPUs)
+    #[clap(short, long)]
+    jobs: Option<usize>,
+    /// Minimum similarity score (0-100, exclusive) for a library function to name a target function
+    #[clap(long, default_value_t = DEFAULT_THRESHOLD)]
+    threshold: u32,
+    /// Also report, for each named function, up to this many other names that matched it
+    #[clap(long, default_value_t = 0)]
+    top_k: usize,
+    /// Do not name the functions called by matched functions after their library counterparts' callees
+    #[clap(long)]
+    no_call_propagation: bool,
+    /// Algorithm used to compare functions: ssdeep, tlsh, or cfg to compare the structure of their control-flow graphs
+    #[clap(long, default_value_t = Algorithm::default())]
+    algorithm: Algorithm,
+    /// Also write a copy of the target with the recovered names in its symbol table (COFF symbols
+    /// for PE files, `.symtab` for ELF files)
+    #[clap(long)]
+    patch_output: Option<PathBuf>,
+    /// Also write a PDB of the recovered names for PE targets, matching the target's CodeView entry
+    /// (or the one added to the `--patch-output` copy if it has none)
+    #[clap(long)]
+    pdb_output: Option<PathBuf>,
+    /// Also write a self-contained HTML report: compiler, dependencies and their builds,
+    /// coverage, score histograms and a searchable table of the recovered symbols
+    #[clap(long)]
+    report: Option<PathBuf>,
+    /// Format of RESULT_FILE: json, idc, idapython, ghidra, binja, r2, x64dbg or nm
+    #[clap(long, default_value_t = Format::default())]
+    format: Format,
+    /// How names are written: mangled, full (demangled), hashless (demangled without hash) or
+    /// sanitized (hashless, with only characters disassemblers accept)
+    #[clap(long, default_value_t = NameStyle::default())]
+    names: NameStyle,
+}
+
+#[derive(Parser, Debug)]
+pub struct ExportArgs {
+    /// JSON file written by `recover`
+    pub results: PathBuf,
+    pub output: PathBuf,
+    /// json, idc, idapython, ghidra, binja, r2, x64dbg or nm
+    #[clap(long)]
+    pub format: Format,
+    /// mangled, full, hashless or sanitized
+    #[clap(long, default_value_t = NameStyle::default())]
+    pub names: NameStyle,
+}
+
+#[derive(Parser, Debug)]
+pub struct SigArgs {
+    /// Signature database to work on (defaults to `signatures.json` in the download directory)
+    #[clap(long, global = true)]
+    sig_db: Option<PathBuf>,
+    #[clap(subcommand)]
+    action: SigAction,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SigAction {
+    /// Build signatures of a crate and add them to the database
+    Build(SigBuildArgs),
+    /// List crate builds in the database
+    List {
+        /// Only list builds of this crate
+        #[clap(long = "crate")]
+        krate: Option<String>,
+    },
+    /// Print every signature of functions whose name contains FUNCTION
+    Show { function: String },
+    /// Add the crate builds of FROM missing from INTO
+    Merge {
+        into: PathBuf,
+        from: PathBuf,
+        /// Write the merged database here instead of overwriting INTO
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Remove empty crate builds, and those matching every given filter
+    Prune {
+        #[clap(long = "crate")]
+        krate: Option<String>,
+        #[clap(long)]
+        rustc: Option<String>,
+        #[clap(long)]
+        target: Option<String>,
+    },
+}
+
+#[derive(Parser, Debug)]
+pub struct SigBuildArgs {
+    /// Crate to build, as `<name>@<version>`
+    krate: String,
+    /// Toolchain to build with, e.g. `1.72.0`
+    #[clap(long)]
+    toolchain: String,
+    /// Features to enable besides default ones, comma separated
+    #[clap(long, value_delimiter = ',')]
+    features: Vec<String>,
+    /// Rebuild crates already in the database
+    #[clap(long)]
+    force: bool,
+    #[clap(flatten)]
+    build: BuildArgs,
 }
 
 fn main() -> Result<(), std::io::Error> {
@@ -51,14 +217,10 @@ fn main() -> Result<(), std::io::Error> {
     let args = Arguments::parse();
     // println!("{args:#?}");
     match args.cmd {
-        SubCommand::Info(subcommand_args) => {
-            return info_subcommand(&subcommand_args);
-        }
-        SubCommand::Download(subcommand_args) => {
-            return download_subcommand(&subcommand_args);
-        }
-        SubCommand::Recover(subcommand_args) => {
-            return recover_subcommand(&subcommand_args);
-        }
+        SubCommand::Info(subcommand_args) => info_subcommand(&subcommand_args),
+        SubCommand::Download(subcommand_args) => download_subcommand(&subcommand_args),
+        SubCommand::Recover(subcommand_args) => recover_subcommand(&subcommand_args),
+        SubCommand::Sig(subcommand_args) => sig_subcommand(&subcommand_args),
+        SubCommand::Export(subcommand_args) => export_subcommand(&subcommand_args),
     }
 }
@@ -0,0 +1,391 @@
+use ariane::compilation::compile::{self, CompileType};
+use ariane::compilation::fallback::{try_in_order, Fallback};
+use ariane::compilation::profile::BuildProfile;
+use ariane::compilation::report::{BuildReport, BuildReportEntry};
+use ariane::compilation::sandbox::Sandbox;
+use ariane::compilation::toolchain::{Toolchain, ToolchainManager, ToolchainSource};
+use ariane::functions_utils::search::{get_functions_from_lib, Function};
+use ariane::info_gathering::krate::Krate;
+use ariane::sig::database::{Signature, SignatureDatabase, SignatureKey, SignatureSet};
+use ariane::sig::fid::{write_fid, FidLibrary};
+use ariane::sig::flirt::write_pat;
+use flate2::read::GzDecoder;
+use log::{debug, error, info, warn};
+use std::cell::OnceCell;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tar::Archive;
+
+use crate::BuildArgs;
+
+/// Where crates are downloaded and built, and where the signature database lives by default.
+pub fn project_directory() -> PathBuf {
+    std::env::temp_dir().join("ariane")
+}
+
+pub fn default_sig_db_path() -> PathBuf {
+    project_directory().join("signatures.json")
+}
+
+pub fn open_sig_db(path: &Path) -> Result<SignatureDatabase, std::io::Error> {
+    match SignatureDatabase::open(path) {
+        Ok(db) => Ok(db),
+        Err(e) => Err(std::io::Error::other(format!(
+            "Could not open signature database {:?}: {}",
+            path, e
+        ))),
+    }
+}
+
+pub fn save_sig_db(db: &SignatureDatabase, path: &Path) -> Result<(), std::io::Error> {
+    match db.save(path) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(std::io::Error::other(format!(
+            "Could not save signature database {:?}: {}",
+            path, e
+        ))),
+    }
+}
+
+pub fn toolchain_manager(args: &BuildArgs) -> ToolchainManager {
+    let toolchain_source = match (&args.toolchain_path, &args.dist_mirror) {
+        (Some(path), _) => ToolchainSource::Path(path.clone()),
+        (None, Some(mirror)) => ToolchainSource::DistMirror(mirror.clone()),
+        (None, None) => ToolchainSource::Rustup,
+    };
+    let toolchain_cache = match &args.toolchain_cache {
+        Some(path) => path.clone(),
+        None => project_directory().join("toolchains"),
+    };
+
+    ToolchainManager::new(toolchain_source, &toolchain_cache)
+}
+
+pub fn install_toolchain(
+    toolchain_manager: &ToolchainManager,
+    version: &str,
+) -> Result<Toolchain, std::io::Error> {
+    info!("Installing toolchain : {}", version);
+    match toolchain_manager.ensure(version) {
+        Ok(toolchain) => Ok(toolchain),
+        Err(e) => Err(std::io::Error::other(format!(
+            "Could not install toolchain {}: {}",
+            version, e
+        ))),
+    }
+}
+
+pub fn sandbox(args: &BuildArgs) -> Result<Sandbox, std::io::Error> {
+    let mut sandbox = match args.sandbox {
+        true => Sandbox::isolated(),
+        false => Sandbox::default(),
+    };
+    if sandbox.enabled && !Sandbox::is_available() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "--sandbox requires bubblewrap (bwrap) on Linux",
+        ));
+    }
+    if let Some(timeout) = args.build_timeout {
+        sandbox.timeout = Some(Duration::from_secs(timeout));
+    }
+    if let Some(memory_limit) = args.build_memory_limit {
+        sandbox.memory_limit = Some(memory_limit * 1024 * 1024);
+    }
+
+    Ok(sandbox)
+}
+
+pub fn fallbacks(args: &BuildArgs) -> &'static [Fallback] {
+    match args.no_fallbacks {
+        true => &[Fallback::None],
+        false => &Fallback::ALL,
+    }
+}
+
+pub fn write_build_report(args: &BuildArgs, report: &BuildReport) -> Result<(), std::io::Error> {
+    let build_report_path = match &args.build_report {
+        Some(path) => path.clone(),
+        None => project_directory().join("build_report.json"),
+    };
+    report.write(&build_report_path)?;
+    println!("{}", report.summary());
+    println!("Build report written to {:?}", build_report_path);
+
+    Ok(())
+}
+
+pub fn extract_targz(targz_path: &Path, dest_dir: &Path) -> Result<(), std::io::Error> {
+    let mut archive = Archive::new(GzDecoder::new(std::fs::File::open(targz_path).unwrap()));
+    archive.unpack(dest_dir)?;
+
+    Ok(())
+}
+
+/// Toolchain used by `Fallback::NewerToolchain`.
+pub const NEWER_TOOLCHAIN: &str = "stable";
+
+/// How dependencies get built, shared by every crate.
+pub struct BuildSettings<'a> {
+    pub toolchain: &'a Toolchain,
+    pub toolchain_manager: &'a ToolchainManager,
+    pub sandbox: &'a Sandbox,
+    /// Strategies to try, in order, until one succeeds.
+    pub fallbacks: &'a [Fallback],
+    /// `NEWER_TOOLCHAIN`, installed the first time it is needed.
+    pub newer_toolchain: OnceCell<Option<Toolchain>>,
+    /// Where to export the functions of built crates as `.pat` files.
+    pub pat_dir: Option<&'a Path>,
+    /// Where to export the functions of built crates for Ghidra Function ID.
+    pub fid_dir: Option<&'a Path>,
+}
+
+impl BuildSettings<'_> {
+    fn newer_toolchain(&self) -> Option<&Toolchain> {
+        self.newer_toolchain
+            .get_or_init(|| {
+                if !self.toolchain_manager.supports_channels() {
+                    debug!("Toolchain source cannot provide {}", NEWER_TOOLCHAIN);
+                    return None;
+                }
+                info!("Installing toolchain : {}", NEWER_TOOLCHAIN);
+                match self.toolchain_manager.ensure(NEWER_TOOLCHAIN) {
+                    Ok(toolchain) => Some(toolchain),
+                    Err(e) => {
+                        error!("Could not install toolchain {}: {}", NEWER_TOOLCHAIN, e);
+                        None
+                    }
+                }
+            })
+            .as_ref()
+    }
+}
+
+/// Returns the compiled library along with the strategy that was needed to build it.
+pub fn download_extract_compile(
+    krate: &mut Krate,
+    dest_dir: &Path,
+    settings: &BuildSettings,
+    compile_type: CompileType,
+    profile: &BuildProfile,
+    report: &mut BuildReport,
+) -> Option<(PathBuf, Fallback)> {
+    let krate_full_name = format!("{}-{:#}", krate.name.clone(), krate.version);
+    let version = krate.version.to_string();
+    let profile_name = profile.to_string();
+
+    let extracted_path = dest_dir.join(PathBuf::from(&krate_full_name));
+    if !extracted_path.exists() {
+        let targz_path = match krate.download(dest_dir) {
+            Ok(path) => path,
+            Err(e) => {
+                report.push(BuildReportEntry::from_setup_error(
+                    &krate.name,
+                    &version,
+                    &profile_name,
+                    &format!("Download failed: {}", e),
+                ));
+                return None;
+            }
+        };
+        if let Err(e) = extract_targz(targz_path.as_path(), dest_dir) {
+            report.push(BuildReportEntry::from_setup_error(
+                &krate.name,
+                &version,
+                &profile_name,
+                &format!("Extraction failed: {}", e),
+            ));
+            return None;
+        }
+    }
+    let features = match krate.get_features() {
+        Ok(features) => features.clone(),
+        Err(e) => {
+            report.push(BuildReportEntry::from_setup_error(
+                &krate.name,
+                &version,
+                &profile_name,
+                &format!("Could not retrieve crate features: {}", e),
+            ));
+            return None;
+        }
+    };
+    try_in_order(settings.fallbacks, &features, |fallback| {
+        let toolchain = match fallback {
+            Fallback::NewerToolchain => settings.newer_toolchain()?,
+            _ => settings.toolchain,
+        };
+        if fallback != Fallback::None {
+            info!("Retrying {:#} with fallback: {}", krate, fallback);
+        }
+
+        let output = match compile::compile(
+            &extracted_path.join("Cargo.toml"),
+            toolchain,
+            &features,
+            compile_type,
+            profile,
+            settings.sandbox,
+            fallback,
+        ) {
+            Ok(output) => output,
+            Err(e) => {
+                error!("Could not run cargo for {:#}: {}", krate, e);
+                report.push(
+                    BuildReportEntry::from_setup_error(
+                        &krate.name,
+                        &version,
+                        &profile_name,
+                        &format!("Could not run cargo: {}", e),
+                    )
+                    .with_fallback(fallback),
+                );
+                return None;
+            }
+        };
+        let entry = BuildReportEntry::from_output(&krate.name, &version, &profile_name, &output)
+            .with_fallback(fallback);
+        if !output.success() {
+            report.push(entry);
+            return None;
+        }
+
+        let release_dir = compile::release_dir(&extracted_path, profile);
+        let result_path = match compile_type {
+            CompileType::Dylib => release_dir.join(format!("{}.pdb", krate.name)),
+            CompileType::StaticLib => {
+                release_dir.join(format!("lib{}.rlib", krate.name.replace("-", "_")))
+            }
+        };
+        if !result_path.exists() {
+            warn!("{:#} built but {:?} is missing", krate, result_path);
+            report.push(entry.with_missing_artifact(&result_path));
+            return None;
+        }
+
+        report.push(entry);
+        Some(result_path)
+    })
+}
+
+/// Writes `functions` to `<pat_dir>/<krate>-<version>.pat`.
+fn export_pat(pat_dir: &Path, krate: &str, version: &str, functions: &[Function]) {
+    let path = pat_dir.join(format!("{}-{}.pat", krate, version));
+    match write_pat(&path, functions) {
+        Ok(added) => info!("{} patterns written to {:?}", added, path),
+        Err(e) => error!("Could not write {:?}: {}", path, e),
+    }
+}
+
+/// Writes `functions` to `<fid_dir>/<krate>-<version>.fid.json`, as the build of `krate` described
+/// by `key`.
+fn export_fid(
+    fid_dir: &Path,
+    krate: &str,
+    version: &str,
+    key: &SignatureKey,
+    functions: &[Function],
+) {
+    let path = fid_dir.join(format!("{}-{}.fid.json", krate, version));
+    let mut variant = format!("{} {} {}", key.rustc, key.target, key.profile);
+    if !key.features.is_empty() {
+        variant = format!("{} {}", variant, key.features.join(","));
+    }
+    let library = FidLibrary::new(krate, version, &variant, &key.target, functions);
+    match write_fid(&path, library) {
+        Ok(written) => info!("{} Function ID entries written to {:?}", written, path),
+        Err(e) => error!("Could not write {:?}: {}", path, e),
+    }
+}
+
+/// Profile recorded for signatures of the standard library, which comes precompiled.
+pub const STD_PROFILE: &str = "precompiled";
+
+/// Signatures of `std`, `core` and `alloc` for `key.target`, from the rlibs shipped with the
+/// toolchain. Their functions are also exported to `pat_dir` and `fid_dir`, if given.
+pub fn std_signatures(
+    toolchain_manager: &ToolchainManager,
+    toolchain: &Toolchain,
+    key: SignatureKey,
+    pat_dir: Option<&Path>,
+    fid_dir: Option<&Path>,
+) -> Result<SignatureSet, std::io::Error> {
+    if let Err(e) = toolchain_manager.ensure_target(toolchain, &key.target) {
+        return Err(std::io::Error::other(format!(
+            "Could not install the standard library for {}: {}",
+            key.target, e
+        )));
+    }
+
+    let mut set = SignatureSet::from_functions(key, None, &[]);
+    for rlib in toolchain.std_rlibs(&set.key.target)? {
+        let rlib_bytes = std::fs::read(&rlib)?;
+        let lib_fn = get_functions_from_lib(&rlib_bytes)?;
+        info!("{} functions found in {:?}", lib_fn.len(), rlib);
+        // lib<crate>-<hash>.rlib
+        let krate = rlib
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.trim_start_matches("lib").split('-').next())
+            .unwrap_or("std");
+        if let Some(pat_dir) = pat_dir {
+            export_pat(pat_dir, krate, &set.key.rustc, &lib_fn);
+        }
+        if let Some(fid_dir) = fid_dir {
+            export_fid(fid_dir, krate, &set.key.rustc, &set.key, &lib_fn);
+        }
+        set.signatures
+            .extend(lib_fn.iter().filter_map(Signature::from_function));
+    }
+
+    Ok(set)
+}
+
+/// Builds `krate` with `profile` and returns the signatures of its functions, stored under `key`.
+pub fn build_signatures(
+    krate: &mut Krate,
+    dest_dir: &Path,
+    settings: &BuildSettings,
+    profile: &BuildProfile,
+    key: SignatureKey,
+    report: &mut BuildReport,
+) -> Option<SignatureSet> {
+    let (lib_path, fallback) = download_extract_compile(
+        krate,
+        dest_dir,
+        settings,
+        CompileType::StaticLib,
+        profile,
+        report,
+    )?;
+    info!("Compiled {:?}", &lib_path);
+    let lib_bytes = match std::fs::read(&lib_path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Lib {:?} could not be read: {}", &lib_path, e);
+            return None;
+        }
+    };
+    let lib_fn = match get_functions_from_lib(&lib_bytes) {
+        Ok(lib_fn) => lib_fn,
+        Err(e) => {
+            error!("Lib {:?} could not be parsed: {}", &lib_path, e);
+            return None;
+        }
+    };
+    info!("{} functions found", lib_fn.len());
+    if let Some(pat_dir) = settings.pat_dir {
+        export_pat(pat_dir, &krate.name, &krate.version.to_string(), &lib_fn);
+    }
+    if let Some(fid_dir) = settings.fid_dir {
+        export_fid(
+            fid_dir,
+            &krate.name,
+            &krate.version.to_string(),
+            &key,
+            &lib_fn,
+        );
+    }
+
+    Some(SignatureSet::from_functions(key, Some(fallback), &lib_fn))
+}
@@ -16,7 +16,7 @@ pub fn download_subcommand(args: &DownloadArgs) -> Result<(), std::io::Error> {
     ));
     let rustc_commit_hash: CommitHash = rustc_information.get_commit_hash().to_owned();
     let rustc_version = rustc_commit_hash
-        .search_rustc_version()
+        .search_rustc_version()?
         .expect("Could not find rustc version from your target !");
 
     println!(
@@ -0,0 +1,14 @@
+use ariane::output::export;
+use ariane::output::results::Results;
+use log::info;
+
+use crate::ExportArgs;
+
+pub fn export_subcommand(args: &ExportArgs) -> Result<(), std::io::Error> {
+    let results = Results::from_slice(&std::fs::read(&args.results)?)?;
+
+    std::fs::write(&args.output, export(args.format, &results, args.names))?;
+    info!("{} written to {:?}", args.format, args.output);
+
+    Ok(())
+}
@@ -1,8 +1,9 @@
 use ariane::{
     compilation::compile,
     info_gathering::{
-        compiler::{CommitHash, RustcInformation},
+        compiler::{guess_target_triple, CommitHash, RustcInformation},
         krate::Dependencies,
+        profile_guess::ProfileGuess,
     },
 };
 use log::{debug, error, info, log_enabled, Level};
@@ -18,7 +19,7 @@ pub fn info_subcommand(args: &InfoArgs) -> Result<(), std::io::Error> {
     ));
     let rustc_commit_hash: CommitHash = rustc_information.get_commit_hash().to_owned();
     let rustc_version = rustc_commit_hash
-        .search_rustc_version()
+        .search_rustc_version()?
         .expect("Could not find rustc version from your target !");
 
     println!(
@@ -27,6 +28,16 @@ pub fn info_subcommand(args: &InfoArgs) -> Result<(), std::io::Error> {
         rustc_commit_hash.commit_hash_to_string()
     );
 
+    if let Some(target) = guess_target_triple(&bytes) {
+        println!("Target: {}\n", target);
+    }
+
+    let profile = ProfileGuess::from_buffer(&bytes);
+    println!("Build profile guess:");
+    println!("  opt-level: {}", profile.opt_level);
+    println!("  lto: {}", profile.lto);
+    println!("  panic: {}\n", profile.panic);
+
     let deps = Dependencies::from_buffer(&bytes);
     for dep in deps.get_dependencies() {
         println!("{:#}", dep);
@@ -1,3 +1,6 @@
+pub mod build;
 pub mod download;
+pub mod export;
 pub mod info;
 pub mod recover;
+pub mod sig;
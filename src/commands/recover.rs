@@ -1,38 +1,34 @@
-use ariane::compilation::compile::{self, CompileType};
-use ariane::info_gathering::compiler::RustcInformation;
+use ariane::compilation::profile::BuildProfile;
+use ariane::compilation::report::BuildReport;
+use ariane::info_gathering::compiler::{guess_target_triple, RustcInformation};
+use ariane::info_gathering::profile_guess::ProfileGuess;
 use ariane::sig::sig_generation::{hash_functions, FuzzyFunc};
-use clap::{Parser, Subcommand};
-use flate2::read::GzDecoder;
-use fuzzyhash::FuzzyHash;
 use goblin::pe::section_table::SectionTable;
-use log::{debug, error, info, log_enabled, Level};
-use semver::Version;
+use log::{error, info};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cell::OnceCell;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::fs::File;
 use std::io::{BufReader, Write};
-use std::path::{Path, PathBuf};
-use std::process::{Command, ExitStatus};
-use std::result;
-use tar::Archive;
-
-use ariane::functions_utils::search::{
-    get_functions_from_bin, get_functions_from_lib, get_functions_from_pdb, FunctionType,
-};
+use std::path::Path;
+
+use ariane::functions_utils::search::{get_functions_from_bin, FunctionType};
 use ariane::functions_utils::search::{rva_to_pa, Function};
-use ariane::info_gathering::krate::{self, Dependencies, Krate};
+use ariane::info_gathering::krate::Dependencies;
+use ariane::output::export;
+use ariane::output::html;
+use ariane::output::results::{DetectedCrate, Results, Rustc, Sample};
+use ariane::sig::assignment::assign;
+use ariane::sig::callgraph::{propagate, LibraryCallGraph, TargetCallGraph};
 use ariane::sig::comparaison::compare;
-use ariane::sig::comparaison::Symbol;
+use ariane::sig::database::SignatureKey;
+use ariane::sig::pattern::{PatternMatcher, MIN_PATTERN_LENGTH};
+use ariane::utils::patch::{add_symbols, NamedFunction};
+use ariane::utils::pdb_writer::{add_codeview_entry, codeview_identity, write_pdb, PdbIdentity};
 
+use crate::commands::build::{self, BuildSettings};
 use crate::RecoverArgs;
 
-#[derive(Serialize, Deserialize, Default)]
-struct RecoveredSymbols {
-    // dll_name: String,
-    symbols: Vec<Symbol>,
-}
-
 #[derive(Serialize, Deserialize)]
 /// {
 ///   "functions": [
@@ -72,6 +68,7 @@ impl InputFunctions {
                         data: &file_content[start_pa as usize..end_pa as usize],
                         name: Some(func.name.to_owned()),
                         fn_type: FunctionType::Exe,
+                        relocations: vec![],
                     })
                 }
             }
@@ -91,89 +88,55 @@ fn parse_input_fn_to_functions(filepath: &Path) -> Result<InputFunctions, Box<dy
     Ok(u)
 }
 
-pub fn install_toolchain(compiler_version: &str) -> ExitStatus {
-    let cmd = Command::new("rustup")
-        .args(["install", compiler_version])
-        .output()
-        .expect("please install rustup");
-    debug!(
-        "{:?}, {:?}",
-        String::from_utf8(cmd.stdout),
-        String::from_utf8(cmd.stderr)
-    );
-    cmd.status
-}
-
-pub fn extract_targz(targz_path: &Path, dest_dir: &Path) -> Result<(), std::io::Error> {
-    let mut archive = Archive::new(GzDecoder::new(std::fs::File::open(&targz_path).unwrap()));
-    archive.unpack(&dest_dir)?;
-
-    Ok(())
-}
-
-fn download_extract_compile(
-    krate: &mut Krate,
-    dest_dir: &Path,
-    compiler_version: &str,
-    compile_type: CompileType,
-) -> Option<PathBuf> {
-    let krate_full_name = format!("{}-{:#}", krate.name.clone(), krate.version);
-
-    let extracted_path = dest_dir.join(PathBuf::from(&krate_full_name));
-    let targz_path = match krate.download(&dest_dir) {
-        Ok(path) => path,
-        Err(_) => return None,
-    };
-    extract_targz(targz_path.as_path(), &dest_dir.join(dest_dir));
-    compile::compile(
-        &extracted_path.join("Cargo.toml"),
-        &compiler_version,
-        krate.get_features().unwrap(),
-        compile_type,
-    );
-
-    let result_path = match compile_type {
-        CompileType::Dylib => extracted_path.join(format!("target\\release\\{}.pdb", krate.name)),
-        CompileType::StaticLib => extracted_path.join(format!(
-            "target\\release\\lib{}.rlib",
-            krate.name.replace("-", "_")
-        )),
-    };
-
-    match result_path.exists() {
-        true => Some(result_path),
-        false => None,
+/// Writes the outputs of `--patch-output` and `--pdb-output`. When the target has no CodeView
+/// entry, the patched copy gets one pointing to the PDB.
+fn write_symbol_outputs(
+    args: &RecoverArgs,
+    bytes: &[u8],
+    named: &[NamedFunction],
+) -> Result<(), std::io::Error> {
+    let mut patched = bytes.to_vec();
+    if let Some(pdb_output) = &args.pdb_output {
+        let identity = match codeview_identity(bytes)? {
+            Some(identity) => {
+                info!("Debuggers will look for the PDB at {}", identity.path);
+                identity
+            }
+            None => {
+                let identity = PdbIdentity::from_content(bytes, &pdb_output.to_string_lossy());
+                match args.patch_output {
+                    Some(_) => patched = add_codeview_entry(&patched, &identity)?,
+                    None => error!(
+                        "Target has no CodeView entry, use --patch-output to get a copy referencing the PDB"
+                    ),
+                }
+                identity
+            }
+        };
+        std::fs::write(pdb_output, write_pdb(bytes, &identity, named)?)?;
+        info!("PDB written to {:?}", pdb_output);
     }
-}
 
-fn compile_hello_world_crate(compiler_version: &str) -> Option<PathBuf> {
-    let cmd = Command::new("cargo")
-        .args(["new", "hello_world_for_std", "--lib"])
-        .current_dir(std::env::temp_dir().join("ariane"))
-        .output()
-        .expect("Could not find cargo.exe");
-    debug!(
-        "Exit status : {}\n{}",
-        cmd.status,
-        // String::from_utf8_lossy(cmd.stdout.as_ref()),
-        String::from_utf8_lossy(cmd.stderr.as_ref())
-    );
-    let toml_path = std::env::temp_dir()
-        .join("ariane\\hello_world_for_std")
-        .join("Cargo.toml");
-
-    compile::compile(&toml_path, compiler_version, &vec![], CompileType::Dylib);
-    let result_path = std::env::temp_dir()
-        .join("ariane\\hello_world_for_std\\target\\release\\hello_world_for_std.pdb");
-
-    match result_path.exists() {
-        true => Some(result_path),
-        false => None,
+    if let Some(patch_output) = &args.patch_output {
+        std::fs::write(patch_output, add_symbols(&patched, named)?)?;
+        std::fs::set_permissions(patch_output, std::fs::metadata(&args.target)?.permissions())?;
+        info!("Patched executable written to {:?}", patch_output);
     }
+
+    Ok(())
 }
 
 pub fn recover_subcommand(args: &RecoverArgs) -> Result<(), std::io::Error> {
     // let args = Arguments::parse();
+    if let Some(jobs) = args.jobs {
+        if let Err(e) = rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+        {
+            error!("Could not set the number of threads: {}", e);
+        }
+    }
+
     let bytes = std::fs::read(&args.target)?;
     let mut target_functions = vec![];
 
@@ -182,97 +145,220 @@ pub fn recover_subcommand(args: &RecoverArgs) -> Result<(), std::io::Error> {
             .expect("Invalid file or malformed content");
         target_functions = input_functions.to_functions(&bytes);
     } else {
-        target_functions =
-            get_functions_from_bin(&bytes, 20).expect("Could not read functions from target");
+        target_functions = get_functions_from_bin(&bytes, MIN_PATTERN_LENGTH)
+            .expect("Could not read functions from target");
     }
 
     info!("Target has {} functions", target_functions.len());
 
     let mut compiler_version = String::new();
+    let mut rustc = None;
 
     if let Some(compiler_info) = RustcInformation::from_buffer(&bytes) {
         let rustc_commit_hash = compiler_info.get_commit_hash();
         info!("{}", rustc_commit_hash.commit_hash_to_string());
         compiler_version = rustc_commit_hash
-            .search_rustc_version()
+            .search_rustc_version()?
             .expect("Could not find rustc version from your target !");
+        rustc = Some(Rustc {
+            version: compiler_version.clone(),
+            commit_hash: rustc_commit_hash.commit_hash_to_string().clone(),
+        });
     }
 
-    info!("Installing toolchain : {}", compiler_version);
-    if !install_toolchain(compiler_version.as_str()).success() {
-        panic!("Could not install toolchain {} !", compiler_version);
-    }
+    let build_args = &args.build;
+    let toolchain_manager = build::toolchain_manager(build_args);
+    let toolchain = build::install_toolchain(&toolchain_manager, &compiler_version)?;
+    let sandbox = build::sandbox(build_args)?;
 
     info!("Finding deps");
     let mut deps: Dependencies = Dependencies::from_buffer(&bytes);
 
     info!("Preparing donwload directory under %TEMP%\\ariane");
-    let tmp_path = std::env::temp_dir();
-    let projet_directory = tmp_path.clone().join("ariane");
+    let projet_directory = build::project_directory();
     std::fs::create_dir_all(&projet_directory)?;
 
+    let profiles = if build_args.profile_matrix {
+        BuildProfile::default_matrix()
+    } else if !build_args.profiles.is_empty() {
+        build_args.profiles.clone()
+    } else {
+        let guess = ProfileGuess::from_functions(&bytes, &target_functions);
+        info!("Guessed opt-level: {}", guess.opt_level);
+        info!("Guessed lto: {}", guess.lto);
+        info!("Guessed panic strategy: {}", guess.panic);
+        vec![guess.to_build_profile()]
+    };
+
     // let mut compiled_dll_paths = vec![];
     let mut deps_krates = deps.get_dependencies_mut();
 
     let mut lib_functions: Vec<FuzzyFunc> = vec![];
+    let mut lib_calls = LibraryCallGraph::default();
+    let mut lib_patterns = PatternMatcher::default();
+    let mut build_report = BuildReport::default();
+    let mut crates = vec![];
+
+    let build_settings = BuildSettings {
+        toolchain: &toolchain,
+        toolchain_manager: &toolchain_manager,
+        sandbox: &sandbox,
+        fallbacks: build::fallbacks(build_args),
+        newer_toolchain: OnceCell::new(),
+        pat_dir: build_args.pat_dir.as_deref(),
+        fid_dir: build_args.fid_dir.as_deref(),
+    };
+
+    let sig_db_path = match &args.sig_db {
+        Some(path) => path.clone(),
+        None => build::default_sig_db_path(),
+    };
+    let mut sig_db = build::open_sig_db(&sig_db_path)?;
+    // Crates are built for the host
+    let host = toolchain.host()?;
 
     for cr in deps_krates {
-        if let Some(lib_path) = download_extract_compile(
-            cr,
-            &projet_directory,
-            &compiler_version,
-            CompileType::StaticLib,
-        ) {
-            info!("Compiled {:?}", &lib_path);
-            let lib_bytes = std::fs::read(lib_path.clone())
-                .expect(&format!("Lib {:?} could not be read", &lib_path));
-            let lib_fn = get_functions_from_lib(&lib_bytes);
-            info!("{} functions found", lib_fn.len());
-            lib_functions.append(&mut hash_functions(&lib_fn));
-        } else {
-            error!("Could not compile {:#}", cr);
+        let features = cr.get_features().cloned().unwrap_or_default();
+        crates.push(DetectedCrate {
+            name: cr.name.clone(),
+            version: cr.version.to_string(),
+            features: features.clone(),
+            evidence: cr.get_evidence().clone(),
+        });
+        for profile in &profiles {
+            let key = SignatureKey::new(
+                &cr.name,
+                &cr.version.to_string(),
+                &features,
+                &compiler_version,
+                &host,
+                &profile.to_string(),
+            );
+            if let Some(set) = sig_db.get(&key) {
+                info!("Using signatures of {} from database", key);
+                lib_functions.append(&mut set.fuzzy_funcs(args.algorithm));
+                lib_patterns.add(set);
+                lib_calls.add(set);
+                continue;
+            }
+
+            if let Some(set) = build::build_signatures(
+                cr,
+                &projet_directory,
+                &build_settings,
+                profile,
+                key,
+                &mut build_report,
+            ) {
+                lib_functions.append(&mut set.fuzzy_funcs(args.algorithm));
+                lib_patterns.add(&set);
+                lib_calls.add(&set);
+                sig_db.insert(set);
+            } else {
+                error!("Could not compile {:#} ({})", cr, profile);
+            }
+        }
+    }
+
+    match guess_target_triple(&bytes) {
+        Some(target) => {
+            let key = SignatureKey::new(
+                "std",
+                &compiler_version,
+                &[],
+                &compiler_version,
+                &target,
+                build::STD_PROFILE,
+            );
+            match sig_db.get(&key) {
+                Some(set) => {
+                    lib_functions.append(&mut set.fuzzy_funcs(args.algorithm));
+                    lib_patterns.add(set);
+                    lib_calls.add(set);
+                }
+                None => match build::std_signatures(
+                    &toolchain_manager,
+                    &toolchain,
+                    key,
+                    build_args.pat_dir.as_deref(),
+                    build_args.fid_dir.as_deref(),
+                ) {
+                    Ok(set) => {
+                        lib_functions.append(&mut set.fuzzy_funcs(args.algorithm));
+                        lib_patterns.add(&set);
+                        lib_calls.add(&set);
+                        sig_db.insert(set);
+                    }
+                    Err(e) => error!("Could not get std signatures: {}", e),
+                },
+            }
         }
+        None => error!("Could not guess target triple, std functions will not be recognized"),
     }
 
-    let std_crate_pdb =
-        compile_hello_world_crate(&compiler_version).expect("Could not compile std crate");
-    info!("Compiled {:?}", &std_crate_pdb);
-    let mut dll_path = std_crate_pdb.clone();
-    dll_path.set_extension("dll");
-    let dll_bytes = std::fs::read(dll_path)?;
-    let lib_fn = &get_functions_from_pdb(&dll_bytes, &std_crate_pdb)
-        .expect("Could not extract functions from pdb");
-    info!("{} functions found", lib_fn.len());
+    if let Err(e) = sig_db.save(&sig_db_path) {
+        error!("Could not save signature database {:?}: {}", sig_db_path, e);
+    }
 
-    lib_functions.append(&mut hash_functions(&lib_fn));
+    build::write_build_report(build_args, &build_report)?;
 
     info!("Hash target functions");
-    let hashed_functions_target = hash_functions(&target_functions);
-
-    let mut result: Vec<RecoveredSymbols> = vec![];
-    let mut hash_dll_functions = HashMap::<String, String>::new();
+    let hashed_functions_target = hash_functions(&target_functions, args.algorithm);
 
-    for hash in &lib_functions {
-        if let Some(fn_name) = hash.name.as_ref() {
-            if !hash_dll_functions.contains_key(fn_name) {
-                hash_dll_functions.insert(fn_name.to_string(), hash.hash.hash.to_string());
-            }
-        }
-    }
+    // Profiles often produce the exact same code, only keep one signature per (name, hash).
+    let mut seen_signatures = HashSet::<(String, String)>::new();
+    lib_functions.retain(|f| match &f.name {
+        Some(name) => seen_signatures.insert((name.clone(), f.hash.to_string())),
+        None => false,
+    });
 
     info!("Compare target hashes with lib hashes");
-    let syms = compare(&hashed_functions_target, &hash_dll_functions);
-
-    result.push(RecoveredSymbols { symbols: syms });
+    let mut candidates = compare(&hashed_functions_target, &lib_functions, args.threshold);
+    info!("Match target functions with lib patterns");
+    candidates.append(&mut lib_patterns.matches(&target_functions, args.threshold));
+    let mut syms = assign(candidates, args.top_k);
+    if !args.no_call_propagation {
+        info!("Propagate names through calls");
+        let target_calls = TargetCallGraph::new(&target_functions);
+        syms = propagate(syms, &target_calls, &lib_calls, args.threshold);
+    }
+    info!("{} functions named", syms.len());
 
+    let sample = Sample::new(Path::new(&args.target), &bytes, target_functions.len());
+    let results = Results::new(sample, rustc, crates, args.algorithm, args.threshold, syms);
     let mut f = std::fs::OpenOptions::new()
         .create(true)
         .write(true)
         .truncate(true)
         .open(args.result_file.clone())
         .expect("File could not be open for writing");
-    let j: String = serde_json::to_string(&result).unwrap();
-    f.write(j.as_bytes())?;
+    f.write_all(export(args.format, &results, args.names).as_bytes())?;
+
+    if let Some(report) = &args.report {
+        std::fs::write(report, html::report(&results, &build_report, args.names))?;
+        info!("Report written to {:?}", report);
+    }
+
+    if args.patch_output.is_some() || args.pdb_output.is_some() {
+        let sizes: HashMap<u32, u32> = target_functions
+            .iter()
+            .map(|f| (f.rva, f.data.len() as u32))
+            .collect();
+        let named: Vec<NamedFunction> = results
+            .symbols
+            .iter()
+            .map(|s| NamedFunction {
+                name: &s.name,
+                rva: s.rva,
+                size: sizes.get(&s.rva).copied().unwrap_or(0),
+            })
+            .collect();
+        // The results are already written, a target the symbols cannot be added to should not
+        // fail the whole run.
+        if let Err(e) = write_symbol_outputs(args, &bytes, &named) {
+            error!("Could not write symbol outputs: {}", e);
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,191 @@
+use ariane::compilation::profile::BuildProfile;
+use ariane::compilation::report::BuildReport;
+use ariane::info_gathering::krate::Krate;
+use ariane::sig::database::SignatureKey;
+use log::{error, info};
+use semver::Version;
+use std::cell::OnceCell;
+use std::path::Path;
+
+use crate::commands::build::{self, BuildSettings};
+use crate::{SigAction, SigArgs, SigBuildArgs};
+
+pub fn sig_subcommand(args: &SigArgs) -> Result<(), std::io::Error> {
+    let sig_db_path = match &args.sig_db {
+        Some(path) => path.clone(),
+        None => build::default_sig_db_path(),
+    };
+
+    match &args.action {
+        SigAction::Build(build_args) => build_subcommand(build_args, &sig_db_path),
+        SigAction::List { krate } => list_subcommand(krate.as_deref(), &sig_db_path),
+        SigAction::Show { function } => show_subcommand(function, &sig_db_path),
+        SigAction::Merge { into, from, output } => {
+            let mut db = build::open_sig_db(into)?;
+            let added = db.merge(build::open_sig_db(from)?);
+            let output = output.as_ref().unwrap_or(into);
+            build::save_sig_db(&db, output)?;
+            println!("{} signature sets added to {:?}", added, output);
+            Ok(())
+        }
+        SigAction::Prune {
+            krate,
+            rustc,
+            target,
+        } => {
+            let mut db = build::open_sig_db(&sig_db_path)?;
+            let before = db.sets.len();
+            let filtered = krate.is_some() || rustc.is_some() || target.is_some();
+            db.sets.retain(|set| {
+                if set.signatures.is_empty() {
+                    return false;
+                }
+                let matches = krate.as_ref().is_none_or(|k| &set.key.krate == k)
+                    && rustc.as_ref().is_none_or(|r| &set.key.rustc == r)
+                    && target.as_ref().is_none_or(|t| &set.key.target == t);
+                !(filtered && matches)
+            });
+            build::save_sig_db(&db, &sig_db_path)?;
+            println!("{} signature sets removed", before - db.sets.len());
+            Ok(())
+        }
+    }
+}
+
+fn build_subcommand(args: &SigBuildArgs, sig_db_path: &Path) -> Result<(), std::io::Error> {
+    let (name, version) = match args.krate.split_once('@') {
+        Some((name, version)) => (name, version),
+        None => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Crate must be given as <name>@<version>",
+            ))
+        }
+    };
+    let version = match Version::parse(version) {
+        Ok(version) => version,
+        Err(e) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid version {}: {}", version, e),
+            ))
+        }
+    };
+    let mut krate = Krate::new(name, version).with_features(&args.features);
+
+    let build_args = &args.build;
+    // Unlike `recover`, there is no executable to guess the profile from
+    let profiles = if build_args.profile_matrix {
+        BuildProfile::default_matrix()
+    } else if !build_args.profiles.is_empty() {
+        build_args.profiles.clone()
+    } else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "sig build requires --profile or --profile-matrix",
+        ));
+    };
+
+    let toolchain_manager = build::toolchain_manager(build_args);
+    let toolchain = build::install_toolchain(&toolchain_manager, &args.toolchain)?;
+    // `recover` looks signatures up by the release the target was built with, channels such as
+    // `stable` are resolved to theirs
+    let rustc = toolchain
+        .release()
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    if Version::parse(&rustc).map_or(true, |v| !v.pre.is_empty()) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Toolchain {} is rustc {}, only stable releases can be recovered",
+                args.toolchain, rustc
+            ),
+        ));
+    }
+    let sandbox = build::sandbox(build_args)?;
+    let build_settings = BuildSettings {
+        toolchain: &toolchain,
+        toolchain_manager: &toolchain_manager,
+        sandbox: &sandbox,
+        fallbacks: build::fallbacks(build_args),
+        newer_toolchain: OnceCell::new(),
+        pat_dir: build_args.pat_dir.as_deref(),
+        fid_dir: build_args.fid_dir.as_deref(),
+    };
+
+    let projet_directory = build::project_directory();
+    std::fs::create_dir_all(&projet_directory)?;
+    let mut sig_db = build::open_sig_db(sig_db_path)?;
+    let mut build_report = BuildReport::default();
+    let host = toolchain.host()?;
+    let features = krate.get_features().cloned().unwrap_or_default();
+
+    for profile in &profiles {
+        let key = SignatureKey::new(
+            &krate.name,
+            &krate.version.to_string(),
+            &features,
+            &rustc,
+            &host,
+            &profile.to_string(),
+        );
+        if !args.force && sig_db.get(&key).is_some() {
+            println!("{} already in database", key);
+            continue;
+        }
+
+        match build::build_signatures(
+            &mut krate,
+            &projet_directory,
+            &build_settings,
+            profile,
+            key,
+            &mut build_report,
+        ) {
+            Some(set) => {
+                info!("{} signatures for {}", set.signatures.len(), set.key);
+                sig_db.insert(set);
+            }
+            None => error!("Could not compile {:#} ({})", krate, profile),
+        }
+    }
+
+    build::write_build_report(build_args, &build_report)?;
+    build::save_sig_db(&sig_db, sig_db_path)?;
+
+    Ok(())
+}
+
+fn list_subcommand(krate: Option<&str>, sig_db_path: &Path) -> Result<(), std::io::Error> {
+    let sig_db = build::open_sig_db(sig_db_path)?;
+    for set in &sig_db.sets {
+        if krate.is_some_and(|k| k != set.key.krate) {
+            continue;
+        }
+        match set.fallback {
+            Some(fallback) => println!(
+                "{}: {} signatures (fallback: {})",
+                set.key,
+                set.signatures.len(),
+                fallback
+            ),
+            None => println!("{}: {} signatures", set.key, set.signatures.len()),
+        }
+    }
+
+    Ok(())
+}
+
+fn show_subcommand(function: &str, sig_db_path: &Path) -> Result<(), std::io::Error> {
+    let sig_db = build::open_sig_db(sig_db_path)?;
+    for set in &sig_db.sets {
+        for sig in set.signatures.iter().filter(|s| s.name.contains(function)) {
+            println!("{}", sig.name);
+            println!("  from: {}", set.key);
+            println!("  size: {} bytes", sig.size);
+            println!("  hash: {}", sig.hash);
+        }
+    }
+
+    Ok(())
+}
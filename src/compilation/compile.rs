@@ -1,12 +1,19 @@
 use log::{debug, error, info, log_enabled, Level};
 use std::fmt;
 use std::io::Write;
+use std::path::PathBuf;
 use std::process::ExitStatus;
-use std::{collections::HashMap, fs::OpenOptions, path::Path, process::Command};
+use std::time::{Duration, Instant};
+use std::{collections::HashMap, fs::OpenOptions, path::Path};
 use toml_edit::{Array, Document, Formatted, Item, Value};
 
+use crate::compilation::fallback::{self, Fallback};
+use crate::compilation::profile::BuildProfile;
+use crate::compilation::sandbox::Sandbox;
+use crate::compilation::toolchain::Toolchain;
 use crate::info_gathering::krate::Krate;
 use crate::utils::toml_utils::add_array;
+use crate::ArianeError;
 
 #[derive(Debug, Copy, Clone)]
 pub enum CompileType {
@@ -33,34 +40,91 @@ fn set_crate_type(node: &mut Document, compile_type: &CompileType) {
     // add_array( node, "profile.release", "strip", &Item::Value(Value::Boolean(Formatted::new(false))));
 }
 
+/// Result of a `cargo build` run.
+pub struct CompileOutput {
+    pub status: ExitStatus,
+    pub stderr: String,
+    pub duration: Duration,
+    pub timed_out: bool,
+}
+
+impl CompileOutput {
+    pub fn success(&self) -> bool {
+        self.status.success() && !self.timed_out
+    }
+}
+
+/// Directory where cargo puts release artifacts of the crate at `crate_dir` built with `profile`.
+/// Each profile gets its own target directory so variants of the same crate do not overwrite each other.
+pub fn release_dir(crate_dir: &Path, profile: &BuildProfile) -> PathBuf {
+    crate_dir
+        .join("target")
+        .join(profile.dir_name())
+        .join("release")
+}
+
 pub fn compile(
     toml_path: &Path,
-    toolchain_version: &str,
+    toolchain: &Toolchain,
     features: &Vec<String>,
     compile_type: CompileType,
-) -> ExitStatus {
+    profile: &BuildProfile,
+    sandbox: &Sandbox,
+    fallback: Fallback,
+) -> Result<CompileOutput, ArianeError> {
+    let start = Instant::now();
     debug!("Patching toml : {:?}", toml_path);
-    let mut document = std::fs::read_to_string(toml_path)
-        .unwrap()
-        .parse::<Document>()
-        .expect("Invalid Toml");
+    let mut document = match std::fs::read_to_string(toml_path)?.parse::<Document>() {
+        Ok(document) => document,
+        Err(e) => {
+            return Err(ArianeError::Compile(format!(
+                "Invalid manifest {:?}: {}",
+                toml_path, e
+            )))
+        }
+    };
     set_crate_type(&mut document, &compile_type);
 
     {
-        let mut file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(toml_path)
-            .unwrap();
-
-        file.write(document.to_string().as_bytes())
-            .expect("Could not overwrite Cargo.toml file");
+        let mut file = OpenOptions::new().read(true).write(true).open(toml_path)?;
+
+        file.write_all(document.to_string().as_bytes())?;
     }
 
-    let toolchain = String::from(format!("+{}", toolchain_version));
+    let crate_dir = match toml_path.parent() {
+        Some(crate_dir) => crate_dir,
+        None => {
+            return Err(ArianeError::Compile(format!(
+                "{:?} is not in a crate directory",
+                toml_path
+            )))
+        }
+    };
+
+    if sandbox.enabled {
+        // Network is not available in the sandbox, dependencies are downloaded beforehand.
+        // Fetching does not run build scripts nor proc macros.
+        let fetch = toolchain
+            .cargo_command()
+            .arg("fetch")
+            .current_dir(crate_dir)
+            .output()?;
+        debug!(
+            "Fetch exit status : {}\n{}",
+            fetch.status,
+            String::from_utf8_lossy(fetch.stderr.as_ref())
+        );
+        if !fetch.status.success() {
+            return Ok(CompileOutput {
+                status: fetch.status,
+                stderr: String::from_utf8_lossy(fetch.stderr.as_ref()).to_string(),
+                duration: start.elapsed(),
+                timed_out: false,
+            });
+        }
+    }
 
     let mut args: Vec<&str> = vec![
-        toolchain.as_str(),
         "build",
         "--config",
         "strip=false",
@@ -70,9 +134,31 @@ pub fn compile(
         "--lib",
     ];
 
+    let target_dir = PathBuf::from("target").join(profile.dir_name());
+    let target_dir = target_dir.to_string_lossy();
+    args.push("--target-dir");
+    args.push(&target_dir);
+
+    let profile_args = profile.to_cargo_args();
+    args.extend(profile_args.iter().map(|arg| arg.as_str()));
+
+    if sandbox.enabled {
+        args.push("--frozen");
+    }
+
+    let stub_args = match fallback {
+        Fallback::StubbedBuildScripts => fallback::stub_build_scripts_args(crate_dir, toolchain)?,
+        _ => vec![],
+    };
+    args.extend(stub_args.iter().map(|arg| arg.as_str()));
+
+    if fallback == Fallback::NoDefaultFeatures {
+        args.push("--no-default-features");
+    }
+
     let mut features_string = String::new();
 
-    if !features.is_empty() {
+    if fallback.uses_inferred_features() && !features.is_empty() {
         args.push("--features");
 
         for feature in features {
@@ -83,16 +169,21 @@ pub fn compile(
     }
 
     debug!("Compiling with args : {:?}", args);
-    let cmd = Command::new(String::from("cargo.exe"))
-        .args(args)
-        .current_dir(toml_path.parent().unwrap())
-        .output()
-        .expect("failed to execute process");
+    let command = sandbox.cargo_command(toolchain, &args, crate_dir, &crate_dir.join("target"))?;
+    let (cmd, timed_out) = sandbox.output(command)?;
     debug!(
         "Exit status : {}\n{}",
         cmd.status,
         // String::from_utf8_lossy(cmd.stdout.as_ref()),
         String::from_utf8_lossy(cmd.stderr.as_ref())
     );
-    cmd.status
+    if timed_out {
+        error!("Compilation of {:?} timed out", crate_dir);
+    }
+    Ok(CompileOutput {
+        status: cmd.status,
+        stderr: String::from_utf8_lossy(cmd.stderr.as_ref()).to_string(),
+        duration: start.elapsed(),
+        timed_out,
+    })
 }
@@ -0,0 +1,198 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+use crate::compilation::toolchain::Toolchain;
+use crate::ArianeError;
+
+/// Strategy used to build a crate. When the build with the inferred settings fails,
+/// other strategies are tried in the order of `Fallback::ALL`.
+/// Signatures produced by a fallback are less likely to match the target's code, their scores are
+/// weighted accordingly.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Fallback {
+    /// Inferred features, target toolchain.
+    None,
+    /// Inferred features are dropped, default features are kept.
+    DefaultFeatures,
+    /// `--no-default-features`.
+    NoDefaultFeatures,
+    /// Inferred features, latest stable toolchain.
+    NewerToolchain,
+    /// Inferred features, build scripts of crates linking to a native library are not run.
+    StubbedBuildScripts,
+}
+
+impl Fallback {
+    pub const ALL: [Fallback; 5] = [
+        Fallback::None,
+        Fallback::DefaultFeatures,
+        Fallback::NoDefaultFeatures,
+        Fallback::NewerToolchain,
+        Fallback::StubbedBuildScripts,
+    ];
+
+    /// Factor applied to the similarity score of signatures built with this strategy.
+    pub fn weight(&self) -> f64 {
+        match self {
+            Fallback::None => 1.0,
+            Fallback::DefaultFeatures => 0.9,
+            Fallback::StubbedBuildScripts => 0.9,
+            Fallback::NoDefaultFeatures => 0.8,
+            Fallback::NewerToolchain => 0.7,
+        }
+    }
+
+    /// Whether this strategy would repeat the build of `Fallback::None` for a crate with the
+    /// inferred `features`: without any, it already builds with the default features.
+    pub fn is_redundant(&self, features: &[String]) -> bool {
+        *self == Fallback::DefaultFeatures && features.is_empty()
+    }
+
+    /// Whether the inferred features are passed to cargo.
+    pub fn uses_inferred_features(&self) -> bool {
+        !matches!(
+            self,
+            Fallback::DefaultFeatures | Fallback::NoDefaultFeatures
+        )
+    }
+}
+
+/// Runs `build` with each of `fallbacks` in order, skipping the ones redundant for the inferred
+/// `features`, until it succeeds. Returns its result along with the fallback that succeeded.
+pub fn try_in_order<T>(
+    fallbacks: &[Fallback],
+    features: &[String],
+    mut build: impl FnMut(Fallback) -> Option<T>,
+) -> Option<(T, Fallback)> {
+    fallbacks
+        .iter()
+        .filter(|fallback| !fallback.is_redundant(features))
+        .find_map(|fallback| build(*fallback).map(|result| (result, *fallback)))
+}
+
+impl fmt::Display for Fallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            Fallback::None => write!(f, "none"),
+            Fallback::DefaultFeatures => write!(f, "default features"),
+            Fallback::NoDefaultFeatures => write!(f, "no default features"),
+            Fallback::NewerToolchain => write!(f, "newer toolchain"),
+            Fallback::StubbedBuildScripts => write!(f, "stubbed build scripts"),
+        }
+    }
+}
+
+/// Cargo arguments overriding the build script of every package that declares a `links` key
+/// (most of the time `*-sys` crates), so that no native library needs to be found or built.
+/// Only the Rust code of the crate is needed to generate signatures, nothing gets linked.
+pub fn stub_build_scripts_args(
+    crate_dir: &Path,
+    toolchain: &Toolchain,
+) -> Result<Vec<String>, ArianeError> {
+    let host = toolchain.host()?;
+
+    let metadata = toolchain
+        .cargo_command()
+        .args(["metadata", "--format-version", "1"])
+        .current_dir(crate_dir)
+        .output()?;
+    let metadata: serde_json::Value = serde_json::from_slice(metadata.stdout.as_ref())?;
+
+    let mut args = vec![];
+    if let Some(packages) = metadata["packages"].as_array() {
+        for package in packages {
+            if let Some(links) = package["links"].as_str() {
+                debug!(
+                    "Stubbing build script of {} (links {})",
+                    package["name"], links
+                );
+                args.push("--config".to_string());
+                args.push(format!("target.{}.{}.rustc-link-lib=[]", host, links));
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallbacks_are_tried_until_one_succeeds() {
+        let mut tried = vec![];
+        let result = try_in_order(&Fallback::ALL, &["std".to_string()], |fallback| {
+            tried.push(fallback);
+            (fallback == Fallback::NewerToolchain).then_some("built")
+        });
+        assert_eq!(result, Some(("built", Fallback::NewerToolchain)));
+        assert_eq!(
+            tried,
+            [
+                Fallback::None,
+                Fallback::DefaultFeatures,
+                Fallback::NoDefaultFeatures,
+                Fallback::NewerToolchain,
+            ]
+        );
+
+        let mut tried = vec![];
+        let result: Option<((), Fallback)> = try_in_order(&Fallback::ALL, &[], |fallback| {
+            tried.push(fallback);
+            None
+        });
+        assert_eq!(result, None);
+        // Without inferred features, the default features build is the first one
+        assert_eq!(
+            tried,
+            [
+                Fallback::None,
+                Fallback::NoDefaultFeatures,
+                Fallback::NewerToolchain,
+                Fallback::StubbedBuildScripts,
+            ]
+        );
+    }
+
+    #[test]
+    fn fallbacks_are_weighted_below_the_inferred_build() {
+        assert_eq!(Fallback::None.weight(), 1.0);
+        for fallback in &Fallback::ALL[1..] {
+            assert!(
+                fallback.weight() < 1.0 && fallback.weight() > 0.0,
+                "{}",
+                fallback
+            );
+        }
+        let weights: Vec<f64> = Fallback::ALL.iter().map(|f| f.weight()).collect();
+        assert_eq!(weights, [1.0, 0.9, 0.8, 0.7, 0.9]);
+    }
+
+    #[test]
+    fn default_features_are_only_tried_with_inferred_features() {
+        assert!(Fallback::DefaultFeatures.is_redundant(&[]));
+        assert!(!Fallback::DefaultFeatures.is_redundant(&["std".to_string()]));
+        for fallback in Fallback::ALL
+            .iter()
+            .filter(|f| **f != Fallback::DefaultFeatures)
+        {
+            assert!(!fallback.is_redundant(&[]), "{}", fallback);
+        }
+    }
+
+    #[test]
+    fn serialized_in_snake_case() {
+        for fallback in Fallback::ALL {
+            let json = serde_json::to_string(&fallback).unwrap();
+            assert_eq!(serde_json::from_str::<Fallback>(&json).unwrap(), fallback);
+        }
+        assert_eq!(
+            serde_json::to_string(&Fallback::StubbedBuildScripts).unwrap(),
+            "\"stubbed_build_scripts\""
+        );
+    }
+}
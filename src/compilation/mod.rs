@@ -1 +1,6 @@
 pub mod compile;
+pub mod fallback;
+pub mod profile;
+pub mod report;
+pub mod sandbox;
+pub mod toolchain;
@@ -0,0 +1,336 @@
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum OptLevel {
+    O2,
+    O3,
+    S,
+    Z,
+}
+
+impl OptLevel {
+    fn as_toml(&self) -> &'static str {
+        match self {
+            OptLevel::O2 => "2",
+            OptLevel::O3 => "3",
+            OptLevel::S => "\"s\"",
+            OptLevel::Z => "\"z\"",
+        }
+    }
+}
+
+impl fmt::Display for OptLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            OptLevel::O2 => write!(f, "2"),
+            OptLevel::O3 => write!(f, "3"),
+            OptLevel::S => write!(f, "s"),
+            OptLevel::Z => write!(f, "z"),
+        }
+    }
+}
+
+impl FromStr for OptLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_matches('"') {
+            "2" => Ok(OptLevel::O2),
+            "3" => Ok(OptLevel::O3),
+            "s" => Ok(OptLevel::S),
+            "z" => Ok(OptLevel::Z),
+            other => Err(format!("Unknown opt-level {:?}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Lto {
+    Off,
+    Thin,
+    Fat,
+}
+
+impl Lto {
+    fn as_toml(&self) -> &'static str {
+        match self {
+            Lto::Off => "false",
+            Lto::Thin => "\"thin\"",
+            Lto::Fat => "\"fat\"",
+        }
+    }
+}
+
+impl fmt::Display for Lto {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            Lto::Off => write!(f, "off"),
+            Lto::Thin => write!(f, "thin"),
+            Lto::Fat => write!(f, "fat"),
+        }
+    }
+}
+
+impl FromStr for Lto {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_matches('"') {
+            "off" | "false" | "no" => Ok(Lto::Off),
+            "thin" => Ok(Lto::Thin),
+            "fat" | "true" | "yes" => Ok(Lto::Fat),
+            other => Err(format!("Unknown lto setting {:?}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PanicStrategy {
+    Unwind,
+    Abort,
+}
+
+impl fmt::Display for PanicStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            PanicStrategy::Unwind => write!(f, "unwind"),
+            PanicStrategy::Abort => write!(f, "abort"),
+        }
+    }
+}
+
+impl FromStr for PanicStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_matches('"') {
+            "unwind" => Ok(PanicStrategy::Unwind),
+            "abort" => Ok(PanicStrategy::Abort),
+            other => Err(format!("Unknown panic strategy {:?}", other)),
+        }
+    }
+}
+
+/// Codegen settings of the `release` profile used to build a reference crate.
+/// The default value matches cargo's own `release` profile.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BuildProfile {
+    pub opt_level: OptLevel,
+    pub lto: Lto,
+    /// `None` keeps cargo's default (16 for release builds).
+    pub codegen_units: Option<u32>,
+    pub panic: PanicStrategy,
+}
+
+impl Default for BuildProfile {
+    fn default() -> Self {
+        BuildProfile {
+            opt_level: OptLevel::O3,
+            lto: Lto::Off,
+            codegen_units: None,
+            panic: PanicStrategy::Unwind,
+        }
+    }
+}
+
+impl BuildProfile {
+    /// Profiles most commonly found in release binaries, used when building every variant of a crate.
+    pub fn default_matrix() -> Vec<BuildProfile> {
+        let default = BuildProfile::default();
+
+        vec![
+            default,
+            BuildProfile {
+                opt_level: OptLevel::S,
+                ..default
+            },
+            BuildProfile {
+                opt_level: OptLevel::Z,
+                lto: Lto::Fat,
+                codegen_units: Some(1),
+                panic: PanicStrategy::Abort,
+            },
+            BuildProfile {
+                lto: Lto::Thin,
+                ..default
+            },
+            BuildProfile {
+                lto: Lto::Fat,
+                codegen_units: Some(1),
+                ..default
+            },
+        ]
+    }
+
+    /// Arguments to pass to `cargo build` so the `release` profile uses these settings.
+    pub fn to_cargo_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--config".to_string(),
+            format!("profile.release.opt-level={}", self.opt_level.as_toml()),
+            "--config".to_string(),
+            format!("profile.release.lto={}", self.lto.as_toml()),
+            "--config".to_string(),
+            format!("profile.release.panic=\"{}\"", self.panic),
+        ];
+
+        if let Some(codegen_units) = self.codegen_units {
+            args.push("--config".to_string());
+            args.push(format!("profile.release.codegen-units={}", codegen_units));
+        }
+
+        args
+    }
+
+    /// Name usable as a directory name, e.g. `opt3-ltooff-cgudefault-unwind`. Profiles that differ,
+    /// even only by an explicit `codegen-units` equal to cargo's default, get different names.
+    pub fn dir_name(&self) -> String {
+        let codegen_units = match self.codegen_units {
+            Some(codegen_units) => codegen_units.to_string(),
+            None => "default".to_string(),
+        };
+        format!(
+            "opt{}-lto{}-cgu{}-{}",
+            self.opt_level, self.lto, codegen_units, self.panic
+        )
+    }
+}
+
+impl fmt::Display for BuildProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "opt-level={},lto={},panic={}",
+            self.opt_level, self.lto, self.panic
+        )?;
+        if let Some(codegen_units) = self.codegen_units {
+            write!(f, ",codegen-units={}", codegen_units)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a comma separated list of `key=value`, e.g. `opt-level=z,lto=fat,codegen-units=1,panic=abort`.
+/// Keys that are not given keep their default value.
+impl FromStr for BuildProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut profile = BuildProfile::default();
+
+        for setting in s.split(',').filter(|setting| !setting.trim().is_empty()) {
+            let (key, value) = match setting.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(format!("Expected key=value, got {:?}", setting)),
+            };
+            match key {
+                "opt-level" => profile.opt_level = value.parse()?,
+                "lto" => profile.lto = value.parse()?,
+                "panic" => profile.panic = value.parse()?,
+                "codegen-units" => {
+                    profile.codegen_units = match value.parse() {
+                        Ok(units) => Some(units),
+                        Err(_) => return Err(format!("Invalid codegen-units {:?}", value)),
+                    }
+                }
+                other => return Err(format!("Unknown profile setting {:?}", other)),
+            }
+        }
+
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_round_trip() {
+        for profile in BuildProfile::default_matrix() {
+            assert_eq!(profile.to_string().parse(), Ok(profile), "{}", profile);
+        }
+        assert_eq!(
+            BuildProfile::default().to_string(),
+            "opt-level=3,lto=off,panic=unwind"
+        );
+    }
+
+    #[test]
+    fn missing_settings_keep_their_default() {
+        assert_eq!("".parse(), Ok(BuildProfile::default()));
+        assert_eq!(
+            " opt-level=\"z\", lto=true,".parse(),
+            Ok(BuildProfile {
+                opt_level: OptLevel::Z,
+                lto: Lto::Fat,
+                ..BuildProfile::default()
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_profiles_are_rejected() {
+        for profile in [
+            "opt-level=4",
+            "lto=maybe",
+            "panic=exit",
+            "codegen-units=-1",
+            "debug=true",
+            "opt-level",
+        ] {
+            assert!(profile.parse::<BuildProfile>().is_err(), "{}", profile);
+        }
+    }
+
+    #[test]
+    fn dir_names_are_unique() {
+        assert_eq!(
+            BuildProfile::default().dir_name(),
+            "opt3-ltooff-cgudefault-unwind"
+        );
+
+        let mut profiles = BuildProfile::default_matrix();
+        profiles.push(BuildProfile {
+            codegen_units: Some(16),
+            ..BuildProfile::default()
+        });
+        let mut dir_names: Vec<String> = profiles.iter().map(|p| p.dir_name()).collect();
+        dir_names.sort();
+        dir_names.dedup();
+        assert_eq!(dir_names.len(), profiles.len());
+    }
+
+    #[test]
+    fn cargo_args_override_the_release_profile() {
+        assert_eq!(
+            BuildProfile::default().to_cargo_args(),
+            [
+                "--config",
+                "profile.release.opt-level=3",
+                "--config",
+                "profile.release.lto=false",
+                "--config",
+                "profile.release.panic=\"unwind\"",
+            ]
+        );
+
+        let profile: BuildProfile = "opt-level=z,lto=thin,codegen-units=1,panic=abort"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            profile.to_cargo_args(),
+            [
+                "--config",
+                "profile.release.opt-level=\"z\"",
+                "--config",
+                "profile.release.lto=\"thin\"",
+                "--config",
+                "profile.release.panic=\"abort\"",
+                "--config",
+                "profile.release.codegen-units=1",
+            ]
+        );
+    }
+}
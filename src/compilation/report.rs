@@ -0,0 +1,315 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use crate::compilation::compile::CompileOutput;
+use crate::compilation::fallback::Fallback;
+use crate::ArianeError;
+
+/// Number of compiler output lines kept for each build.
+const OUTPUT_TAIL_LINES: usize = 20;
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// Crate could not be downloaded or extracted, or cargo could not be run.
+    Setup,
+    MissingSystemLibrary,
+    /// Edition, MSRV or unstable feature not supported by the toolchain.
+    ToolchainTooOld,
+    FeatureConflict,
+    BuildScriptFailure,
+    Timeout,
+    /// Cargo succeeded but the expected library was not produced.
+    MissingArtifact,
+    Other,
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            FailureKind::Setup => write!(f, "setup failure"),
+            FailureKind::MissingSystemLibrary => write!(f, "missing system library"),
+            FailureKind::ToolchainTooOld => write!(f, "edition/toolchain too old"),
+            FailureKind::FeatureConflict => write!(f, "feature conflict"),
+            FailureKind::BuildScriptFailure => write!(f, "build script failure"),
+            FailureKind::Timeout => write!(f, "timeout"),
+            FailureKind::MissingArtifact => write!(f, "missing artifact"),
+            FailureKind::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// Patterns of cargo/rustc/cc errors, checked in order.
+const FAILURE_PATTERNS: [(FailureKind, &str); 4] = [
+    (
+        FailureKind::ToolchainTooOld,
+        r"(?i)requires rustc \d|feature `edition20\d\d` is required|is not supported by this version of|version of cargo is older|edition 20\d\d is unstable|may not be used on the stable release channel|use of unstable library feature|unknown `edition`",
+    ),
+    (
+        FailureKind::MissingSystemLibrary,
+        r"(?i)could not find system library|pkg-config|unable to find libclang|cannot find -l|fatal error: [^\n]*\.h: no such file|[^\n]*\.h' file not found|is `cmake` not installed|failed to find tool|could not find directory of openssl",
+    ),
+    (
+        FailureKind::FeatureConflict,
+        r"(?i)does not (have|contain) (the |these |this )?features?|none of the selected packages contains these features|mutually exclusive|error: [^\n]*feature",
+    ),
+    (
+        FailureKind::BuildScriptFailure,
+        r"(?i)failed to run custom build command|build script",
+    ),
+];
+
+static FAILURE_REGEXES: LazyLock<Vec<(FailureKind, Regex)>> = LazyLock::new(|| {
+    FAILURE_PATTERNS
+        .iter()
+        .map(|(kind, pattern)| (*kind, Regex::new(pattern).unwrap()))
+        .collect()
+});
+
+/// Guesses why a build failed from the compiler output.
+pub fn classify(stderr: &str) -> FailureKind {
+    for (kind, regex) in FAILURE_REGEXES.iter() {
+        if regex.is_match(stderr) {
+            return *kind;
+        }
+    }
+
+    FailureKind::Other
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BuildReportEntry {
+    pub krate: String,
+    pub version: String,
+    pub profile: String,
+    /// Strategy used for this build attempt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<Fallback>,
+    pub success: bool,
+    pub duration_secs: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<FailureKind>,
+    /// Last lines of compiler output.
+    pub output_tail: Vec<String>,
+}
+
+impl BuildReportEntry {
+    pub fn from_output(krate: &str, version: &str, profile: &str, output: &CompileOutput) -> Self {
+        let failure = match (output.success(), output.timed_out) {
+            (true, _) => None,
+            (false, true) => Some(FailureKind::Timeout),
+            (false, false) => Some(classify(&output.stderr)),
+        };
+        let lines: Vec<&str> = output.stderr.lines().collect();
+        let output_tail = lines[lines.len().saturating_sub(OUTPUT_TAIL_LINES)..]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+
+        BuildReportEntry {
+            krate: krate.to_string(),
+            version: version.to_string(),
+            profile: profile.to_string(),
+            fallback: None,
+            success: failure.is_none(),
+            duration_secs: output.duration.as_secs_f64(),
+            failure,
+            output_tail,
+        }
+    }
+
+    /// Entry for a crate that could not even be built, e.g. download failure.
+    pub fn from_setup_error(krate: &str, version: &str, profile: &str, error: &str) -> Self {
+        BuildReportEntry {
+            krate: krate.to_string(),
+            version: version.to_string(),
+            profile: profile.to_string(),
+            fallback: None,
+            success: false,
+            duration_secs: 0.0,
+            failure: Some(FailureKind::Setup),
+            output_tail: vec![error.to_string()],
+        }
+    }
+
+    pub fn with_fallback(mut self, fallback: Fallback) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    /// Marks a successful build as failed because `artifact` was not produced.
+    pub fn with_missing_artifact(mut self, artifact: &Path) -> Self {
+        self.success = false;
+        self.failure = Some(FailureKind::MissingArtifact);
+        self.output_tail
+            .push(format!("error: expected {:?} was not produced", artifact));
+        self
+    }
+
+    fn same_build(&self, other: &BuildReportEntry) -> bool {
+        self.krate == other.krate && self.version == other.version && self.profile == other.profile
+    }
+}
+
+impl fmt::Display for BuildReportEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{} [{}] {:.1}s",
+            self.krate, self.version, self.profile, self.duration_secs
+        )?;
+        if let Some(fallback) = self.fallback.filter(|f| *f != Fallback::None) {
+            write!(f, " (fallback: {})", fallback)?;
+        }
+        match self.failure {
+            Some(failure) => write!(f, " FAILED ({})", failure),
+            None => write!(f, " OK"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BuildReport {
+    pub builds: Vec<BuildReportEntry>,
+}
+
+impl BuildReport {
+    pub fn push(&mut self, entry: BuildReportEntry) {
+        self.builds.push(entry);
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &BuildReportEntry> {
+        self.builds.iter().filter(|entry| !entry.success)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), ArianeError> {
+        let mut f = std::fs::File::create(path)?;
+        f.write_all(serde_json::to_string_pretty(&self)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Human readable summary. A crate that failed to build with every strategy is listed along
+    /// with the reason of its first failure.
+    pub fn summary(&self) -> String {
+        let mut builds: Vec<&BuildReportEntry> = vec![];
+        for entry in &self.builds {
+            if !builds.iter().any(|b| b.same_build(entry)) {
+                builds.push(entry);
+            }
+        }
+
+        let mut failed = vec![];
+        let mut with_fallback = 0;
+        for build in &builds {
+            match self
+                .builds
+                .iter()
+                .find(|entry| entry.same_build(build) && entry.success)
+            {
+                Some(success) if success.fallback.is_some_and(|f| f != Fallback::None) => {
+                    with_fallback += 1
+                }
+                Some(_) => {}
+                None => failed.push(*build),
+            }
+        }
+
+        let mut summary = format!(
+            "{}/{} builds succeeded ({} thanks to a fallback)\n",
+            builds.len() - failed.len(),
+            builds.len(),
+            with_fallback
+        );
+
+        for failure in failed {
+            summary.push_str(&format!("  {}\n", failure));
+            let first_error = failure
+                .output_tail
+                .iter()
+                .find(|l| l.starts_with("error"))
+                .or(failure.output_tail.iter().rev().find(|l| !l.is_empty()));
+            if let Some(line) = first_error {
+                summary.push_str(&format!("    {}\n", line.trim()));
+            }
+        }
+
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toolchain_errors_are_recognized() {
+        for stderr in [
+            "error: package `clap v4.4.0` cannot be built because it requires rustc 1.70.0 or newer, while the currently active rustc version is 1.60.0",
+            "error: failed to parse manifest at `/tmp/foo/Cargo.toml`\n\nCaused by:\n  feature `edition2021` is required\n\n  The package requires the Cargo feature called `edition2021`, but that feature is not stabilized in this version of Cargo (1.55.0).",
+            "error[E0658]: use of unstable library feature 'let_chains'\n --> src/lib.rs:3:5",
+        ] {
+            assert_eq!(classify(stderr), FailureKind::ToolchainTooOld, "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn missing_system_libraries_are_recognized() {
+        for stderr in [
+            "error: failed to run custom build command for `openssl-sys v0.9.93`\n\nCaused by:\n  process didn't exit successfully: `/tmp/target/release/build/openssl-sys-5a4c/build-script-main` (exit status: 101)\n  --- stderr\n  Could not find directory of OpenSSL installation, and this `-sys` crate cannot proceed without this knowledge.",
+            "error: failed to run custom build command for `glib-sys v0.18.1`\n  --- stderr\n  thread 'main' panicked at 'called `Result::unwrap()` on an `Err` value: \"`\\\"pkg-config\\\" \\\"--libs\\\" \\\"--cflags\\\" \\\"glib-2.0\\\"` did not exit successfully\"'",
+            "  = note: /usr/bin/ld: cannot find -lsqlite3: No such file or directory\n          collect2: error: ld returned 1 exit status",
+            "  cargo:warning=src/zstd.c:1:10: fatal error: zstd.h: No such file or directory",
+            "  thread 'main' panicked at 'Unable to find libclang: \"couldn't find any valid shared libraries matching: ['libclang.so']\"'",
+        ] {
+            assert_eq!(classify(stderr), FailureKind::MissingSystemLibrary, "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn feature_conflicts_are_recognized() {
+        for stderr in [
+            "error: Package `serde v1.0.188` does not have the feature `unstable`",
+            "error: none of the selected packages contains these features: nightly",
+            "error: failed to select a version for `tokio`.\n    ... required by package `foo v0.1.0`\nversions that meet the requirements `^1` are: 1.32.0\n\nthe package `foo` depends on `tokio`, with features: `io` but `tokio` does not have these features.",
+            "error: The features `rustls` and `native-tls` are mutually exclusive",
+            "error: at least one of the `std` or `alloc` features must be enabled",
+        ] {
+            assert_eq!(classify(stderr), FailureKind::FeatureConflict, "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn build_script_failures_are_recognized() {
+        for stderr in [
+            "error: failed to run custom build command for `ring v0.16.20`\n\nCaused by:\n  process didn't exit successfully: `/tmp/target/release/build/ring-f2ef/build-script-build` (exit status: 101)\n  --- stderr\n  thread 'main' panicked at 'called `Option::unwrap()` on a `None` value'",
+            "warning: build script of `foo` emitted invalid instruction",
+        ] {
+            assert_eq!(classify(stderr), FailureKind::BuildScriptFailure, "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn earlier_patterns_win() {
+        // A build script failing because of pkg-config, not because of the build script itself
+        let stderr = "error: failed to run custom build command for `alsa-sys v0.3.1`\n  --- stderr\n  Could not run `PKG_CONFIG_ALLOW_SYSTEM_CFLAGS=1 \"pkg-config\" \"--libs\" \"--cflags\" \"alsa\"`";
+        assert_eq!(classify(stderr), FailureKind::MissingSystemLibrary);
+
+        // Unstable features are toolchain issues, not feature conflicts
+        let stderr = "error[E0554]: `#![feature]` may not be used on the stable release channel\n --> src/lib.rs:1:1";
+        assert_eq!(classify(stderr), FailureKind::ToolchainTooOld);
+
+        // Any error mentioning a feature is taken as a feature conflict, even in a build script
+        let stderr = "error: failed to run custom build command for `foo v0.1.0`\n  --- stderr\nerror: the `simd` feature requires a nightly compiler";
+        assert_eq!(classify(stderr), FailureKind::FeatureConflict);
+    }
+
+    #[test]
+    fn unrelated_errors_are_other() {
+        let stderr = "error[E0432]: unresolved import `std::os::fd`\n --> src/sys/unix.rs:3:14\n\nerror: could not compile `foo` (lib) due to 1 previous error";
+        assert_eq!(classify(stderr), FailureKind::Other);
+    }
+}
@@ -0,0 +1,456 @@
+use log::{debug, error};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::compilation::toolchain::Toolchain;
+use crate::ArianeError;
+
+/// Isolation applied to `cargo build`, which runs build scripts and proc macros of untrusted crates.
+///
+/// When enabled, builds run under [bubblewrap](https://github.com/containers/bubblewrap) with no
+/// network, a read-only view of the crate sources and toolchains, a private `/tmp`, only the
+/// crate's target directory writable, and none of the host's environment variables but `PATH`,
+/// `HOME` and those selecting the toolchain. Dependencies are fetched beforehand, outside the sandbox,
+/// with `cargo fetch` which does not run any code from the crates.
+/// Timeout applies even if the sandbox is disabled.
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    pub enabled: bool,
+    pub allow_network: bool,
+    pub timeout: Option<Duration>,
+    /// Maximum address space of each process, in bytes.
+    pub memory_limit: Option<u64>,
+    /// Maximum CPU time of each process, in seconds.
+    pub cpu_time_limit: Option<u64>,
+}
+
+/// System directories needed to run a toolchain, bound read-only if they exist.
+const SYSTEM_DIRS: [&str; 8] = [
+    "/usr",
+    "/bin",
+    "/sbin",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/etc/alternatives",
+    "/etc/ssl",
+];
+/// Host environment variables kept in the sandbox, every other one is cleared so build scripts
+/// cannot read tokens or credentials.
+const KEPT_ENV_VARS: [&str; 3] = ["PATH", "HOME", "RUSTUP_TOOLCHAIN"];
+/// First bubblewrap release with `--tmp-overlay`.
+const MIN_BWRAP_VERSION: (u64, u64) = (0, 10);
+const SYSTEM_FILES: [&str; 4] = [
+    "/etc/ld.so.cache",
+    "/etc/ld.so.conf",
+    "/etc/passwd",
+    "/etc/resolv.conf",
+];
+
+impl Sandbox {
+    /// Sandbox with sensible limits for compiling a single crate.
+    pub fn isolated() -> Sandbox {
+        Sandbox {
+            enabled: true,
+            allow_network: false,
+            timeout: Some(Duration::from_secs(30 * 60)),
+            memory_limit: Some(8 * 1024 * 1024 * 1024),
+            cpu_time_limit: Some(60 * 60),
+        }
+    }
+
+    /// Whether a recent enough bubblewrap can be used on this host.
+    pub fn is_available() -> bool {
+        if !cfg!(target_os = "linux") {
+            return false;
+        }
+
+        let output = match Command::new("bwrap").arg("--version").output() {
+            Ok(output) if output.status.success() => output,
+            _ => return false,
+        };
+        // "bubblewrap 0.10.0"
+        let version = String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .last()
+            .and_then(|v| semver::Version::parse(v).ok());
+        match version {
+            Some(version) => (version.major, version.minor) >= MIN_BWRAP_VERSION,
+            None => false,
+        }
+    }
+
+    /// Builds the command running `toolchain`'s cargo with `args` from `crate_dir`, isolated if
+    /// enabled. `writable_dir` is the only location (besides a private `/tmp`) the command can
+    /// write to.
+    pub fn cargo_command(
+        &self,
+        toolchain: &Toolchain,
+        args: &[&str],
+        crate_dir: &Path,
+        writable_dir: &Path,
+    ) -> Result<Command, ArianeError> {
+        if !self.enabled {
+            let mut cmd = toolchain.cargo_command();
+            cmd.args(args).current_dir(crate_dir);
+            return Ok(cmd);
+        }
+
+        if !Sandbox::is_available() {
+            return Err(ArianeError::Compile(
+                "Sandboxing requires bubblewrap (bwrap) 0.10 or later on Linux".to_string(),
+            ));
+        }
+
+        self.bwrap_command(toolchain, args, crate_dir, writable_dir)
+    }
+
+    /// `cargo_command` when the sandbox is enabled, without checking that bubblewrap is available.
+    fn bwrap_command(
+        &self,
+        toolchain: &Toolchain,
+        args: &[&str],
+        crate_dir: &Path,
+        writable_dir: &Path,
+    ) -> Result<Command, ArianeError> {
+        let crate_dir = crate_dir.canonicalize()?;
+        std::fs::create_dir_all(writable_dir)?;
+        let writable_dir = writable_dir.canonicalize()?;
+
+        // `--unshare-all` includes a PID namespace: once bwrap is killed, everything the build
+        // spawned dies with it
+        let mut cmd = Command::new("bwrap");
+        cmd.args(["--die-with-parent", "--new-session", "--unshare-all"]);
+        if self.allow_network {
+            cmd.arg("--share-net");
+        }
+        cmd.arg("--clearenv");
+        for var in KEPT_ENV_VARS {
+            if let Some(value) = std::env::var_os(var) {
+                cmd.arg("--setenv").arg(var).arg(value);
+            }
+        }
+        for dir in SYSTEM_DIRS {
+            cmd.args(["--ro-bind-try", dir, dir]);
+        }
+        for file in SYSTEM_FILES {
+            cmd.args(["--ro-bind-try", file, file]);
+        }
+        cmd.args(["--proc", "/proc", "--dev", "/dev", "--tmpfs", "/tmp"]);
+
+        // Toolchains are read-only. Cargo needs to write lock files in its home, writes are
+        // redirected to a throwaway overlay so build scripts cannot tamper with the registry.
+        if let Some(rustup_home) = rustup_home() {
+            cmd.arg("--ro-bind-try").arg(&rustup_home).arg(&rustup_home);
+            cmd.arg("--setenv").arg("RUSTUP_HOME").arg(&rustup_home);
+        }
+        if let Some(cargo_home) = cargo_home() {
+            cmd.arg("--overlay-src").arg(&cargo_home);
+            cmd.arg("--tmp-overlay").arg(&cargo_home);
+            cmd.arg("--setenv").arg("CARGO_HOME").arg(&cargo_home);
+        }
+        if let Some(prefix) = &toolchain.prefix {
+            let prefix = prefix.canonicalize()?;
+            cmd.arg("--ro-bind").arg(&prefix).arg(&prefix);
+        }
+        for (var, value) in toolchain.envs() {
+            cmd.args(["--setenv", var, &value]);
+        }
+
+        cmd.arg("--ro-bind").arg(&crate_dir).arg(&crate_dir);
+        cmd.arg("--bind").arg(&writable_dir).arg(&writable_dir);
+        cmd.arg("--chdir").arg(&crate_dir);
+
+        // Resource limits are applied by the shell right before running the actual program
+        let mut limits = String::new();
+        if let Some(memory_limit) = self.memory_limit {
+            limits.push_str(&format!("ulimit -v {} && ", memory_limit / 1024));
+        }
+        if let Some(cpu_time_limit) = self.cpu_time_limit {
+            limits.push_str(&format!("ulimit -t {} && ", cpu_time_limit));
+        }
+        cmd.args(["--", "sh", "-c"]);
+        cmd.arg(format!("{}exec \"$@\"", limits));
+        cmd.arg("ariane-sandbox")
+            .arg(toolchain.cargo())
+            .args(toolchain.toolchain_args())
+            .args(args);
+
+        Ok(cmd)
+    }
+
+    /// Runs `cmd` to completion, killing it if it exceeds the timeout.
+    /// Returns the output of the command and whether it timed out.
+    pub fn output(&self, mut cmd: Command) -> Result<(Output, bool), ArianeError> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Ok((cmd.output()?, false)),
+        };
+
+        debug!("Running {:?} with a {:?} timeout", cmd, timeout);
+        // In its own process group, so that the compilers, build scripts and proc macros cargo
+        // spawns can be killed with it
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Pipes are drained from threads so a verbose build cannot fill them and block
+        let (mut stdout, mut stderr) = match (child.stdout.take(), child.stderr.take()) {
+            (Some(stdout), Some(stderr)) => (stdout, stderr),
+            _ => {
+                return Err(ArianeError::Compile(
+                    "Could not capture build output".to_string(),
+                ))
+            }
+        };
+        let stdout_reader = std::thread::spawn(move || {
+            let mut buf = vec![];
+            let _ = stdout.read_to_end(&mut buf);
+            buf
+        });
+        let stderr_reader = std::thread::spawn(move || {
+            let mut buf = vec![];
+            let _ = stderr.read_to_end(&mut buf);
+            buf
+        });
+
+        let start = Instant::now();
+        let mut timed_out = false;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if start.elapsed() > timeout {
+                error!("Build timed out after {:?}, killing it", timeout);
+                timed_out = true;
+                kill_process_group(&mut child)?;
+                break child.wait()?;
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        };
+
+        Ok((
+            Output {
+                status,
+                stdout: stdout_reader.join().unwrap_or_default(),
+                stderr: stderr_reader.join().unwrap_or_default(),
+            },
+            timed_out,
+        ))
+    }
+}
+
+/// Kills `child` and every process of the group it leads.
+#[cfg(unix)]
+fn kill_process_group(child: &mut Child) -> Result<(), std::io::Error> {
+    // A negative pid targets the process group
+    match unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) } {
+        0 => Ok(()),
+        _ => child.kill(),
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(child: &mut Child) -> Result<(), std::io::Error> {
+    child.kill()
+}
+
+fn home_subdir(env_var: &str, default_dir: &str) -> Option<PathBuf> {
+    let dir = match std::env::var_os(env_var) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(default_dir),
+    };
+
+    dir.canonicalize().ok()
+}
+
+fn rustup_home() -> Option<PathBuf> {
+    home_subdir("RUSTUP_HOME", ".rustup")
+}
+
+fn cargo_home() -> Option<PathBuf> {
+    home_subdir("CARGO_HOME", ".cargo")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command_args(cmd: &Command) -> Vec<String> {
+        cmd.get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("ariane-sandbox-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    /// Position of `window` in `args`.
+    fn find(args: &[String], window: &[&str]) -> Option<usize> {
+        args.windows(window.len()).position(|w| w == window)
+    }
+
+    #[test]
+    fn bwrap_isolates_the_build() {
+        std::env::set_var("ARIANE_SANDBOX_SECRET", "hunter2");
+        let crate_dir = temp_dir("crate");
+        let target_dir = crate_dir.join("target");
+        let cmd = Sandbox::isolated()
+            .bwrap_command(
+                &Toolchain::rustup("1.70.0"),
+                &["build", "--release"],
+                &crate_dir,
+                &target_dir,
+            )
+            .unwrap();
+        let args = command_args(&cmd);
+        let crate_path = crate_dir.to_string_lossy();
+        let target_path = target_dir.to_string_lossy();
+
+        assert_eq!(cmd.get_program(), "bwrap");
+        assert_eq!(
+            args[..3],
+            ["--die-with-parent", "--new-session", "--unshare-all"]
+        );
+        assert!(!args
+            .iter()
+            .any(|arg| arg == "--unshare-pid" || arg == "--share-net"));
+
+        // Host variables are cleared first, only the ones cargo needs are set again
+        assert_eq!(args[3], "--clearenv");
+        assert_eq!(cmd.get_envs().count(), 0);
+        let set_vars: Vec<&str> = args
+            .windows(2)
+            .filter(|w| w[0] == "--setenv")
+            .map(|w| w[1].as_str())
+            .collect();
+        for var in &set_vars {
+            assert!(
+                [
+                    "PATH",
+                    "HOME",
+                    "RUSTUP_TOOLCHAIN",
+                    "RUSTUP_HOME",
+                    "CARGO_HOME"
+                ]
+                .contains(var),
+                "{}",
+                var
+            );
+        }
+        if let Ok(path) = std::env::var("PATH") {
+            assert!(find(&args, &["--setenv", "PATH", &path]).is_some());
+        }
+        assert!(!args
+            .iter()
+            .any(|arg| arg.contains("ARIANE_SANDBOX_SECRET") || arg.contains("hunter2")));
+        assert!(find(&args, &["--ro-bind-try", "/usr", "/usr"]).is_some());
+        assert!(find(&args, &["--tmpfs", "/tmp"]).is_some());
+        assert!(find(&args, &["--ro-bind", &crate_path, &crate_path]).is_some());
+        assert!(find(&args, &["--bind", &target_path, &target_path]).is_some());
+        assert!(find(&args, &["--chdir", &crate_path]).is_some());
+
+        let program = find(&args, &["--", "sh", "-c"]).unwrap() + 3;
+        assert_eq!(
+            args[program..],
+            [
+                "ulimit -v 8388608 && ulimit -t 3600 && exec \"$@\"",
+                "ariane-sandbox",
+                "cargo",
+                "+1.70.0",
+                "build",
+                "--release",
+            ]
+        );
+
+        let sandbox = Sandbox {
+            allow_network: true,
+            ..Sandbox::isolated()
+        };
+        let cmd = sandbox
+            .bwrap_command(&Toolchain::rustup("1.70.0"), &[], &crate_dir, &target_dir)
+            .unwrap();
+        assert!(command_args(&cmd).iter().any(|arg| arg == "--share-net"));
+
+        std::fs::remove_dir_all(crate_dir).unwrap();
+    }
+
+    #[test]
+    fn limits_are_applied_before_running_the_program() {
+        let sandbox = Sandbox {
+            memory_limit: Some(512 * 1024 * 1024),
+            cpu_time_limit: Some(42),
+            ..Sandbox::isolated()
+        };
+        let crate_dir = temp_dir("limits");
+        let cmd = sandbox
+            .bwrap_command(&Toolchain::rustup("1.70.0"), &[], &crate_dir, &crate_dir)
+            .unwrap();
+        let args = command_args(&cmd);
+        let script = &args[find(&args, &["--", "sh", "-c"]).unwrap() + 3];
+
+        // Runs the wrapper outside of bwrap, with a program printing its limits
+        let output = Command::new("sh")
+            .args(["-c", script, "ariane-sandbox"])
+            .args(["sh", "-c", "ulimit -v && ulimit -t"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "524288\n42\n");
+
+        let no_limits = Sandbox {
+            memory_limit: None,
+            cpu_time_limit: None,
+            ..Sandbox::isolated()
+        };
+        let cmd = no_limits
+            .bwrap_command(&Toolchain::rustup("1.70.0"), &[], &crate_dir, &crate_dir)
+            .unwrap();
+        assert!(command_args(&cmd).iter().any(|arg| arg == "exec \"$@\""));
+
+        std::fs::remove_dir_all(crate_dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timeout_kills_the_whole_process_group() {
+        let sandbox = Sandbox {
+            timeout: Some(Duration::from_secs(1)),
+            ..Sandbox::default()
+        };
+        // The background `sleep` keeps stdout open: output would not be returned before it exits
+        // if only `sh` was killed
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "sleep 60 & sleep 60"]);
+
+        let start = Instant::now();
+        let (output, timed_out) = sandbox.output(cmd).unwrap();
+        assert!(timed_out);
+        assert!(!output.status.success());
+        assert!(start.elapsed() < Duration::from_secs(30));
+    }
+
+    #[test]
+    fn fast_commands_do_not_time_out() {
+        let sandbox = Sandbox {
+            timeout: Some(Duration::from_secs(30)),
+            ..Sandbox::default()
+        };
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo ok"]);
+
+        let (output, timed_out) = sandbox.output(cmd).unwrap();
+        assert!(!timed_out);
+        assert_eq!(output.stdout, b"ok\n");
+    }
+}
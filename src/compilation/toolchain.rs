@@ -0,0 +1,471 @@
+use flate2::read::GzDecoder;
+use log::{debug, info, warn};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tar::Archive;
+
+use crate::ArianeError;
+
+/// Components needed to build a crate, as named in dist tarballs.
+const DIST_COMPONENTS: [&str; 3] = ["rustc", "rust-std", "cargo"];
+/// Crates of the standard library whose precompiled rlibs are shipped in `rust-std`.
+const STD_CRATES: [&str; 3] = ["std", "core", "alloc"];
+/// Written in a toolchain directory once every component has been installed.
+const COMPLETE_MARKER: &str = ".ariane-complete";
+
+/// Where toolchains come from.
+#[derive(Debug, Clone)]
+pub enum ToolchainSource {
+    /// Toolchains installed and managed by rustup.
+    Rustup,
+    /// An already installed toolchain, given by its prefix (the directory containing `bin/cargo`).
+    Path(PathBuf),
+    /// A mirror of `https://static.rust-lang.org`, either a local directory or an http(s) url,
+    /// containing `dist/<component>-<version>-<target>.tar.gz` tarballs and their `.sha256` files.
+    /// Checksums come from the mirror itself: they catch corrupted downloads, not a malicious
+    /// mirror, which must be trusted.
+    DistMirror(String),
+}
+
+/// A toolchain ready to be used.
+#[derive(Debug, Clone)]
+pub struct Toolchain {
+    pub version: String,
+    /// Installation prefix, `None` if the toolchain is managed by rustup.
+    pub prefix: Option<PathBuf>,
+}
+
+impl Toolchain {
+    pub fn rustup(version: &str) -> Toolchain {
+        Toolchain {
+            version: version.to_string(),
+            prefix: None,
+        }
+    }
+
+    fn bin(&self, name: &str) -> String {
+        match &self.prefix {
+            Some(prefix) => prefix
+                .join("bin")
+                .join(format!("{}{}", name, std::env::consts::EXE_SUFFIX))
+                .to_string_lossy()
+                .to_string(),
+            None => name.to_string(),
+        }
+    }
+
+    /// Program to run for cargo.
+    pub fn cargo(&self) -> String {
+        self.bin("cargo")
+    }
+
+    /// Arguments to put before any cargo or rustc argument, selecting the toolchain.
+    pub fn toolchain_args(&self) -> Vec<String> {
+        match &self.prefix {
+            Some(_) => vec![],
+            None => vec![format!("+{}", self.version)],
+        }
+    }
+
+    /// Environment variables cargo needs to use this toolchain's rustc.
+    pub fn envs(&self) -> Vec<(&'static str, String)> {
+        match &self.prefix {
+            Some(_) => vec![
+                ("RUSTC", self.bin("rustc")),
+                ("RUSTDOC", self.bin("rustdoc")),
+            ],
+            None => vec![],
+        }
+    }
+
+    /// `cargo` command using this toolchain.
+    pub fn cargo_command(&self) -> Command {
+        let mut cmd = Command::new(self.cargo());
+        cmd.args(self.toolchain_args()).envs(self.envs());
+        cmd
+    }
+
+    /// `rustc` command using this toolchain.
+    pub fn rustc_command(&self) -> Command {
+        let mut cmd = Command::new(self.bin("rustc"));
+        cmd.args(self.toolchain_args());
+        cmd
+    }
+
+    /// Host triple of this toolchain's rustc.
+    pub fn host(&self) -> Result<String, ArianeError> {
+        let output = self.rustc_command().arg("-vV").output()?;
+        let rustc_info = String::from_utf8_lossy(output.stdout.as_ref()).to_string();
+        match rustc_info.lines().find_map(|l| l.strip_prefix("host: ")) {
+            Some(host) => Ok(host.trim().to_string()),
+            None => Err(ArianeError::Toolchain(
+                "Could not find host triple".to_string(),
+            )),
+        }
+    }
+
+    /// Release of this toolchain's rustc, e.g. `1.72.0` for `stable` or `1.74.0-nightly`.
+    pub fn release(&self) -> Result<String, ArianeError> {
+        let output = self.rustc_command().arg("-vV").output()?;
+        match release(&String::from_utf8_lossy(output.stdout.as_ref())) {
+            Some(release) => Ok(release),
+            None => Err(ArianeError::Toolchain(format!(
+                "Could not find the release of toolchain {}",
+                self.version
+            ))),
+        }
+    }
+
+    pub fn sysroot(&self) -> Result<PathBuf, ArianeError> {
+        if let Some(prefix) = &self.prefix {
+            return Ok(prefix.clone());
+        }
+
+        let output = self.rustc_command().args(["--print", "sysroot"]).output()?;
+        if !output.status.success() {
+            return Err(ArianeError::Toolchain(format!(
+                "Could not find sysroot of toolchain {}",
+                self.version
+            )));
+        }
+        Ok(PathBuf::from(
+            String::from_utf8_lossy(output.stdout.as_ref()).trim(),
+        ))
+    }
+
+    /// Directory holding the precompiled standard library for `target`.
+    pub fn target_libdir(&self, target: &str) -> Result<PathBuf, ArianeError> {
+        Ok(self
+            .sysroot()?
+            .join("lib")
+            .join("rustlib")
+            .join(target)
+            .join("lib"))
+    }
+
+    /// Precompiled `std`, `core` and `alloc` rlibs for `target`, which must be installed.
+    pub fn std_rlibs(&self, target: &str) -> Result<Vec<PathBuf>, ArianeError> {
+        let mut rlibs = vec![];
+        for entry in std::fs::read_dir(self.target_libdir(target)?)? {
+            let path = entry?.path();
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            let is_std_crate = STD_CRATES
+                .iter()
+                .any(|krate| file_name.starts_with(&format!("lib{}-", krate)));
+            if is_std_crate && file_name.ends_with(".rlib") {
+                rlibs.push(path);
+            }
+        }
+        rlibs.sort();
+
+        Ok(rlibs)
+    }
+}
+
+/// Provides toolchains from a `ToolchainSource`. Toolchains unpacked from a mirror are cached
+/// under `cache_dir/<version>-<target>`, so they are only downloaded once.
+pub struct ToolchainManager {
+    pub source: ToolchainSource,
+    pub cache_dir: PathBuf,
+    pub target: String,
+}
+
+impl ToolchainManager {
+    pub fn new(source: ToolchainSource, cache_dir: &Path) -> ToolchainManager {
+        ToolchainManager {
+            source,
+            cache_dir: cache_dir.to_path_buf(),
+            target: host_target(),
+        }
+    }
+
+    /// Returns toolchain `version`, installing it if needed.
+    pub fn ensure(&self, version: &str) -> Result<Toolchain, ArianeError> {
+        match &self.source {
+            ToolchainSource::Rustup => install_with_rustup(version),
+            ToolchainSource::Path(prefix) => use_installed(prefix, version),
+            ToolchainSource::DistMirror(mirror) => self.install_from_mirror(mirror, version),
+        }
+    }
+
+    /// Installs the standard library of `target` for `toolchain`, which may differ from the host.
+    pub fn ensure_target(&self, toolchain: &Toolchain, target: &str) -> Result<(), ArianeError> {
+        let libdir = toolchain.target_libdir(target)?;
+        if libdir.exists() {
+            return Ok(());
+        }
+
+        match &self.source {
+            ToolchainSource::Rustup => {
+                let cmd = Command::new("rustup")
+                    .args(["target", "add", "--toolchain", &toolchain.version, target])
+                    .output()?;
+                if !cmd.status.success() {
+                    return Err(ArianeError::Toolchain(
+                        String::from_utf8_lossy(cmd.stderr.as_ref()).to_string(),
+                    ));
+                }
+            }
+            ToolchainSource::Path(_) => {
+                return Err(ArianeError::Toolchain(format!(
+                    "{:?} not found, install the {} standard library",
+                    libdir, target
+                )))
+            }
+            ToolchainSource::DistMirror(mirror) => {
+                let prefix = self.toolchain_dir(&toolchain.version);
+                self.install_dist_component(
+                    mirror,
+                    "rust-std",
+                    &toolchain.version,
+                    target,
+                    &prefix,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether toolchains other than the target's one (e.g. `stable`) can be provided.
+    pub fn supports_channels(&self) -> bool {
+        matches!(self.source, ToolchainSource::Rustup)
+    }
+
+    pub fn toolchain_dir(&self, version: &str) -> PathBuf {
+        self.cache_dir.join(format!("{}-{}", version, self.target))
+    }
+
+    fn install_from_mirror(&self, mirror: &str, version: &str) -> Result<Toolchain, ArianeError> {
+        let prefix = self.toolchain_dir(version);
+        let toolchain = Toolchain {
+            version: version.to_string(),
+            prefix: Some(prefix.clone()),
+        };
+        if prefix.join(COMPLETE_MARKER).exists() {
+            debug!("Using cached toolchain {:?}", prefix);
+            return Ok(toolchain);
+        }
+
+        std::fs::create_dir_all(&prefix)?;
+
+        for component in DIST_COMPONENTS {
+            self.install_dist_component(mirror, component, version, &self.target, &prefix)?;
+        }
+
+        std::fs::write(prefix.join(COMPLETE_MARKER), version)?;
+        Ok(toolchain)
+    }
+
+    /// Downloads, verifies and installs `<component>-<version>-<target>.tar.gz` to `prefix`.
+    fn install_dist_component(
+        &self,
+        mirror: &str,
+        component: &str,
+        version: &str,
+        target: &str,
+        prefix: &Path,
+    ) -> Result<(), ArianeError> {
+        let download_dir = self.cache_dir.join("downloads");
+        std::fs::create_dir_all(&download_dir)?;
+
+        let tarball_name = format!("{}-{}-{}.tar.gz", component, version, target);
+        info!("Fetching {} from {}", tarball_name, mirror);
+        let tarball = fetch_from_mirror(mirror, &format!("dist/{}", tarball_name))?;
+        let checksum = fetch_from_mirror(mirror, &format!("dist/{}.sha256", tarball_name))?;
+        verify_sha256(&tarball, &checksum, &tarball_name)?;
+
+        let unpack_dir = download_dir.join(format!("{}-{}-{}", component, version, target));
+        Archive::new(GzDecoder::new(tarball.as_slice())).unpack(&download_dir)?;
+        install_component(&unpack_dir, prefix)?;
+        std::fs::remove_dir_all(&unpack_dir)?;
+
+        Ok(())
+    }
+}
+
+/// `release` line of `rustc -vV`.
+fn release(rustc_info: &str) -> Option<String> {
+    rustc_info
+        .lines()
+        .find_map(|l| l.strip_prefix("release: "))
+        .map(|release| release.trim().to_string())
+}
+
+/// Target triple Ariane itself runs on, toolchains are downloaded for it.
+pub fn host_target() -> String {
+    let arch = std::env::consts::ARCH;
+    match std::env::consts::OS {
+        "windows" => format!("{}-pc-windows-msvc", arch),
+        "macos" => format!("{}-apple-darwin", arch),
+        _ => format!("{}-unknown-linux-gnu", arch),
+    }
+}
+
+fn install_with_rustup(version: &str) -> Result<Toolchain, ArianeError> {
+    let cmd = Command::new("rustup").args(["install", version]).output()?;
+    debug!(
+        "{:?}, {:?}",
+        String::from_utf8_lossy(cmd.stdout.as_ref()),
+        String::from_utf8_lossy(cmd.stderr.as_ref())
+    );
+    if !cmd.status.success() {
+        return Err(ArianeError::Toolchain(
+            String::from_utf8_lossy(cmd.stderr.as_ref()).to_string(),
+        ));
+    }
+
+    Ok(Toolchain::rustup(version))
+}
+
+fn use_installed(prefix: &Path, version: &str) -> Result<Toolchain, ArianeError> {
+    let toolchain = Toolchain {
+        version: version.to_string(),
+        prefix: Some(prefix.to_path_buf()),
+    };
+    for bin in [toolchain.cargo(), toolchain.bin("rustc")] {
+        if !Path::new(&bin).exists() {
+            return Err(ArianeError::Toolchain(format!("{} not found", bin)));
+        }
+    }
+
+    let output = toolchain.rustc_command().arg("-V").output()?;
+    let installed_version = String::from_utf8_lossy(output.stdout.as_ref()).to_string();
+    if !installed_version.contains(version) {
+        warn!(
+            "Toolchain at {:?} is {}, expected {}: signatures may not match",
+            prefix,
+            installed_version.trim(),
+            version
+        );
+    }
+
+    Ok(toolchain)
+}
+
+/// Reads `path` from a mirror, which is either a local directory or an http(s) url.
+fn fetch_from_mirror(mirror: &str, path: &str) -> Result<Vec<u8>, ArianeError> {
+    if mirror.starts_with("http://") || mirror.starts_with("https://") {
+        let url = format!("{}/{}", mirror.trim_end_matches('/'), path);
+        let response = reqwest::blocking::get(&url)?;
+        if !response.status().is_success() {
+            return Err(ArianeError::Toolchain(format!("{} not found", url)));
+        }
+        return Ok(response.bytes()?.to_vec());
+    }
+
+    let file = Path::new(mirror).join(path);
+    if !file.exists() {
+        return Err(ArianeError::Toolchain(format!("{:?} not found", file)));
+    }
+    Ok(std::fs::read(file)?)
+}
+
+/// `checksum_file` has the `sha256sum` format: `<hex digest>  <file name>`.
+fn verify_sha256(data: &[u8], checksum_file: &[u8], name: &str) -> Result<(), ArianeError> {
+    let checksum_file = String::from_utf8_lossy(checksum_file);
+    let expected = checksum_file.split_whitespace().next().unwrap_or_default();
+    if expected.len() != 64 || !expected.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ArianeError::Toolchain(format!(
+            "Malformed checksum file for {}",
+            name
+        )));
+    }
+    let actual = hex::encode(Sha256::digest(data));
+
+    if !expected.eq_ignore_ascii_case(&actual) {
+        return Err(ArianeError::Toolchain(format!(
+            "Checksum mismatch for {}",
+            name
+        )));
+    }
+
+    Ok(())
+}
+
+/// Does what the `install.sh` script shipped in dist tarballs does: every component listed in the
+/// `components` file is a directory whose content is copied to the prefix.
+fn install_component(unpack_dir: &Path, prefix: &Path) -> Result<(), ArianeError> {
+    let components_file = unpack_dir.join("components");
+    let components = match std::fs::read_to_string(&components_file) {
+        Ok(components) => components,
+        Err(_) => {
+            return Err(ArianeError::Toolchain(format!(
+                "{:?} not found",
+                components_file
+            )))
+        }
+    };
+
+    for component in components.lines().filter(|c| !c.trim().is_empty()) {
+        debug!("Installing component {} to {:?}", component, prefix);
+        copy_dir(&unpack_dir.join(component.trim()), prefix)?;
+    }
+
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<(), std::io::Error> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dest)?;
+        } else if entry.file_name() != "manifest.in" {
+            std::fs::copy(entry.path(), dest)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `sha256sum` of `b"hello\n"`.
+    const HELLO_SHA256: &str = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+
+    #[test]
+    fn checksums_are_verified() {
+        let checksum = format!("{}  hello.txt\n", HELLO_SHA256);
+        assert!(verify_sha256(b"hello\n", checksum.as_bytes(), "hello.txt").is_ok());
+        assert!(verify_sha256(
+            b"hello\n",
+            HELLO_SHA256.to_uppercase().as_bytes(),
+            "hello.txt"
+        )
+        .is_ok());
+        assert!(verify_sha256(b"hello!\n", checksum.as_bytes(), "hello.txt").is_err());
+    }
+
+    #[test]
+    fn release_is_read_from_rustc_info() {
+        let stable = "rustc 1.72.0 (5680fa18f 2023-08-23)\nbinary: rustc\n\
+                      commit-hash: 5680fa18feaa87f3ff04063800aec256c3d4b4be\n\
+                      host: x86_64-unknown-linux-gnu\nrelease: 1.72.0\nLLVM version: 16.0.5\n";
+        assert_eq!(release(stable), Some("1.72.0".to_string()));
+        let nightly = "rustc 1.74.0-nightly (84a9f4c6e 2023-08-29)\nrelease: 1.74.0-nightly\n";
+        assert_eq!(release(nightly), Some("1.74.0-nightly".to_string()));
+        assert_eq!(release("error: toolchain 'foo' is not installed"), None);
+    }
+
+    #[test]
+    fn malformed_checksum_files_are_rejected() {
+        for checksum in [
+            "",
+            "\n",
+            "<html>Not Found</html>",
+            &HELLO_SHA256[..63],
+            &format!("{}0  hello.txt", HELLO_SHA256),
+        ] {
+            assert!(
+                verify_sha256(b"hello\n", checksum.as_bytes(), "hello.txt").is_err(),
+                "{:?}",
+                checksum
+            );
+        }
+    }
+}
@@ -5,6 +5,9 @@ use std::io::Write;
 use std::{fs, path::Path};
 
 use fuzzyhash::FuzzyHash;
+use goblin::elf::header::ELFMAG;
+use goblin::elf::section_header::{SHN_UNDEF, SHT_NOBITS};
+use goblin::elf::{sym, Elf};
 use goblin::pe::section_table::SectionTable;
 use goblin::pe::Coff;
 use goblin::{archive, pe};
@@ -13,6 +16,7 @@ use object::coff;
 use pdb::{FallibleIterator, ImageSectionHeader};
 
 use crate::sig::sig_generation::{hash_single_func, FuzzyFunc, Hash};
+use crate::ArianeError;
 
 #[derive(Clone, Eq, PartialEq, PartialOrd, Ord)]
 pub enum FunctionType {
@@ -30,6 +34,9 @@ pub struct Function<'data> {
     /// Otherwise it is reserved field.
     pub rva: u32,
     pub fn_type: FunctionType,
+    /// Symbols referenced by the function, by offset in `data`, sorted. Empty when unknown.
+    /// Only known for library functions.
+    pub relocations: Vec<(u32, String)>,
 }
 
 impl<'data> Display for Function<'data> {
@@ -42,8 +49,12 @@ const VALID_MIN_BYTES_FUNCTION_TRESHOLD: u32 = 50;
 
 pub fn rva_to_pa(pe: &goblin::pe::PE, rva: u32) -> Option<u32> {
     for section in &pe.sections {
-        if rva >= section.virtual_address && rva < section.virtual_size + section.virtual_address {
-            return Some(rva - section.virtual_address + section.pointer_to_raw_data);
+        let contains = section
+            .virtual_address
+            .checked_add(section.virtual_size)
+            .is_some_and(|end| rva >= section.virtual_address && rva < end);
+        if contains {
+            return (rva - section.virtual_address).checked_add(section.pointer_to_raw_data);
         }
     }
 
@@ -71,21 +82,22 @@ fn guess_function_size(function_bytes: &[u8]) -> u32 {
     function_size - padding_bytes
 }
 
-fn get_exception_data_functions(file: &[u8]) -> Vec<Function> {
+fn get_exception_data_functions(file: &[u8]) -> Result<Vec<Function<'_>>, ArianeError> {
     let mut functions = vec![];
-    let parsed_pe = goblin::pe::PE::parse(file).unwrap();
+    let parsed_pe = goblin::pe::PE::parse(file)?;
     if let Some(except_data) = &parsed_pe.exception_data {
         for f in except_data.functions() {
-            let f = f.unwrap();
+            let f = f?;
             if let Some(start_pa) = rva_to_pa(&parsed_pe, f.begin_address) {
                 if let Some(end_pa) = rva_to_pa(&parsed_pe, f.end_address) {
-                    if start_pa + end_pa < file.len() as u32 {
+                    if let Some(data) = file.get(start_pa as usize..end_pa as usize) {
                         functions.push(Function {
                             rva: f.begin_address,
-                            data: &file[start_pa as usize..end_pa as usize],
+                            data,
                             // todo : search for symbols in dwarf
                             name: None,
                             fn_type: FunctionType::Exe,
+                            relocations: vec![],
                         });
                     }
                 }
@@ -93,85 +105,78 @@ fn get_exception_data_functions(file: &[u8]) -> Vec<Function> {
         }
     }
 
-    functions
+    Ok(functions)
+}
+
+/// Physical and virtual addresses of `offset`, `None` when its section is unknown or they overflow.
+fn pdb_addresses(
+    section_map: &HashMap<u16, ImageSectionHeader>,
+    offset: pdb::PdbInternalSectionOffset,
+) -> Option<(u32, u32)> {
+    let section = section_map.get(&offset.section)?;
+    Some((
+        offset.offset.checked_add(section.pointer_to_raw_data)?,
+        offset.offset.checked_add(section.virtual_address)?,
+    ))
 }
 
 pub fn get_functions_from_pdb<'data>(
     exe_bytes: &'data [u8],
     pdb_path: &Path,
-) -> Result<Vec<Function<'data>>, std::io::Error> {
+) -> Result<Vec<Function<'data>>, ArianeError> {
     pdb_path.try_exists()?;
 
     let mut section_map = HashMap::<u16, ImageSectionHeader>::new();
     let file = std::fs::File::open(pdb_path)?;
 
-    let mut pdb = pdb::PDB::open(file).unwrap();
+    let mut pdb = pdb::PDB::open(file)?;
 
-    let sections = pdb.sections().unwrap().unwrap();
+    let sections = match pdb.sections()? {
+        Some(sections) => sections,
+        None => return Err(ArianeError::Parse("PDB has no section headers".to_string())),
+    };
     for (i, section) in sections.iter().enumerate() {
         section_map.insert(i as u16 + 1, section.clone());
     }
 
-    let symbol_table = pdb.global_symbols().unwrap();
+    let symbol_table = pdb.global_symbols()?;
 
     let mut symbols = symbol_table.iter();
 
     let mut map = HashMap::<u32, (u32, String)>::new();
     let mut result_functions = vec![];
 
-    while let Some(symbol) = symbols.next().unwrap() {
+    while let Some(symbol) = symbols.next()? {
         // println!("{:?}", symbol);
-        match symbol.parse().unwrap() {
-            pdb::SymbolData::Public(func) => {
-                // println!("{:?}", func);
-                if section_map.contains_key(&func.offset.section) {
-                    let pa =
-                        func.offset.offset + section_map[&func.offset.section].pointer_to_raw_data;
-                    let va = func.offset.offset + section_map[&func.offset.section].virtual_address;
-                    map.insert(
-                        pa,
-                        (
-                            va,
-                            String::from_utf8(func.name.as_bytes().to_vec()).unwrap(),
-                        ),
-                    );
-                }
+        if let pdb::SymbolData::Public(func) = symbol.parse()? {
+            // println!("{:?}", func);
+            if let Some((pa, va)) = pdb_addresses(&section_map, func.offset) {
+                map.insert(pa, (va, func.name.to_string().into_owned()));
             }
-            _ => {}
         }
     }
 
     // println!("Module private symbols:");
-    let dbi = pdb.debug_information().unwrap();
-    let mut modules = dbi.modules().unwrap();
-    while let Some(module) = modules.next().unwrap() {
+    let dbi = pdb.debug_information()?;
+    let mut modules = dbi.modules()?;
+    while let Some(module) = modules.next()? {
         // println!("Module: {}", module.object_file_name());
-        let info = match pdb.module_info(&module).unwrap() {
+        let info = match pdb.module_info(&module)? {
             Some(info) => info,
             None => {
                 // println!("  no module info");
                 continue;
             }
         };
-        let mut s = info.symbols().unwrap();
-        while let Some(symbol) = s.next().unwrap() {
+        let mut s = info.symbols()?;
+        while let Some(symbol) = s.next()? {
             // println!("{:?}", symbol);
             if let Ok(s) = symbol.parse() {
                 match s {
                     pdb::SymbolData::Procedure(func) => {
                         // println!("{:x} {:?}", func.offset.offset, func.name);
-                        if section_map.contains_key(&func.offset.section) {
-                            let pa = func.offset.offset
-                                + section_map[&func.offset.section].pointer_to_raw_data;
-                            let va = func.offset.offset
-                                + section_map[&func.offset.section].virtual_address;
-                            map.insert(
-                                pa,
-                                (
-                                    va,
-                                    String::from_utf8(func.name.as_bytes().to_vec()).unwrap(),
-                                ),
-                            );
+                        if let Some((pa, va)) = pdb_addresses(&section_map, func.offset) {
+                            map.insert(pa, (va, func.name.to_string().into_owned()));
                         }
                     }
                     _ => {}
@@ -188,16 +193,24 @@ pub fn get_functions_from_pdb<'data>(
     for (fn_number, (function_pa, (function_va, function_name))) in sorted_funcs.iter().enumerate()
     {
         let func_bytes = match sorted_funcs.get(fn_number + 1) {
-            Some((next_f_pa, _)) => &exe_bytes[*function_pa as usize..*next_f_pa as usize],
-            _ => &exe_bytes[*function_pa as usize..],
+            Some((next_f_pa, _)) => exe_bytes.get(*function_pa as usize..*next_f_pa as usize),
+            _ => exe_bytes.get(*function_pa as usize..),
+        };
+        let func_bytes = match func_bytes {
+            Some(func_bytes) => func_bytes,
+            None => {
+                debug!("{} is outside of the executable", function_name);
+                continue;
+            }
         };
         let fn_size = guess_function_size(&func_bytes);
         if fn_size != 0 {
             result_functions.push(Function {
                 rva: *function_va,
-                data: &exe_bytes[*function_pa as usize..(*function_pa + fn_size) as usize],
+                data: &func_bytes[..fn_size as usize],
                 name: Some(function_name.clone()),
                 fn_type: FunctionType::Exe,
+                relocations: vec![],
             });
         }
     }
@@ -212,9 +225,9 @@ pub fn get_functions_from_pdb<'data>(
 pub fn get_functions_from_bin<'data>(
     exe_bytes: &'data [u8],
     min_fn_size: usize,
-) -> Result<Vec<Function<'data>>, std::io::Error> {
+) -> Result<Vec<Function<'data>>, ArianeError> {
     // funcs.append(&mut guess_smda_functions(filepath, &bytes));
-    let mut result = get_exception_data_functions(&exe_bytes);
+    let result = get_exception_data_functions(exe_bytes)?;
     // result.append(&mut get_exported_functions_goblin(&bytes));
 
     Ok(result
@@ -224,55 +237,182 @@ pub fn get_functions_from_bin<'data>(
         .collect())
 }
 
-pub fn get_functions_from_lib<'data>(lib_bytes: &'data [u8]) -> Vec<Function<'data>> {
+/// Functions defined in a relocatable ELF object. Rust emits one section per function, symbol values
+/// are offsets in their section.
+fn get_functions_from_elf(object: &[u8]) -> Vec<Function<'_>> {
+    let mut result = vec![];
+    let elf = match Elf::parse(object) {
+        Ok(elf) => elf,
+        Err(err) => {
+            debug!("Invalid ELF object: {:?}", err);
+            return result;
+        }
+    };
+
+    // Relocations of each section, by offset in the section
+    let mut relocations = HashMap::<usize, Vec<(u64, String)>>::new();
+    for (idx, relocs) in &elf.shdr_relocs {
+        let section = match elf.section_headers.get(*idx) {
+            Some(section) => section.sh_info as usize,
+            None => continue,
+        };
+        for reloc in relocs.iter() {
+            let sym = match elf.syms.get(reloc.r_sym) {
+                Some(sym) => sym,
+                None => continue,
+            };
+            // Local functions are referenced through their section, named after them
+            let name = if sym.st_type() == sym::STT_SECTION {
+                elf.section_headers
+                    .get(sym.st_shndx)
+                    .and_then(|section| elf.shdr_strtab.get_at(section.sh_name))
+                    .map(|name| name.strip_prefix(".text.").unwrap_or(name))
+            } else {
+                elf.strtab.get_at(sym.st_name)
+            };
+            relocations
+                .entry(section)
+                .or_default()
+                .push((reloc.r_offset, name.unwrap_or_default().to_string()));
+        }
+    }
+
+    for sym in elf.syms.iter() {
+        if sym.st_type() != sym::STT_FUNC || sym.st_shndx == SHN_UNDEF as usize || sym.st_size == 0
+        {
+            continue;
+        }
+        let section = match elf.section_headers.get(sym.st_shndx) {
+            Some(section) if section.sh_type != SHT_NOBITS => section,
+            _ => continue,
+        };
+        // Offsets of malformed objects may overflow
+        let (start, end, section_end) = match (
+            section.sh_offset.checked_add(sym.st_value),
+            section.sh_offset.checked_add(section.sh_size),
+        ) {
+            (Some(start), Some(section_end)) => match start.checked_add(sym.st_size) {
+                Some(end) => (start, end, section_end),
+                None => continue,
+            },
+            _ => continue,
+        };
+        if end > section_end || end > object.len() as u64 {
+            continue;
+        }
+
+        if let Some(name) = elf.strtab.get_at(sym.st_name) {
+            let mut function_relocations: Vec<(u32, String)> = relocations
+                .get(&sym.st_shndx)
+                .into_iter()
+                .flatten()
+                .filter(|(offset, _)| (sym.st_value..sym.st_value + sym.st_size).contains(offset))
+                .map(|(offset, name)| ((offset - sym.st_value) as u32, name.clone()))
+                .collect();
+            function_relocations.sort();
+            result.push(Function {
+                data: &object[start as usize..end as usize],
+                name: Some(name.to_string()),
+                rva: 0,
+                fn_type: FunctionType::Lib,
+                relocations: function_relocations,
+            });
+        }
+    }
+
+    result
+}
+
+/// Functions defined in a COFF object, one per function symbol.
+fn get_functions_from_coff<'data>(
+    coff_file: &goblin::pe::Coff,
+    extracted: &'data [u8],
+) -> Result<Vec<Function<'data>>, ArianeError> {
+    let mut result = vec![];
+    for (index, name, s) in coff_file.symbols.iter() {
+        if s.is_function_definition() {
+            debug!(
+                "Symbol nb {}, name : {:?} | {:?}",
+                index,
+                name,
+                s.name(&coff_file.strings)
+            );
+            // let fn_def = coff_file.symbols.aux_function_definition(index).unwrap();
+
+            // println!("idx {}, idx2 {}, Function size : {:x}, off {:x} type {:x}",index, fn_def.tag_index, fn_def.total_size, s.value, s.typ);
+            // Section numbers start at 1
+            let sec_data = match (s.section_number as usize)
+                .checked_sub(1)
+                .and_then(|i| coff_file.sections.get(i))
+            {
+                Some(sec_data) => sec_data,
+                None => continue,
+            };
+            let start = sec_data.pointer_to_raw_data as usize;
+            let symbol_bytes =
+                match extracted.get(start..start + sec_data.size_of_raw_data as usize) {
+                    Some(symbol_bytes) => symbol_bytes,
+                    None => {
+                        debug!("Section of {:?} is out of bounds", name);
+                        continue;
+                    }
+                };
+            let mut relocations = vec![];
+            for reloc in sec_data.relocations(extracted)? {
+                // debug!("RELOC : {:?}", reloc);
+                let name = coff_file
+                    .symbols
+                    .get(reloc.symbol_table_index as usize)
+                    .and_then(|(_, sym)| sym.name(&coff_file.strings).ok().map(str::to_string));
+                if let Some(name) = name {
+                    relocations.push((reloc.virtual_address, name));
+                }
+            }
+            relocations.sort();
+            debug!("data: {:?}", hex::encode(symbol_bytes));
+            debug!(
+                "data post: {:?}",
+                hex::encode(hash_single_func(symbol_bytes, false))
+            );
+            // debug!("\n\n");
+            // let hash = FuzzyHash::new(hash_single_func(symbol_bytes, false));
+            result.push(Function {
+                data: symbol_bytes,
+                name: Some(s.name(&coff_file.strings)?.to_string()),
+                rva: 0,
+                fn_type: FunctionType::Lib,
+                relocations,
+            })
+        }
+    }
+
+    Ok(result)
+}
+
+/// Functions defined in the objects of a static library or rlib, either COFF or ELF.
+pub fn get_functions_from_lib<'data>(
+    lib_bytes: &'data [u8],
+) -> Result<Vec<Function<'data>>, ArianeError> {
     let mut result = vec![];
 
     match archive::Archive::parse(&lib_bytes) {
         Ok(archive) => {
             for (name, member, idx) in &archive.summarize() {
                 // debug!("A {:?}\n B{:?}\n C{:?}\n", name, member, idx);
-                let extracted = archive.extract(name, &lib_bytes).unwrap();
-
-                if let Ok(coff_file) = goblin::pe::Coff::parse(extracted) {
-                    // for section in coff_file.sections {
-                    // println!("{:?}", section);
-                    // }
-                    // debug!("Coff file {}", name);
-                    for (index, name, s) in coff_file.symbols.iter() {
-                        if s.is_function_definition() {
-                            debug!(
-                                "Symbol nb {}, name : {:?} | {:?}",
-                                index,
-                                name,
-                                s.name(&coff_file.strings)
-                            );
-                            // let fn_def = coff_file.symbols.aux_function_definition(index).unwrap();
-
-                            // println!("idx {}, idx2 {}, Function size : {:x}, off {:x} type {:x}",index, fn_def.tag_index, fn_def.total_size, s.value, s.typ);
-                            let sec_data = coff_file
-                                .sections
-                                .get(s.section_number as usize - 1)
-                                .unwrap();
-                            let symbol_bytes = &extracted[sec_data.pointer_to_raw_data as usize
-                                ..(sec_data.pointer_to_raw_data + sec_data.size_of_raw_data)
-                                    as usize];
-                            for reloc in sec_data.relocations(extracted).unwrap().into_iter() {
-                                // debug!("RELOC : {:?}", reloc);
-                            }
-                            debug!("data: {:?}", hex::encode(symbol_bytes));
-                            debug!(
-                                "data post: {:?}",
-                                hex::encode(hash_single_func(symbol_bytes, false))
-                            );
-                            // debug!("\n\n");
-                            // let hash = FuzzyHash::new(hash_single_func(symbol_bytes, false));
-                            result.push(Function {
-                                data: symbol_bytes,
-                                name: Some(s.name(&coff_file.strings).unwrap().to_string()),
-                                rva: 0,
-                                fn_type: FunctionType::Lib,
-                            })
-                        }
+                let extracted = match archive.extract(name, lib_bytes) {
+                    Ok(extracted) => extracted,
+                    Err(e) => {
+                        debug!("Skipping archive member {}: {}", name, e);
+                        continue;
+                    }
+                };
+
+                if extracted.starts_with(ELFMAG) {
+                    result.append(&mut get_functions_from_elf(extracted));
+                } else if let Ok(coff_file) = goblin::pe::Coff::parse(extracted) {
+                    match get_functions_from_coff(&coff_file, extracted) {
+                        Ok(mut functions) => result.append(&mut functions),
+                        Err(e) => debug!("Skipping archive member {}: {}", name, e),
                     }
                 }
 
@@ -281,7 +421,7 @@ pub fn get_functions_from_lib<'data>(lib_bytes: &'data [u8]) -> Vec<Function<'da
                 //     // archive.
             }
         }
-        Err(err) => println!("Err: {:?}", err),
+        Err(err) => return Err(err.into()),
     }
 
     // for member in x.members() {
@@ -296,5 +436,39 @@ pub fn get_functions_from_lib<'data>(lib_bytes: &'data [u8]) -> Vec<Function<'da
     //     }
     // }
 
-    result
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::patch::tests::{minimal_elf, ElfSymbols};
+
+    /// Fixture whose `_start` symbol has value `value` and size 0x10.
+    fn elf_with_start_at(value: u64) -> Vec<u8> {
+        let mut bytes = minimal_elf(ElfSymbols::OwnStrtab);
+        let elf = Elf::parse(&bytes).unwrap();
+        let symtab = elf
+            .section_headers
+            .iter()
+            .find(|s| s.sh_type == goblin::elf::section_header::SHT_SYMTAB)
+            .unwrap();
+        // Second symbol, after its name, info, other and section index
+        let st_value = symtab.sh_offset as usize + 24 + 8;
+        bytes[st_value..st_value + 8].copy_from_slice(&value.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn elf_symbols_out_of_their_section_are_skipped() {
+        let bytes = elf_with_start_at(0x20);
+        let functions = get_functions_from_elf(&bytes);
+        assert_eq!(functions.len(), 1);
+        assert_eq!(functions[0].name.as_deref(), Some("_start"));
+        assert_eq!(functions[0].data, &bytes[0x1020..0x1030]);
+
+        for value in [0xf8, u64::MAX - 0x1000, u64::MAX - 8] {
+            assert!(get_functions_from_elf(&elf_with_start_at(value)).is_empty());
+        }
+    }
 }
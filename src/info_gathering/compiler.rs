@@ -1,9 +1,13 @@
+use goblin::elf::header::{EM_386, EM_AARCH64, EM_X86_64};
+use goblin::pe::header::{COFF_MACHINE_ARM64, COFF_MACHINE_X86, COFF_MACHINE_X86_64};
 use regex::bytes::Regex;
 use std::{
     io::{Cursor, Write},
     path::Path,
 };
 
+use crate::ArianeError;
+
 #[derive(Clone)]
 pub struct CommitHash {
     hash: String,
@@ -18,9 +22,9 @@ impl CommitHash {
     /// Blocking network request to github to try to find latest tag related to the commit hash.
     /// Returns the tag if found, or latest rustc version if not found (considering the hash must
     /// belong to an unreleased tag).
-    pub fn search_rustc_version(&self) -> Option<String> {
-        match search_rustc_version_from_commit(&self.hash) {
-            Some(version) => Some(version),
+    pub fn search_rustc_version(&self) -> Result<Option<String>, ArianeError> {
+        match search_rustc_version_from_commit(&self.hash)? {
+            Some(version) => Ok(Some(version)),
             None => get_latest_rustc_version(),
         }
     }
@@ -33,7 +37,7 @@ impl CommitHash {
 /// if let Some(compiler_info) = RustcInformation::from_file(&Path::new(&args.file))? {
 ///     let rustc_commit_hash = compiler_info.get_commit_hash();
 ///     println!("{}", rustc_commit_hash.commit_hash_to_string());
-///     compiler_version = rustc_commit_hash.search_rustc_version().expect("Could not find rustc version from your target !");
+///     compiler_version = rustc_commit_hash.search_rustc_version()?.expect("Could not find rustc version from your target !");
 /// }
 /// ```
 pub struct RustcInformation {
@@ -46,7 +50,7 @@ impl RustcInformation {
     }
 
     /// Searches rustc commit hash from a file on disk.
-    pub fn from_file(filepath: &Path) -> Result<Option<RustcInformation>, std::io::Error> {
+    pub fn from_file(filepath: &Path) -> Result<Option<RustcInformation>, ArianeError> {
         let content = std::fs::read(&filepath)?;
 
         Ok(RustcInformation::from_buffer(&content))
@@ -58,7 +62,7 @@ impl RustcInformation {
 
         // let x = re.captures_iter(content.as_ref());//.collect();
         for c in version_regex.captures_iter(buffer.as_ref()) {
-            let v = String::from_utf8(c.name("hash").unwrap().as_bytes().to_vec()).unwrap();
+            let v = String::from_utf8_lossy(&c["hash"]).to_string();
             return Some(RustcInformation {
                 hash: CommitHash { hash: v, tag: None },
             });
@@ -68,7 +72,38 @@ impl RustcInformation {
     }
 }
 
-fn search_rustc_version_from_commit(hash: &str) -> Option<String> {
+/// Guesses the target triple `buffer` was compiled for, from its file format and architecture.
+/// Windows executables are assumed to use the MSVC toolchain, unless MinGW artifacts are found.
+pub fn guess_target_triple(buffer: &[u8]) -> Option<String> {
+    match goblin::Object::parse(buffer).ok()? {
+        goblin::Object::PE(pe) => {
+            let arch = match pe.header.coff_header.machine {
+                COFF_MACHINE_X86_64 => "x86_64",
+                COFF_MACHINE_X86 => "i686",
+                COFF_MACHINE_ARM64 => "aarch64",
+                _ => return None,
+            };
+            let mingw_regex = Regex::new(r"(?i)mingw").unwrap();
+            let env = match mingw_regex.is_match(buffer) {
+                true => "gnu",
+                false => "msvc",
+            };
+            Some(format!("{}-pc-windows-{}", arch, env))
+        }
+        goblin::Object::Elf(elf) => {
+            let arch = match elf.header.e_machine {
+                EM_X86_64 => "x86_64",
+                EM_386 => "i686",
+                EM_AARCH64 => "aarch64",
+                _ => return None,
+            };
+            Some(format!("{}-unknown-linux-gnu", arch))
+        }
+        _ => None,
+    }
+}
+
+fn search_rustc_version_from_commit(hash: &str) -> Result<Option<String>, ArianeError> {
     let tag_regex = Regex::new(r##"href="/rust-lang/rust/releases/tag/(?<tag>[0-9\.]+)"##).unwrap();
 
     // curl -s https://github.com/rust-lang/rust/branch_commits/9c20b2a8cc7588decb6de25ac6a7912dcef24d65
@@ -81,18 +116,18 @@ fn search_rustc_version_from_commit(hash: &str) -> Option<String> {
     let client = reqwest::blocking::Client::new();
     // According to https://github.com/s0md3v/Zen :
     // "Github allows 60 unauthenticated requests per hour". This should be way enough for a single user, but might reach a limit in CTF.
-    let response = client.get(&url).send().unwrap();
-    let content = Cursor::new(response.bytes().unwrap());
+    let response = client.get(&url).send()?.error_for_status()?;
+    let content = Cursor::new(response.bytes()?);
     let ca = tag_regex.captures_iter(content.get_ref());
     for c in ca {
-        let v = String::from_utf8(c.name("tag").unwrap().as_bytes().to_vec()).unwrap();
+        let v = String::from_utf8_lossy(&c["tag"]).to_string();
         result = Some(v);
     }
 
-    result
+    Ok(result)
 }
 
-fn get_latest_rustc_version() -> Option<String> {
+fn get_latest_rustc_version() -> Result<Option<String>, ArianeError> {
     let tag_regex = Regex::new(r##"/rust-lang/rust/releases/tag/(?<tag>[0-9\.]+)"##).unwrap();
     let url = String::from("https://github.com/rust-lang/rust/tags");
 
@@ -100,15 +135,15 @@ fn get_latest_rustc_version() -> Option<String> {
     let client = reqwest::blocking::Client::new();
     // According to https://github.com/s0md3v/Zen :
     // "Github allows 60 unauthenticated requests per hour". This should be way enough for a single user, but might reach a limit in CTF.
-    let response = client.get(&url).send().unwrap();
-    let content = Cursor::new(response.bytes().unwrap());
+    let response = client.get(&url).send()?.error_for_status()?;
+    let content = Cursor::new(response.bytes()?);
     let ca = tag_regex.captures_iter(content.get_ref());
     // let mut latest_tag = None;
     for c in ca {
-        let v = String::from_utf8(c.name("tag").unwrap().as_bytes().to_vec()).unwrap();
+        let v = String::from_utf8_lossy(&c["tag"]).to_string();
         result = Some(v);
         break;
     }
 
-    result
+    Ok(result)
 }
@@ -9,6 +9,8 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use crate::ArianeError;
+
 #[derive(Clone)]
 pub struct Krate {
     pub name: String,
@@ -17,6 +19,7 @@ pub struct Krate {
     features: Vec<String>,
     is_accurate: bool,
     metadata: Option<CrateResponse>,
+    evidence: Vec<String>,
 }
 
 impl Display for Krate {
@@ -25,15 +28,6 @@ impl Display for Krate {
     }
 }
 
-#[derive(Debug)]
-pub enum KrateError {
-    CursorError(std::io::Error),
-    DownloadError(reqwest::Error),
-    FileCreationError(std::io::Error),
-    NoMetadataError(crates_io_api::Error),
-    NonExistantVersion,
-}
-
 impl Krate {
     pub fn new(name: &str, version: Version) -> Krate {
         Krate {
@@ -43,82 +37,83 @@ impl Krate {
             features: vec![],
             is_accurate: false,
             metadata: None,
+            evidence: vec![],
         }
     }
 
-    pub fn from_name(name: &str) -> Result<Krate, KrateError> {
-        let metadata = match Krate::get_metadata_from_crates_api_from_name(name) {
-            Ok(m) => m,
-            Err(e) => return Err(KrateError::NoMetadataError(e)),
+    /// Sets the features to build the crate with. Features the crate does not declare are
+    /// dropped once its metadata is retrieved from crates.io.
+    pub fn with_features(mut self, features: &[String]) -> Krate {
+        self.features = features.to_vec();
+        self
+    }
+
+    pub fn from_name(name: &str) -> Result<Krate, ArianeError> {
+        let metadata = Krate::get_metadata_from_crates_api_from_name(name)?;
+        let version = match metadata.versions.last() {
+            Some(version) => version,
+            None => return Err(ArianeError::Registry(format!("{} has no version", name))),
         };
-        let version = metadata.versions.last().unwrap();
-        Ok(Krate::new(name, Version::parse(&version.num).unwrap()))
+        Ok(Krate::new(name, Version::parse(&version.num)?))
     }
 
-    pub fn new_with_remote_info(name: &str, version: Version) -> Krate {
+    /// ⚠️ This makes a blocking request to crates.io/api ⚠️
+    pub fn new_with_remote_info(name: &str, version: Version) -> Result<Krate, ArianeError> {
         let mut k = Krate::new(name, version);
 
-        k.fill_information_from_crates_api();
+        k.fill_information_from_crates_api()?;
 
-        k
+        Ok(k)
     }
 
     /// Retrives krate metadata from crates.io.
     /// ⚠️ This makes a blocking request to crates.io/api ⚠️
-    pub fn get_krate_meta(&mut self) -> Option<CrateResponse> {
-        if !self.is_accurate {
-            self.fill_information_from_crates_api();
-        }
+    pub fn get_krate_meta(&mut self) -> Result<CrateResponse, ArianeError> {
+        self.fill_information_from_crates_api()?;
 
-        self.metadata.clone()
+        match &self.metadata {
+            Some(metadata) => Ok(metadata.clone()),
+            None => Err(ArianeError::Registry(format!("No metadata for {}", self.name))),
+        }
     }
 
-    pub fn download(&mut self, dest_dir: &Path) -> Result<PathBuf, KrateError> {
+    pub fn download(&mut self, dest_dir: &Path) -> Result<PathBuf, ArianeError> {
         debug!(
             "Downloading {} to {:?}",
             self.name,
             &dest_dir.to_string_lossy()
         );
-        if let Err(e) = std::fs::create_dir_all(&dest_dir) {
-            return Err(KrateError::FileCreationError(e));
-        };
+        std::fs::create_dir_all(dest_dir)?;
 
         let reqwest_client = reqwest::blocking::Client::new();
 
-        let response = match reqwest_client.get(self.get_download_url()?).send() {
-            Ok(response) => response,
-            Err(e) => return Err(KrateError::DownloadError(e)),
-        };
+        let response = reqwest_client
+            .get(self.get_download_url()?)
+            .send()?
+            .error_for_status()?;
 
         let tarball_path = dest_dir.clone().join(format!("{:#}.tar.gz", self.name));
-        let mut tarball_file = match std::fs::File::create(&tarball_path) {
-            Ok(tarball) => tarball,
-            Err(e) => return Err(KrateError::FileCreationError(e)),
-        };
-        let response_content = match response.bytes() {
-            Ok(b) => b,
-            Err(e) => return Err(KrateError::DownloadError(e)),
-        };
-        let mut content = std::io::Cursor::new(response_content);
+        let mut tarball_file = std::fs::File::create(&tarball_path)?;
+        let mut content = std::io::Cursor::new(response.bytes()?);
 
-        if let Err(e) = std::io::copy(&mut content, &mut tarball_file) {
-            return Err(KrateError::FileCreationError(e));
-        };
+        std::io::copy(&mut content, &mut tarball_file)?;
 
         Ok(tarball_path)
     }
 
-    fn get_metadata_from_crates_api_from_name(name: &str) -> Result<CrateResponse, crates_io_api::Error> {
-        let client = SyncClient::new(
+    fn get_metadata_from_crates_api_from_name(name: &str) -> Result<CrateResponse, ArianeError> {
+        let client = match SyncClient::new(
             "Ariane (https://github.com/N0fix/Ariane)",
             std::time::Duration::from_millis(1_0000),
-        )
-        .unwrap();
+        ) {
+            Ok(client) => client,
+            Err(e) => return Err(ArianeError::Registry(e.to_string())),
+        };
 
-        client.get_crate(name)
+        Ok(client.get_crate(name)?)
     }
 
-    fn get_metadata_from_crates_api(&self) -> Result<CrateResponse, crates_io_api::Error> {
+    fn get_metadata_from_crates_api(&self) -> Result<CrateResponse, ArianeError> {
         Krate::get_metadata_from_crates_api_from_name(&self.name.as_str())
     }
 
@@ -129,15 +124,12 @@ impl Krate {
     }
 
     /// ⚠️ This makes a blocking request to crates.io/api ⚠️
-    fn fill_information_from_crates_api(&mut self) -> Result<&Krate, KrateError> {
+    fn fill_information_from_crates_api(&mut self) -> Result<&Krate, ArianeError> {
         if self.is_accurate {
             return Ok(self);
         }
 
-        let metadata = match self.get_metadata_from_crates_api() {
-            Ok(meta) => meta,
-            Err(e) => return Err(KrateError::NoMetadataError(e)),
-        };
+        let metadata = self.get_metadata_from_crates_api()?;
 
         self.metadata = Some(metadata.clone());
 
@@ -147,7 +139,12 @@ impl Krate {
             .find(|v| v.num == self.version.to_string())
         {
             Some(v) => v,
-            None => return Err(KrateError::NonExistantVersion),
+            None => {
+                return Err(ArianeError::Registry(format!(
+                    "{} has no version {}",
+                    self.name, self.version
+                )))
+            }
         };
 
         self.filter_features(&v);
@@ -165,9 +162,14 @@ impl Krate {
         &self.features
     }
 
+    /// Strings of the executable the crate was found from, empty if it was not found in one.
+    pub fn get_evidence(&self) -> &Vec<String> {
+        &self.evidence
+    }
+
     /// Retrieves download url.
     /// ⚠️ This makes a blocking request to crates.io/api ⚠️
-    pub fn get_download_url(&mut self) -> Result<&str, KrateError> {
+    pub fn get_download_url(&mut self) -> Result<&str, ArianeError> {
         if !self.is_accurate {
             self.fill_information_from_crates_api()?;
         }
@@ -177,7 +179,7 @@ impl Krate {
 
     /// Retrives potential features used.
     /// ⚠️ This makes a blocking request to crates.io/api ⚠️
-    pub fn get_features(&mut self) -> Result<&Vec<String>, KrateError> {
+    pub fn get_features(&mut self) -> Result<&Vec<String>, ArianeError> {
         if !self.is_accurate {
             self.fill_information_from_crates_api()?;
         }
@@ -215,14 +217,14 @@ impl Dependencies {
         &mut self.krates
     }
 
-    pub fn from_file(filepath: &Path) -> Result<Dependencies, std::io::Error> {
+    pub fn from_file(filepath: &Path) -> Result<Dependencies, ArianeError> {
         let content = std::fs::read(&filepath)?;
 
         Ok(Dependencies::from_buffer(&content))
     }
 
     pub fn from_buffer(buffer: &Vec<u8>) -> Dependencies {
-        let mut map = HashMap::<String, Version>::new();
+        let mut map = HashMap::<String, (Version, Vec<String>)>::new();
         let re = Regex::new(r"cargo.registry.src.[^\\\/]+.(?<cratename>[^\\\/]+)").unwrap();
         let re_sources = Regex::new(
             r"cargo.registry.src.[^\\\/]+.([^\\\/]+[^\\\/])+.([^\\\/]+)+.(?<sources>[^\\\/].*).rs",
@@ -236,24 +238,34 @@ impl Dependencies {
         for c in ca {
             // println!("{:?}",c);
             if let Some(cratename) = c.name("cratename") {
-                let crate_string = String::from_utf8(cratename.as_bytes().to_vec()).unwrap();
-                let (name, version) = crate_string.rsplit_once('-').unwrap();
-                let splited_version: Vec<u64> = version
-                    .split('.')
-                    .into_iter()
-                    .map(|x| x.parse::<u64>().unwrap())
-                    .collect();
-                let version = Version::new(
-                    *splited_version.get(0).unwrap(),
-                    *splited_version.get(1).unwrap(),
-                    *splited_version.get(2).unwrap(),
-                );
-                map.insert(name.to_string(), version);
+                let crate_string = String::from_utf8_lossy(cratename.as_bytes()).to_string();
+                // Paths that do not end with `<name>-<version>` are not crates
+                let (name, version) = match crate_string
+                    .rsplit_once('-')
+                    .map(|(name, version)| (name, Version::parse(version)))
+                {
+                    Some((name, Ok(version))) => (name, version),
+                    _ => {
+                        debug!("Ignoring {:?}, not a crate", crate_string);
+                        continue;
+                    }
+                };
+                let evidence = String::from_utf8_lossy(&c[0]).to_string();
+                match map.get_mut(name) {
+                    Some((v, e)) if *v == version => {
+                        if !e.contains(&evidence) {
+                            e.push(evidence);
+                        }
+                    }
+                    _ => {
+                        map.insert(name.to_string(), (version, vec![evidence]));
+                    }
+                }
             }
         }
         for c in ca_sources {
             if let Some(sources) = c.name("sources") {
-                let sources = String::from_utf8(sources.as_bytes().to_vec()).unwrap();
+                let sources = String::from_utf8_lossy(sources.as_bytes()).to_string();
                 if sources.contains("\\") {
                     for s in sources.split("\\") {
                         features.push(s.to_string());
@@ -267,13 +279,14 @@ impl Dependencies {
         Dependencies {
             krates: map
                 .iter()
-                .map(|(name, version)| Krate {
+                .map(|(name, (version, evidence))| Krate {
                     name: name.to_owned(),
                     version: version.to_owned(),
                     download_url: String::new(),
                     features: features.clone(),
                     is_accurate: false,
                     metadata: None,
+                    evidence: evidence.to_owned(),
                 })
                 .collect(),
         }
@@ -1,2 +1,3 @@
 pub mod compiler;
 pub mod krate;
+pub mod profile_guess;
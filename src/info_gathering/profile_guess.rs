@@ -0,0 +1,375 @@
+use std::fmt::Display;
+
+use goblin::Object;
+use iced_x86::{Decoder, DecoderOptions, Instruction, Mnemonic};
+use regex::bytes::Regex;
+
+use crate::compilation::profile::{BuildProfile, Lto, OptLevel, PanicStrategy};
+use crate::functions_utils::search::{get_functions_from_bin, Function};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+impl Display for Confidence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Confidence::Low => write!(f, "low"),
+            Confidence::Medium => write!(f, "medium"),
+            Confidence::High => write!(f, "high"),
+        }
+    }
+}
+
+/// A guessed setting, along with what made us think so.
+#[derive(Debug, Clone)]
+pub struct Guess<T> {
+    pub value: T,
+    pub confidence: Confidence,
+    pub evidence: String,
+}
+
+impl<T: Display> Display for Guess<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({} confidence, {})",
+            self.value, self.confidence, self.evidence
+        )
+    }
+}
+
+/// Codegen settings the target was most likely built with.
+/// Every guess is a heuristic and may be wrong, especially on small binaries.
+///
+/// ```ignore
+/// let guess = ProfileGuess::from_buffer(&bytes);
+/// println!("panic={}", guess.panic);
+/// let profile = guess.to_build_profile();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProfileGuess {
+    pub panic: Guess<PanicStrategy>,
+    pub lto: Guess<Lto>,
+    pub opt_level: Guess<OptLevel>,
+}
+
+/// Minimum number of functions required to say anything about LTO and opt-level.
+const MIN_FUNCTIONS: usize = 100;
+/// Functions smaller than this are shims and small helpers that LTO usually inlines away.
+const TINY_FUNCTION_SZ: usize = 32;
+
+impl ProfileGuess {
+    /// Guesses the build profile of an executable. Functions are read from the exception
+    /// directory for PE files.
+    pub fn from_buffer(buffer: &[u8]) -> ProfileGuess {
+        let functions = match Object::parse(buffer) {
+            Ok(Object::PE(_)) => get_functions_from_bin(buffer, 0).unwrap_or_default(),
+            _ => vec![],
+        };
+
+        ProfileGuess::from_functions(buffer, &functions)
+    }
+
+    /// Guesses the build profile of an executable, given a list of its functions.
+    pub fn from_functions(buffer: &[u8], functions: &[Function]) -> ProfileGuess {
+        ProfileGuess {
+            panic: guess_panic_strategy(buffer),
+            lto: guess_lto(functions),
+            opt_level: guess_opt_level(functions),
+        }
+    }
+
+    pub fn to_build_profile(&self) -> BuildProfile {
+        BuildProfile {
+            opt_level: self.opt_level.value,
+            lto: self.lto.value,
+            // Fat LTO is most of the time used along with a single codegen unit
+            codegen_units: match self.lto.value {
+                Lto::Fat => Some(1),
+                _ => None,
+            },
+            panic: self.panic.value,
+        }
+    }
+}
+
+/// `panic_unwind` is only linked when building with `panic=unwind`. It either registers the
+/// `rust_panic` C++ exception type (MSVC), or uses the `MOZ\0RUST` exception class (gcc unwinder),
+/// stored as a big endian integer.
+fn guess_panic_strategy(buffer: &[u8]) -> Guess<PanicStrategy> {
+    let msvc_marker = Regex::new(r"rust_panic\x00").unwrap();
+    let gcc_marker = Regex::new(r"(?-u)MOZ\x00RUST|TSUR\x00ZOM").unwrap();
+
+    if msvc_marker.is_match(buffer) {
+        return Guess {
+            value: PanicStrategy::Unwind,
+            confidence: Confidence::High,
+            evidence: String::from("rust_panic exception type found"),
+        };
+    }
+    if gcc_marker.is_match(buffer) {
+        return Guess {
+            value: PanicStrategy::Unwind,
+            confidence: Confidence::High,
+            evidence: String::from("MOZ\\0RUST exception class found"),
+        };
+    }
+
+    match Object::parse(buffer) {
+        Ok(Object::Elf(elf)) => {
+            let except_table = elf
+                .section_headers
+                .iter()
+                .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".gcc_except_table"));
+            match except_table {
+                Some(sh) if sh.sh_size > 0 => Guess {
+                    value: PanicStrategy::Unwind,
+                    confidence: Confidence::Medium,
+                    evidence: format!(".gcc_except_table has {} bytes of landing pads", sh.sh_size),
+                },
+                _ => Guess {
+                    value: PanicStrategy::Abort,
+                    confidence: Confidence::High,
+                    evidence: String::from("no unwinding marker nor landing pads"),
+                },
+            }
+        }
+        Ok(_) => Guess {
+            value: PanicStrategy::Abort,
+            confidence: Confidence::Medium,
+            evidence: String::from("no panic_unwind exception type found"),
+        },
+        Err(_) => Guess {
+            value: PanicStrategy::Unwind,
+            confidence: Confidence::Low,
+            evidence: String::from("unknown file format, assuming default"),
+        },
+    }
+}
+
+/// LTO inlines small cross-crate helpers (trait shims, `drop_in_place` glue, accessors), which
+/// considerably reduces the share of tiny functions.
+fn guess_lto(functions: &[Function]) -> Guess<Lto> {
+    if functions.len() < MIN_FUNCTIONS {
+        return Guess {
+            value: Lto::Off,
+            confidence: Confidence::Low,
+            evidence: format!("only {} functions known, assuming default", functions.len()),
+        };
+    }
+
+    let tiny = functions
+        .iter()
+        .filter(|f| f.data.len() < TINY_FUNCTION_SZ)
+        .count();
+    let ratio = tiny as f64 / functions.len() as f64;
+    let evidence = format!("{:.1}% of functions are tiny", ratio * 100.0);
+
+    let (value, confidence) = if ratio < 0.04 {
+        (Lto::Fat, Confidence::Medium)
+    } else if ratio < 0.08 {
+        (Lto::Thin, Confidence::Low)
+    } else {
+        (Lto::Off, Confidence::Medium)
+    };
+
+    Guess {
+        value,
+        confidence,
+        evidence,
+    }
+}
+
+/// `opt-level=3` aligns loop headers with multi-byte `nop`s, `s` and `z` do not. `z` additionally
+/// stops aligning functions on 16 bytes.
+fn guess_opt_level(functions: &[Function]) -> Guess<OptLevel> {
+    if functions.len() < MIN_FUNCTIONS {
+        return Guess {
+            value: OptLevel::O3,
+            confidence: Confidence::Low,
+            evidence: format!("only {} functions known, assuming default", functions.len()),
+        };
+    }
+
+    let mut instructions = 0;
+    let mut alignment_nops = 0;
+    let mut aligned_functions = 0;
+
+    for f in functions {
+        if f.rva % 16 == 0 {
+            aligned_functions += 1;
+        }
+
+        let mut decoder = Decoder::new(64, f.data, DecoderOptions::NONE);
+        let mut instruction = Instruction::default();
+        while decoder.can_decode() {
+            decoder.decode_out(&mut instruction);
+            instructions += 1;
+            if instruction.mnemonic() == Mnemonic::Nop && instruction.len() > 1 {
+                alignment_nops += 1;
+            }
+        }
+    }
+
+    let nops_per_k = alignment_nops as f64 * 1000.0 / instructions.max(1) as f64;
+    let aligned_ratio = aligned_functions as f64 / functions.len() as f64;
+    let evidence = format!(
+        "{:.2} alignment nops per 1000 instructions, {:.0}% of functions aligned on 16 bytes",
+        nops_per_k,
+        aligned_ratio * 100.0
+    );
+
+    let (value, confidence) = if aligned_ratio < 0.5 {
+        (OptLevel::Z, Confidence::Medium)
+    } else if nops_per_k < 1.0 {
+        (OptLevel::S, Confidence::Low)
+    } else {
+        (OptLevel::O3, Confidence::Medium)
+    };
+
+    Guess {
+        value,
+        confidence,
+        evidence,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions_utils::search::FunctionType;
+    use crate::utils::patch::tests::{minimal_elf, minimal_elf_with_sections, ElfSymbols};
+    use crate::utils::pdb_writer::tests::minimal_pe;
+
+    /// 22 instructions, none of them a `nop`.
+    const PLAIN: [u8; 64] = {
+        let mut data = [0xc3; 64];
+        let mut i = 0;
+        while i < 63 {
+            // add rax, rcx
+            data[i] = 0x48;
+            data[i + 1] = 0x01;
+            data[i + 2] = 0xc8;
+            i += 3;
+        }
+        data
+    };
+
+    /// `PLAIN` starting with a 4 bytes alignment `nop`.
+    const WITH_NOP: [u8; 64] = {
+        let mut data = PLAIN;
+        data[0] = 0x0f;
+        data[1] = 0x1f;
+        data[2] = 0x40;
+        data[3] = 0x00;
+        data
+    };
+
+    /// `count` functions of `data`, the first `tiny` of them only being 8 bytes long.
+    fn functions(count: usize, tiny: usize, data: &[u8], aligned: bool) -> Vec<Function<'_>> {
+        (0..count)
+            .map(|i| Function {
+                data: if i < tiny { &data[..8] } else { data },
+                name: None,
+                rva: 0x1000 + i as u32 * 0x40 + if aligned { 0 } else { 4 },
+                fn_type: FunctionType::Exe,
+                relocations: vec![],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unwinding_markers_mean_unwind() {
+        for marker in [&b"rust_panic\0"[..], b"MOZ\0RUST", b"TSUR\0ZOM"] {
+            let mut buffer = minimal_pe();
+            buffer[0x400..0x400 + marker.len()].copy_from_slice(marker);
+            let guess = guess_panic_strategy(&buffer);
+            assert_eq!(guess.value, PanicStrategy::Unwind, "{}", guess);
+            assert_eq!(guess.confidence, Confidence::High);
+        }
+    }
+
+    #[test]
+    fn missing_markers_mean_abort() {
+        let guess = guess_panic_strategy(&minimal_pe());
+        assert_eq!(
+            (guess.value, guess.confidence),
+            (PanicStrategy::Abort, Confidence::Medium)
+        );
+
+        let guess = guess_panic_strategy(&minimal_elf(ElfSymbols::None));
+        assert_eq!(
+            (guess.value, guess.confidence),
+            (PanicStrategy::Abort, Confidence::High)
+        );
+    }
+
+    #[test]
+    fn elf_landing_pads_mean_unwind() {
+        let elf = minimal_elf_with_sections(ElfSymbols::None, &[(".gcc_except_table", 0x40)]);
+        let guess = guess_panic_strategy(&elf);
+        assert_eq!(
+            (guess.value, guess.confidence),
+            (PanicStrategy::Unwind, Confidence::Medium)
+        );
+
+        let elf = minimal_elf_with_sections(ElfSymbols::None, &[(".gcc_except_table", 0)]);
+        assert_eq!(guess_panic_strategy(&elf).value, PanicStrategy::Abort);
+    }
+
+    #[test]
+    fn unparsable_executables_keep_the_default() {
+        let guess = guess_panic_strategy(&minimal_elf(ElfSymbols::None)[..0x20]);
+        assert_eq!(
+            (guess.value, guess.confidence),
+            (PanicStrategy::Unwind, Confidence::Low)
+        );
+    }
+
+    #[test]
+    fn share_of_tiny_functions_gives_lto() {
+        for (tiny, lto, confidence) in [
+            (6, Lto::Fat, Confidence::Medium),
+            (12, Lto::Thin, Confidence::Low),
+            (40, Lto::Off, Confidence::Medium),
+        ] {
+            let guess = guess_lto(&functions(200, tiny, &PLAIN, true));
+            assert_eq!(
+                (guess.value, guess.confidence),
+                (lto, confidence),
+                "{}",
+                guess
+            );
+        }
+
+        let guess = guess_lto(&functions(MIN_FUNCTIONS - 1, 0, &PLAIN, true));
+        assert_eq!((guess.value, guess.confidence), (Lto::Off, Confidence::Low));
+    }
+
+    #[test]
+    fn alignment_gives_opt_level() {
+        for (data, aligned, opt_level, confidence) in [
+            (&WITH_NOP, true, OptLevel::O3, Confidence::Medium),
+            (&PLAIN, true, OptLevel::S, Confidence::Low),
+            (&WITH_NOP, false, OptLevel::Z, Confidence::Medium),
+        ] {
+            let guess = guess_opt_level(&functions(200, 0, data, aligned));
+            assert_eq!(
+                (guess.value, guess.confidence),
+                (opt_level, confidence),
+                "{}",
+                guess
+            );
+        }
+
+        let guess = guess_opt_level(&functions(MIN_FUNCTIONS - 1, 0, &WITH_NOP, false));
+        assert_eq!(
+            (guess.value, guess.confidence),
+            (OptLevel::O3, Confidence::Low)
+        );
+    }
+}
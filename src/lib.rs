@@ -1,11 +1,100 @@
+use std::fmt;
+
 pub mod compilation;
 pub mod functions_utils;
 pub mod info_gathering;
+pub mod output;
 pub mod sig;
 pub mod utils;
 
+/// Errors of the library, malformed samples and unreachable services being reported rather than
+/// panicking.
 #[derive(Debug)]
 pub enum ArianeError {
-    InvalidInput,
-    IOError(std::io::Error),
+    /// Malformed executable, debug information, archive or data file.
+    Parse(String),
+    /// Request to crates.io, GitHub or a toolchain mirror that could not be completed.
+    Network(reqwest::Error),
+    /// Crate or version unknown to crates.io, or whose metadata could not be retrieved.
+    Registry(String),
+    /// Crate that could not be prepared for compilation.
+    Compile(String),
+    /// Toolchain that could not be installed, found or run.
+    Toolchain(String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for ArianeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArianeError::Parse(e) => write!(f, "parse error: {}", e),
+            ArianeError::Network(e) => write!(f, "network error: {}", e),
+            ArianeError::Registry(e) => write!(f, "registry error: {}", e),
+            ArianeError::Compile(e) => write!(f, "compilation error: {}", e),
+            ArianeError::Toolchain(e) => write!(f, "toolchain error: {}", e),
+            ArianeError::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ArianeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ArianeError::Network(e) => Some(e),
+            ArianeError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ArianeError {
+    fn from(e: std::io::Error) -> Self {
+        ArianeError::Io(e)
+    }
+}
+
+impl From<reqwest::Error> for ArianeError {
+    fn from(e: reqwest::Error) -> Self {
+        ArianeError::Network(e)
+    }
+}
+
+impl From<crates_io_api::Error> for ArianeError {
+    fn from(e: crates_io_api::Error) -> Self {
+        ArianeError::Registry(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ArianeError {
+    fn from(e: serde_json::Error) -> Self {
+        ArianeError::Parse(e.to_string())
+    }
+}
+
+impl From<goblin::error::Error> for ArianeError {
+    fn from(e: goblin::error::Error) -> Self {
+        ArianeError::Parse(e.to_string())
+    }
+}
+
+impl From<pdb::Error> for ArianeError {
+    fn from(e: pdb::Error) -> Self {
+        ArianeError::Parse(e.to_string())
+    }
+}
+
+impl From<semver::Error> for ArianeError {
+    fn from(e: semver::Error) -> Self {
+        ArianeError::Parse(e.to_string())
+    }
+}
+
+/// So that commands, which report `std::io::Error`s, can use `?` on library calls.
+impl From<ArianeError> for std::io::Error {
+    fn from(e: ArianeError) -> Self {
+        match e {
+            ArianeError::Io(e) => e,
+            e => std::io::Error::other(e),
+        }
+    }
 }
@@ -0,0 +1,28 @@
+use std::fmt::Write;
+
+use crate::output::{python_string, ExportedSymbol};
+
+/// Binary Ninja Python script creating and naming functions, to run from the script console or
+/// with *Run Script*.
+pub fn script(symbols: &[ExportedSymbol]) -> String {
+    let mut script = String::from("from binaryninja import Symbol, SymbolType\n\nSYMBOLS = [\n");
+    for symbol in symbols {
+        writeln!(
+            script,
+            "    (0x{:x}, {}),",
+            symbol.address,
+            python_string(&symbol.name)
+        )
+        .unwrap();
+    }
+    script.push_str(
+        "]\n\
+         \n\
+         for address, name in SYMBOLS:\n    \
+             if bv.get_function_at(address) is None:\n        \
+                 bv.create_user_function(address)\n    \
+             bv.define_user_symbol(Symbol(SymbolType.FunctionSymbol, address, name))\n",
+    );
+
+    script
+}
@@ -0,0 +1,39 @@
+use std::fmt::Write;
+
+use crate::output::{python_string, ExportedSymbol};
+
+/// Ghidra Python script creating and naming functions. Ghidra scripts run on Jython, whose string
+/// literals are not unicode unless prefixed with `u`.
+pub fn script(symbols: &[ExportedSymbol]) -> String {
+    let mut script = String::from(
+        "# Names functions recovered by Ariane.\n\
+         #@category Ariane\n\
+         \n\
+         from ghidra.program.model.symbol import SourceType, SymbolUtilities\n\
+         \n\
+         SYMBOLS = [\n",
+    );
+    for symbol in symbols {
+        writeln!(
+            script,
+            "    (0x{:x}, u{}),",
+            symbol.address,
+            python_string(&symbol.name)
+        )
+        .unwrap();
+    }
+    script.push_str(
+        "]\n\
+         \n\
+         for address, name in SYMBOLS:\n    \
+             entry = toAddr(address)\n    \
+             function = getFunctionAt(entry) or createFunction(entry, None)\n    \
+             name = SymbolUtilities.replaceInvalidChars(name, True)\n    \
+             if function is not None:\n        \
+                 function.setName(name, SourceType.IMPORTED)\n    \
+             else:\n        \
+                 createLabel(entry, name, True, SourceType.IMPORTED)\n",
+    );
+
+    script
+}
@@ -0,0 +1,349 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::compilation::fallback::Fallback;
+use crate::compilation::report::{BuildReport, BuildReportEntry};
+use crate::output::results::Results;
+use crate::output::NameStyle;
+use crate::sig::comparaison::{Method, Symbol};
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; color: #222; }
+h1, h2 { font-weight: normal; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; vertical-align: top; }
+th { background: #eee; }
+td.num { text-align: right; font-family: monospace; }
+code { font-size: 0.9em; }
+.ok { color: #080; }
+.failed { color: #b00; }
+.histogram td.bar div { background: #4a7fb5; height: 1em; }
+#search { width: 40em; margin-bottom: 0.5em; }";
+
+/// Hides the rows of the symbol table not containing the searched text.
+const SEARCH: &str = "document.getElementById('search').addEventListener('input', function (e) {
+  var needle = e.target.value.toLowerCase();
+  document.querySelectorAll('#symbols tbody tr').forEach(function (row) {
+    row.style.display = row.textContent.toLowerCase().includes(needle) ? '' : 'none';
+  });
+});";
+
+/// Width of score histogram buckets.
+const BUCKET: u32 = 10;
+
+/// `s` with HTML special characters escaped.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn percent(part: usize, total: usize) -> f64 {
+    match total {
+        0 => 0.0,
+        total => part as f64 * 100.0 / total as f64,
+    }
+}
+
+fn method_name(method: &Method) -> String {
+    match method {
+        Method::Hash { algorithm } => format!("hash ({})", algorithm),
+        Method::Pattern => "pattern".to_string(),
+        Method::Call { called_from } => format!("call from 0x{:x}", called_from),
+    }
+}
+
+/// Status of each profile `krate` was built with, successful if any attempt succeeded.
+fn build_status(builds: &BuildReport, krate: &str, version: &str) -> String {
+    let mut by_profile = BTreeMap::<&str, &BuildReportEntry>::new();
+    for entry in &builds.builds {
+        if entry.krate == krate && entry.version == version {
+            match by_profile.get(entry.profile.as_str()) {
+                Some(previous) if previous.success => {}
+                _ => {
+                    by_profile.insert(&entry.profile, entry);
+                }
+            }
+        }
+    }
+    if by_profile.is_empty() {
+        return "signatures from database".to_string();
+    }
+
+    let mut status = String::new();
+    for (profile, entry) in by_profile {
+        match (entry.success, entry.failure) {
+            (true, _) => {
+                write!(status, "<span class=\"ok\">{}: built", escape(profile)).unwrap();
+                if let Some(fallback) = entry.fallback.filter(|f| *f != Fallback::None) {
+                    write!(status, " ({})", fallback).unwrap();
+                }
+                status.push_str("</span><br>");
+            }
+            (false, failure) => {
+                write!(status, "<span class=\"failed\">{}: failed", escape(profile)).unwrap();
+                if let Some(failure) = failure {
+                    write!(status, " ({})", failure).unwrap();
+                }
+                status.push_str("</span><br>");
+            }
+        }
+    }
+    status
+}
+
+fn histogram(report: &mut String, title: &str, symbols: &[&Symbol]) {
+    let mut buckets = [0usize; (100 / BUCKET) as usize];
+    for symbol in symbols {
+        let bucket = (symbol.score / BUCKET).min(buckets.len() as u32 - 1);
+        buckets[bucket as usize] += 1;
+    }
+    let max = buckets.iter().copied().max().unwrap_or(0);
+
+    writeln!(
+        report,
+        "<h3>{}</h3>\n<table class=\"histogram\">",
+        escape(title)
+    )
+    .unwrap();
+    for (i, count) in buckets.iter().enumerate() {
+        let low = i as u32 * BUCKET;
+        let high = match i + 1 == buckets.len() {
+            true => 100,
+            false => low + BUCKET - 1,
+        };
+        writeln!(
+            report,
+            "<tr><td class=\"num\">{}-{}</td><td class=\"num\">{}</td>\
+             <td class=\"bar\"><div style=\"width: {:.0}px\"></div></td></tr>",
+            low,
+            high,
+            count,
+            percent(*count, max) * 3.0
+        )
+        .unwrap();
+    }
+    report.push_str("</table>\n");
+}
+
+/// Self-contained HTML page summarizing `results`, builds of the crates coming from `builds`.
+/// Names are written in `style`.
+pub fn report(results: &Results, builds: &BuildReport, style: NameStyle) -> String {
+    let sample = &results.sample;
+    let mut report = String::new();
+    writeln!(
+        report,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Ariane report: {}</title>\n<style>\n{}\n</style>\n</head>\n<body>",
+        escape(&sample.file_name),
+        STYLE
+    )
+    .unwrap();
+
+    writeln!(report, "<h1>{}</h1>\n<table>", escape(&sample.file_name)).unwrap();
+    let rows = [
+        ("SHA-256", format!("<code>{}</code>", sample.sha256)),
+        (
+            "Image base",
+            format!("<code>0x{:x}</code>", sample.image_base),
+        ),
+        (
+            "Target",
+            escape(sample.target.as_deref().unwrap_or("unknown")),
+        ),
+        (
+            "Compiler",
+            match &results.rustc {
+                Some(rustc) => format!(
+                    "rustc {} (<code>{}</code>)",
+                    escape(&rustc.version),
+                    escape(&rustc.commit_hash)
+                ),
+                None => "not identified".to_string(),
+            },
+        ),
+        (
+            "Comparison",
+            format!("{}, threshold {}", results.algorithm, results.threshold),
+        ),
+        ("Ariane", escape(&results.ariane_version)),
+    ];
+    for (name, value) in rows {
+        writeln!(report, "<tr><th>{}</th><td>{}</td></tr>", name, value).unwrap();
+    }
+    report.push_str("</table>\n");
+
+    // Coverage
+    let mut by_crate = BTreeMap::<&str, usize>::new();
+    let mut by_method = BTreeMap::<String, usize>::new();
+    for symbol in &results.symbols {
+        let krate = symbol
+            .origin
+            .as_ref()
+            .map_or("unknown", |o| o.krate.as_str());
+        *by_crate.entry(krate).or_default() += 1;
+        let method = match symbol.method {
+            Method::Call { .. } => "call".to_string(),
+            method => method_name(&method),
+        };
+        *by_method.entry(method).or_default() += 1;
+    }
+    writeln!(
+        report,
+        "<h2>Coverage</h2>\n<p>{} of {} functions named ({:.1}%).</p>",
+        results.symbols.len(),
+        sample.functions,
+        percent(results.symbols.len(), sample.functions)
+    )
+    .unwrap();
+    report.push_str("<table>\n<tr><th>Found by</th><th>Functions</th></tr>\n");
+    for (method, count) in &by_method {
+        writeln!(
+            report,
+            "<tr><td>{}</td><td class=\"num\">{}</td></tr>",
+            escape(method),
+            count
+        )
+        .unwrap();
+    }
+    report.push_str("</table>\n<table>\n<tr><th>Crate</th><th>Functions</th><th>Share</th></tr>\n");
+    for (krate, count) in &by_crate {
+        writeln!(
+            report,
+            "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{:.1}%</td></tr>",
+            escape(krate),
+            count,
+            percent(*count, sample.functions)
+        )
+        .unwrap();
+    }
+    report.push_str("</table>\n");
+
+    // Dependencies
+    report.push_str(
+        "<h2>Dependencies</h2>\n<table>\n<tr><th>Crate</th><th>Version</th><th>Features</th>\
+         <th>Evidence</th><th>Signatures</th><th>Functions</th></tr>\n",
+    );
+    for krate in &results.crates {
+        let evidence = match krate.evidence.len() {
+            0 => String::new(),
+            n => format!(
+                "<details><summary>{} string{}</summary>{}</details>",
+                n,
+                if n > 1 { "s" } else { "" },
+                krate
+                    .evidence
+                    .iter()
+                    .map(|e| format!("<code>{}</code>", escape(e)))
+                    .collect::<Vec<String>>()
+                    .join("<br>")
+            ),
+        };
+        writeln!(
+            report,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"num\">{}</td></tr>",
+            escape(&krate.name),
+            escape(&krate.version),
+            escape(&krate.features.join(", ")),
+            evidence,
+            build_status(builds, &krate.name, &krate.version),
+            by_crate.get(krate.name.as_str()).copied().unwrap_or(0)
+        )
+        .unwrap();
+    }
+    report.push_str("</table>\n");
+
+    // Scores
+    report.push_str("<h2>Scores</h2>\n");
+    let all: Vec<&Symbol> = results.symbols.iter().collect();
+    histogram(&mut report, "All names", &all);
+    let hashed: Vec<&Symbol> = all
+        .iter()
+        .copied()
+        .filter(|s| matches!(s.method, Method::Hash { .. }))
+        .collect();
+    histogram(&mut report, "Names found by hash", &hashed);
+
+    // Symbols
+    writeln!(
+        report,
+        "<h2>Symbols</h2>\n<input id=\"search\" type=\"search\" placeholder=\"Filter symbols\">\n\
+         <table id=\"symbols\">\n<thead><tr><th>Address</th><th>Name</th><th>Score</th>\
+         <th>Found by</th><th>Origin</th><th>Alternatives</th></tr></thead>\n<tbody>"
+    )
+    .unwrap();
+    for symbol in &results.symbols {
+        let origin = match &symbol.origin {
+            Some(o) => format!("{} {} ({})", o.krate, o.version, o.profile),
+            None => String::new(),
+        };
+        let alternatives = symbol
+            .alternatives
+            .iter()
+            .map(|a| format!("{} ({})", escape(&style.apply(&a.name)), a.score))
+            .collect::<Vec<String>>()
+            .join("<br>");
+        writeln!(
+            report,
+            "<tr><td class=\"num\">{:x}</td><td title=\"{}\">{}</td><td class=\"num\">{}</td>\
+             <td>{}</td><td>{}</td><td>{}</td></tr>",
+            sample.image_base + symbol.rva as u64,
+            escape(&symbol.name),
+            escape(&style.apply(&symbol.name)),
+            symbol.score,
+            escape(&method_name(&symbol.method)),
+            escape(&origin),
+            alternatives
+        )
+        .unwrap();
+    }
+    writeln!(
+        report,
+        "</tbody>\n</table>\n<script>\n{}\n</script>\n</body>\n</html>",
+        SEARCH
+    )
+    .unwrap();
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::results::tests::results;
+
+    #[test]
+    fn html_is_escaped() {
+        assert_eq!(
+            escape("<script>alert('x & y')</script>\""),
+            "&lt;script&gt;alert(&#39;x &amp; y&#39;)&lt;/script&gt;&quot;"
+        );
+
+        let mut results = results();
+        results.sample.file_name = "<script>alert(1)</script>.exe".to_string();
+        results.symbols[0].name = "<script>alert(2)</script>".to_string();
+        let report = report(&results, &BuildReport::default(), NameStyle::Hashless);
+        assert!(!report.contains("<script>alert"));
+        assert!(report.contains("&lt;script&gt;alert(1)&lt;/script&gt;.exe"));
+        assert!(report.contains("&lt;script&gt;alert(2)&lt;/script&gt;"));
+    }
+
+    #[test]
+    fn names_are_written_in_the_chosen_style() {
+        let results = results();
+        let name = "_ZN4core3fmt5write17h0123456789abcdefE";
+
+        let report_names = |style| report(&results, &BuildReport::default(), style);
+        assert!(report_names(NameStyle::Hashless).contains(">core::fmt::write<"));
+        assert!(report_names(NameStyle::Full).contains(">core::fmt::write::h0123456789abcdef<"));
+        assert!(report_names(NameStyle::Mangled).contains(&format!(">{}<", name)));
+    }
+}
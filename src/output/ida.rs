@@ -0,0 +1,62 @@
+use std::fmt::Write;
+
+use crate::output::{python_string, ExportedSymbol};
+
+/// IDC string literal of `s`, bytes outside of printable ASCII being escaped.
+fn idc_string(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for b in s.bytes() {
+        match b {
+            b'"' | b'\\' => {
+                literal.push('\\');
+                literal.push(b as char);
+            }
+            0x20..=0x7e => literal.push(b as char),
+            _ => write!(literal, "\\x{:02x}", b).unwrap(),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// IDC script creating and naming functions. IDA replaces characters it does not accept in names
+/// (`SN_NOCHECK`) and suffixes names already in use (`SN_FORCE`).
+pub fn idc(symbols: &[ExportedSymbol]) -> String {
+    let mut script = String::from("#include <idc.idc>\n\nstatic main() {\n");
+    for symbol in symbols {
+        writeln!(script, "    add_func(0x{:x});", symbol.address).unwrap();
+        writeln!(
+            script,
+            "    set_name(0x{:x}, {}, SN_NOCHECK | SN_NOWARN | SN_FORCE);",
+            symbol.address,
+            idc_string(&symbol.name)
+        )
+        .unwrap();
+    }
+    script.push_str("}\n");
+
+    script
+}
+
+/// IDAPython equivalent of `idc`.
+pub fn idapython(symbols: &[ExportedSymbol]) -> String {
+    let mut script = String::from("import ida_funcs\nimport ida_name\n\nSYMBOLS = [\n");
+    for symbol in symbols {
+        writeln!(
+            script,
+            "    (0x{:x}, {}),",
+            symbol.address,
+            python_string(&symbol.name)
+        )
+        .unwrap();
+    }
+    script.push_str(
+        "]\n\
+         \n\
+         for address, name in SYMBOLS:\n    \
+             ida_funcs.add_func(address)\n    \
+             ida_name.set_name(address, name, ida_name.SN_NOCHECK | ida_name.SN_NOWARN | ida_name.SN_FORCE)\n",
+    );
+
+    script
+}
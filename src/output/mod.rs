@@ -0,0 +1,308 @@
+pub mod binja;
+pub mod ghidra;
+pub mod html;
+pub mod ida;
+pub mod nm;
+pub mod r2;
+pub mod results;
+pub mod x64dbg;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::output::results::Results;
+use crate::sig::comparaison::Symbol;
+
+/// Format of the results of `recover`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Format {
+    /// `results::Results`, the only format that keeps scores, origins and alternatives.
+    #[default]
+    Json,
+    /// IDA IDC script.
+    Idc,
+    /// IDAPython script.
+    Idapython,
+    /// Ghidra Python script.
+    Ghidra,
+    /// Binary Ninja Python script.
+    Binja,
+    /// radare2 command script, to run with `.` or `-i`.
+    R2,
+    /// x64dbg label database, to import with `dbload`.
+    X64dbg,
+    /// `nm`-style listing.
+    Nm,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Json => write!(f, "json"),
+            Format::Idc => write!(f, "idc"),
+            Format::Idapython => write!(f, "idapython"),
+            Format::Ghidra => write!(f, "ghidra"),
+            Format::Binja => write!(f, "binja"),
+            Format::R2 => write!(f, "r2"),
+            Format::X64dbg => write!(f, "x64dbg"),
+            Format::Nm => write!(f, "nm"),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "idc" => Ok(Format::Idc),
+            "idapython" => Ok(Format::Idapython),
+            "ghidra" => Ok(Format::Ghidra),
+            "binja" => Ok(Format::Binja),
+            "r2" => Ok(Format::R2),
+            "x64dbg" => Ok(Format::X64dbg),
+            "nm" => Ok(Format::Nm),
+            other => Err(format!("Unknown format {:?}", other)),
+        }
+    }
+}
+
+/// How names are written.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum NameStyle {
+    /// As in the library, e.g. `_ZN4core3fmt5write17h0123456789abcdefE`.
+    Mangled,
+    /// Demangled, with the hash of legacy symbols, e.g. `core::fmt::write::h0123456789abcdef`.
+    Full,
+    /// Demangled without hash, e.g. `core::fmt::write`.
+    #[default]
+    Hashless,
+    /// Demangled without hash, characters disassemblers reject in names (spaces, `<>`, `&`, ...)
+    /// being replaced with `_`, e.g. `_alloc::vec::Vec_T__as_core::ops::drop::Drop_::drop`.
+    Sanitized,
+}
+
+impl NameStyle {
+    /// `name` in this style. Names that are not Rust symbols are returned as is, sanitized if
+    /// needed.
+    pub fn apply(&self, name: &str) -> String {
+        match self {
+            NameStyle::Mangled => name.to_string(),
+            NameStyle::Full => rustc_demangle::demangle(name).to_string(),
+            NameStyle::Hashless => format!("{:#}", rustc_demangle::demangle(name)),
+            NameStyle::Sanitized => sanitize(&NameStyle::Hashless.apply(name)),
+        }
+    }
+}
+
+/// `name` with only the characters IDA, Ghidra and debuggers accept in names.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | ':' | '.' | '$' | '@' | '?' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+impl fmt::Display for NameStyle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NameStyle::Mangled => write!(f, "mangled"),
+            NameStyle::Full => write!(f, "full"),
+            NameStyle::Hashless => write!(f, "hashless"),
+            NameStyle::Sanitized => write!(f, "sanitized"),
+        }
+    }
+}
+
+impl FromStr for NameStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mangled" => Ok(NameStyle::Mangled),
+            "full" => Ok(NameStyle::Full),
+            "hashless" => Ok(NameStyle::Hashless),
+            "sanitized" => Ok(NameStyle::Sanitized),
+            other => Err(format!("Unknown name style {:?}", other)),
+        }
+    }
+}
+
+/// A symbol as written by exporters.
+pub struct ExportedSymbol {
+    pub address: u64,
+    pub rva: u32,
+    /// In the style picked for the output.
+    pub name: String,
+}
+
+/// Symbols of `results`, sorted by address.
+fn exported_symbols(results: &Results, style: NameStyle) -> Vec<ExportedSymbol> {
+    let mut symbols: Vec<ExportedSymbol> = results
+        .symbols
+        .iter()
+        .map(|s| ExportedSymbol {
+            address: results.sample.image_base + s.rva as u64,
+            rva: s.rva,
+            name: style.apply(&s.name),
+        })
+        .collect();
+    symbols.sort_by_key(|s| s.address);
+
+    symbols
+}
+
+/// `name` of each symbol, suffixed with `_<rva>` when a previous symbol already has it, for tools
+/// where a name identifies a single address. Monomorphized shims often share a demangled name.
+fn unique_names(
+    symbols: &[ExportedSymbol],
+    name: impl Fn(&ExportedSymbol) -> String,
+) -> Vec<String> {
+    let mut used = HashSet::new();
+    symbols
+        .iter()
+        .map(|symbol| {
+            let mut name = name(symbol);
+            if !used.insert(name.clone()) {
+                name = format!("{}_{:x}", name, symbol.rva);
+                used.insert(name.clone());
+            }
+            name
+        })
+        .collect()
+}
+
+/// Python string literal of `s`, which JSON string literals are.
+fn python_string(s: &str) -> String {
+    serde_json::to_string(s).unwrap()
+}
+
+/// `results` in `format`, names being written in `style`. JSON keeps mangled names, along with
+/// their `style` version.
+pub fn export(format: Format, results: &Results, style: NameStyle) -> String {
+    if format == Format::Json {
+        let results = Results {
+            symbols: results
+                .symbols
+                .iter()
+                .map(|s| Symbol {
+                    demangled: (style != NameStyle::Mangled).then(|| style.apply(&s.name)),
+                    ..s.clone()
+                })
+                .collect(),
+            ..results.clone()
+        };
+        return serde_json::to_string(&results).unwrap();
+    }
+
+    let symbols = exported_symbols(results, style);
+    match format {
+        Format::Json => unreachable!(),
+        Format::Idc => ida::idc(&symbols),
+        Format::Idapython => ida::idapython(&symbols),
+        Format::Ghidra => ghidra::script(&symbols),
+        Format::Binja => binja::script(&symbols),
+        Format::R2 => r2::script(&symbols),
+        Format::X64dbg => x64dbg::database(&results.sample, &symbols),
+        Format::Nm => nm::listing(&symbols),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(rva: u32, name: &str) -> ExportedSymbol {
+        ExportedSymbol {
+            address: 0x1_4000_0000 + rva as u64,
+            rva,
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn shared_names_are_suffixed_with_their_rva() {
+        let symbols = [
+            symbol(0x1000, "<&T as core::fmt::Debug>::fmt"),
+            symbol(0x1010, "main"),
+            symbol(0x1020, "<&T as core::fmt::Debug>::fmt"),
+            symbol(0x1030, "<&T as core::fmt::Debug>::fmt"),
+        ];
+        assert_eq!(
+            unique_names(&symbols, |s| s.name.clone()),
+            [
+                "<&T as core::fmt::Debug>::fmt",
+                "main",
+                "<&T as core::fmt::Debug>::fmt_1020",
+                "<&T as core::fmt::Debug>::fmt_1030",
+            ]
+        );
+    }
+
+    const LEGACY: &str = "_ZN4core3fmt5write17h0123456789abcdefE";
+    const LEGACY_IMPL: &str =
+        "_ZN66_$LT$alloc..vec..Vec$LT$T$GT$$u20$as$u20$core..ops..drop..Drop$GT$4drop17h0123456789abcdefE";
+    const V0: &str = "_RNvCs1234_7mycrate3foo";
+    const V0_GENERIC: &str = "_RINvNtC3std3mem8align_ofdE";
+
+    #[test]
+    fn legacy_symbols_are_styled() {
+        assert_eq!(NameStyle::Mangled.apply(LEGACY), LEGACY);
+        assert_eq!(
+            NameStyle::Full.apply(LEGACY),
+            "core::fmt::write::h0123456789abcdef"
+        );
+        assert_eq!(NameStyle::Hashless.apply(LEGACY), "core::fmt::write");
+        assert_eq!(NameStyle::Sanitized.apply(LEGACY), "core::fmt::write");
+
+        assert_eq!(
+            NameStyle::Hashless.apply(LEGACY_IMPL),
+            "<alloc::vec::Vec<T> as core::ops::drop::Drop>::drop"
+        );
+        assert_eq!(
+            NameStyle::Sanitized.apply(LEGACY_IMPL),
+            "_alloc::vec::Vec_T__as_core::ops::drop::Drop_::drop"
+        );
+    }
+
+    #[test]
+    fn v0_symbols_are_styled() {
+        assert_eq!(NameStyle::Mangled.apply(V0), V0);
+        assert_eq!(NameStyle::Full.apply(V0), "mycrate[3c1c0]::foo");
+        assert_eq!(NameStyle::Hashless.apply(V0), "mycrate::foo");
+        assert_eq!(NameStyle::Sanitized.apply(V0), "mycrate::foo");
+
+        assert_eq!(
+            NameStyle::Hashless.apply(V0_GENERIC),
+            "std::mem::align_of::<f64>"
+        );
+        assert_eq!(
+            NameStyle::Sanitized.apply(V0_GENERIC),
+            "std::mem::align_of::_f64_"
+        );
+    }
+
+    #[test]
+    fn other_names_are_kept() {
+        for style in [NameStyle::Mangled, NameStyle::Full, NameStyle::Hashless] {
+            assert_eq!(style.apply("?main@@YAHXZ"), "?main@@YAHXZ");
+            assert_eq!(style.apply("operator new"), "operator new");
+        }
+        assert_eq!(NameStyle::Sanitized.apply("?main@@YAHXZ"), "?main@@YAHXZ");
+        assert_eq!(NameStyle::Sanitized.apply("operator new"), "operator_new");
+    }
+
+    #[test]
+    fn sanitize_only_keeps_name_characters() {
+        assert_eq!(
+            sanitize("<&T as core::fmt::Debug>::fmt"),
+            "__T_as_core::fmt::Debug_::fmt"
+        );
+        assert_eq!(sanitize("{{closure}}#1 [é]"), "__closure___1____");
+        assert_eq!(sanitize("_$.@?:aZ09"), "_$.@?:aZ09");
+    }
+}
@@ -0,0 +1,13 @@
+use std::fmt::Write;
+
+use crate::output::ExportedSymbol;
+
+/// One `<address> T <name>` line per symbol, like `nm`.
+pub fn listing(symbols: &[ExportedSymbol]) -> String {
+    let mut listing = String::new();
+    for symbol in symbols {
+        writeln!(listing, "{:016x} T {}", symbol.address, symbol.name).unwrap();
+    }
+
+    listing
+}
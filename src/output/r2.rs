@@ -0,0 +1,29 @@
+use std::fmt::Write;
+
+use crate::output::{unique_names, ExportedSymbol};
+
+/// Name made of the characters radare2 accepts in flags and function names, others being
+/// replaced with `_`.
+fn r2_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.' | ':' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// radare2 script flagging, creating and naming functions. Setting a flag that already exists
+/// moves it, so names are made unique.
+pub fn script(symbols: &[ExportedSymbol]) -> String {
+    let names = unique_names(symbols, |symbol| r2_name(&symbol.name));
+    let mut script = String::from("fs+ariane\n");
+    for (symbol, name) in symbols.iter().zip(names) {
+        writeln!(script, "f sym.{} @ 0x{:x}", name, symbol.address).unwrap();
+        writeln!(script, "af @ 0x{:x}", symbol.address).unwrap();
+        writeln!(script, "afn {} @ 0x{:x}", name, symbol.address).unwrap();
+    }
+    script.push_str("fs-\n");
+
+    script
+}
@@ -0,0 +1,282 @@
+//! JSON results of `recover`, read back by `export`.
+//!
+//! ```json
+//! {
+//!   "format_version": 1,
+//!   "ariane_version": "1.0.3",
+//!   "sample": {
+//!     "file_name": "sample.exe",
+//!     "sha256": "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08",
+//!     "image_base": 5368709120,
+//!     "target": "x86_64-pc-windows-msvc",
+//!     "functions": 4242
+//!   },
+//!   "rustc": { "version": "1.72.0", "commit_hash": "5680fa18feaa87f3ff04063800aec256c3d4b4be" },
+//!   "crates": [
+//!     {
+//!       "name": "regex",
+//!       "version": "1.9.4",
+//!       "features": ["std"],
+//!       "evidence": ["cargo\\registry\\src\\index.crates.io-6f17d22bba15001f\\regex-1.9.4"]
+//!     }
+//!   ],
+//!   "algorithm": "ssdeep",
+//!   "threshold": 25,
+//!   "symbols": [
+//!     {
+//!       "name": "_ZN5regex5regex6string5Regex3new17h0123456789abcdefE",
+//!       "demangled": "regex::regex::string::Regex::new",
+//!       "rva": 4096,
+//!       "score": 87,
+//!       "method": { "kind": "hash", "algorithm": "ssdeep" },
+//!       "origin": { "crate": "regex", "version": "1.9.4", "profile": "opt-level=3" },
+//!       "alternatives": [{ "name": "...", "score": 80, "origin": { "...": "..." } }]
+//!     }
+//!   ]
+//! }
+//! ```
+
+use goblin::Object;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use crate::info_gathering::compiler::guess_target_triple;
+use crate::sig::comparaison::Symbol;
+use crate::sig::sig_generation::Algorithm;
+use crate::utils::patch::elf_image_base;
+
+/// Version of the results, bumped on incompatible changes.
+pub const RESULTS_FORMAT_VERSION: u32 = 1;
+
+/// The executable symbols were recovered from.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Sample {
+    /// Which x64dbg identifies modules by.
+    pub file_name: String,
+    pub sha256: String,
+    /// Address RVAs are relative to.
+    pub image_base: u64,
+    /// Target triple, when it could be guessed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Number of functions found in the sample.
+    pub functions: usize,
+}
+
+impl Sample {
+    pub fn new(path: &Path, bytes: &[u8], functions: usize) -> Sample {
+        let image_base = match Object::parse(bytes) {
+            Ok(Object::PE(pe)) => pe.image_base as u64,
+            Ok(Object::Elf(elf)) => elf_image_base(&elf),
+            _ => 0,
+        };
+
+        Sample {
+            file_name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            sha256: hex::encode(Sha256::digest(bytes)),
+            image_base,
+            target: guess_target_triple(bytes),
+            functions,
+        }
+    }
+}
+
+/// Compiler the sample was built with.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Rustc {
+    pub version: String,
+    pub commit_hash: String,
+}
+
+/// A dependency found in the sample.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DetectedCrate {
+    pub name: String,
+    pub version: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+    /// Strings of the sample the crate was found from.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub evidence: Vec<String>,
+}
+
+/// Results of `recover`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Results {
+    pub format_version: u32,
+    /// Version of Ariane the results were produced by.
+    pub ariane_version: String,
+    pub sample: Sample,
+    /// Absent when the compiler could not be identified.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rustc: Option<Rustc>,
+    pub crates: Vec<DetectedCrate>,
+    /// Used to compare functions.
+    pub algorithm: Algorithm,
+    /// Score hash matches had to exceed.
+    pub threshold: u32,
+    /// One per named function, sorted by RVA.
+    pub symbols: Vec<Symbol>,
+}
+
+impl Results {
+    pub fn new(
+        sample: Sample,
+        rustc: Option<Rustc>,
+        crates: Vec<DetectedCrate>,
+        algorithm: Algorithm,
+        threshold: u32,
+        mut symbols: Vec<Symbol>,
+    ) -> Results {
+        symbols.sort_by_key(|s| s.rva);
+
+        Results {
+            format_version: RESULTS_FORMAT_VERSION,
+            ariane_version: env!("CARGO_PKG_VERSION").to_string(),
+            sample,
+            rustc,
+            crates,
+            algorithm,
+            threshold,
+            symbols,
+        }
+    }
+
+    /// Parses results written by this version of Ariane.
+    pub fn from_slice(content: &[u8]) -> Result<Results, Error> {
+        #[derive(Deserialize)]
+        struct Versioned {
+            format_version: Option<u32>,
+        }
+
+        match serde_json::from_slice::<Versioned>(content)
+            .ok()
+            .and_then(|v| v.format_version)
+        {
+            Some(RESULTS_FORMAT_VERSION) => Ok(serde_json::from_slice(content)?),
+            Some(version) => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Results format version {} is not supported, expected {}",
+                    version, RESULTS_FORMAT_VERSION
+                ),
+            )),
+            None => Err(Error::new(
+                ErrorKind::InvalidData,
+                "Not Ariane results, or results written before they were versioned",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::sig::comparaison::{Alternative, Method, Origin};
+
+    pub(crate) fn results() -> Results {
+        let origin = Origin {
+            krate: "regex".to_string(),
+            version: "1.9.4".to_string(),
+            profile: "opt-level=3,lto=off,panic=unwind".to_string(),
+            fallback: None,
+        };
+        let symbols = vec![
+            Symbol {
+                name: "_ZN5regex5regex6string5Regex3new17h0123456789abcdefE".to_string(),
+                demangled: None,
+                rva: 0x2000,
+                score: 87,
+                method: Method::Hash {
+                    algorithm: Algorithm::Ssdeep,
+                },
+                origin: Some(origin.clone()),
+                alternatives: vec![Alternative {
+                    name: "_ZN5regex5regex5bytes5Regex3new17h0123456789abcdefE".to_string(),
+                    score: 80,
+                    origin: Some(origin),
+                }],
+            },
+            Symbol {
+                name: "_ZN4core3fmt5write17h0123456789abcdefE".to_string(),
+                demangled: Some("core::fmt::write".to_string()),
+                rva: 0x1000,
+                score: 78,
+                method: Method::Call {
+                    called_from: 0x2000,
+                },
+                origin: None,
+                alternatives: vec![],
+            },
+        ];
+
+        Results::new(
+            Sample {
+                file_name: "sample.exe".to_string(),
+                sha256: hex::encode(Sha256::digest(b"sample")),
+                image_base: 0x1_4000_0000,
+                target: Some("x86_64-pc-windows-msvc".to_string()),
+                functions: 4242,
+            },
+            Some(Rustc {
+                version: "1.72.0".to_string(),
+                commit_hash: "5680fa18feaa87f3ff04063800aec256c3d4b4be".to_string(),
+            }),
+            vec![DetectedCrate {
+                name: "regex".to_string(),
+                version: "1.9.4".to_string(),
+                features: vec!["std".to_string()],
+                evidence: vec![],
+            }],
+            Algorithm::Ssdeep,
+            25,
+            symbols,
+        )
+    }
+
+    #[test]
+    fn results_round_trip() {
+        let results = results();
+        let json = serde_json::to_vec(&results).unwrap();
+        let parsed = Results::from_slice(&json).unwrap();
+
+        assert_eq!(parsed.format_version, RESULTS_FORMAT_VERSION);
+        assert_eq!(
+            parsed.symbols.iter().map(|s| s.rva).collect::<Vec<u32>>(),
+            [0x1000, 0x2000]
+        );
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            serde_json::to_value(&results).unwrap()
+        );
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut json = serde_json::to_value(results()).unwrap();
+        json["format_version"] = (RESULTS_FORMAT_VERSION + 1).into();
+        let error = Results::from_slice(&serde_json::to_vec(&json).unwrap()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("not supported"), "{}", error);
+
+        json.as_object_mut().unwrap().remove("format_version");
+        let error = Results::from_slice(&serde_json::to_vec(&json).unwrap()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("versioned"), "{}", error);
+
+        assert!(Results::from_slice(b"[]").is_err());
+        assert!(Results::from_slice(b"not json").is_err());
+    }
+
+    #[test]
+    fn incomplete_results_are_rejected() {
+        let mut json = serde_json::to_value(results()).unwrap();
+        json.as_object_mut().unwrap().remove("symbols");
+        assert!(Results::from_slice(&serde_json::to_vec(&json).unwrap()).is_err());
+    }
+}
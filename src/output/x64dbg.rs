@@ -0,0 +1,38 @@
+use serde::Serialize;
+
+use crate::output::results::Sample;
+use crate::output::{unique_names, ExportedSymbol};
+
+#[derive(Serialize)]
+struct Label<'a> {
+    module: &'a str,
+    /// Relative to the module, so labels survive ASLR.
+    address: String,
+    manual: bool,
+    text: String,
+}
+
+#[derive(Serialize)]
+struct Database<'a> {
+    labels: Vec<Label<'a>>,
+}
+
+/// x64dbg database (`.dd64`) holding a label per symbol. x64dbg resolves labels to a single
+/// address, so names are made unique.
+pub fn database(sample: &Sample, symbols: &[ExportedSymbol]) -> String {
+    let names = unique_names(symbols, |symbol| symbol.name.clone());
+    let database = Database {
+        labels: symbols
+            .iter()
+            .zip(names)
+            .map(|(symbol, text)| Label {
+                module: &sample.file_name,
+                address: format!("0x{:x}", symbol.rva),
+                manual: true,
+                text,
+            })
+            .collect(),
+    };
+
+    serde_json::to_string_pretty(&database).unwrap()
+}
@@ -0,0 +1,133 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::sig::comparaison::{Alternative, Symbol};
+
+/// Picks at most one name per address, and gives each library function to at most one address.
+///
+/// Matches are assigned greedily, best score first: when a library function matches several
+/// addresses, it goes to the one it resembles the most and the others fall back to their next best
+/// name. Ties are broken by address then name so the result does not depend on the input order.
+/// Up to `top_k` other names that matched each address are kept as alternatives.
+pub fn assign(candidates: Vec<Symbol>, top_k: usize) -> Vec<Symbol> {
+    let mut by_rva = BTreeMap::<u32, Vec<Symbol>>::new();
+    for symbol in candidates {
+        by_rva.entry(symbol.rva).or_default().push(symbol);
+    }
+    for symbols in by_rva.values_mut() {
+        symbols.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+    }
+
+    let mut pairs: Vec<(u32, u32, &str)> = by_rva
+        .values()
+        .flatten()
+        .map(|s| (s.score, s.rva, s.name.as_str()))
+        .collect();
+    pairs.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(b.2)));
+
+    let mut assigned_names = HashSet::<&str>::new();
+    let mut assigned = BTreeMap::<u32, &str>::new();
+    for (_, rva, name) in pairs {
+        if assigned.contains_key(&rva) || assigned_names.contains(name) {
+            continue;
+        }
+        assigned_names.insert(name);
+        assigned.insert(rva, name);
+    }
+
+    let mut result = vec![];
+    for (rva, name) in assigned {
+        let symbols = &by_rva[&rva];
+        let mut symbol = match symbols.iter().find(|s| s.name == name) {
+            Some(symbol) => symbol.clone(),
+            None => continue,
+        };
+        symbol.alternatives = symbols
+            .iter()
+            .filter(|s| s.name != name)
+            .take(top_k)
+            .map(|s| Alternative {
+                name: s.name.clone(),
+                score: s.score,
+                origin: s.origin.clone(),
+            })
+            .collect();
+        result.push(symbol);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sig::comparaison::Method;
+
+    fn candidate(rva: u32, name: &str, score: u32) -> Symbol {
+        Symbol {
+            name: name.to_string(),
+            rva,
+            score,
+            method: Method::Pattern,
+            origin: None,
+            demangled: None,
+            alternatives: vec![],
+        }
+    }
+
+    /// (rva, name, alternative names) of the assigned symbols.
+    fn assigned(symbols: &[Symbol]) -> Vec<(u32, &str, Vec<&str>)> {
+        symbols
+            .iter()
+            .map(|s| {
+                let alternatives = s.alternatives.iter().map(|a| a.name.as_str()).collect();
+                (s.rva, s.name.as_str(), alternatives)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn names_go_to_the_best_address() {
+        let candidates = vec![
+            candidate(0x2000, "foo", 80),
+            candidate(0x2000, "bar", 70),
+            candidate(0x1000, "foo", 90),
+            candidate(0x3000, "bar", 60),
+        ];
+        let symbols = assign(candidates, 5);
+        assert_eq!(
+            assigned(&symbols),
+            [(0x1000, "foo", vec![]), (0x2000, "bar", vec!["foo"])]
+        );
+        assert_eq!(symbols[1].score, 70);
+        assert_eq!(symbols[1].alternatives[0].score, 80);
+    }
+
+    #[test]
+    fn alternatives_are_the_top_k_other_names() {
+        let candidates = vec![
+            candidate(0x1000, "d", 40),
+            candidate(0x1000, "a", 90),
+            candidate(0x1000, "c", 60),
+            candidate(0x1000, "b", 70),
+        ];
+        assert_eq!(
+            assigned(&assign(candidates.clone(), 2)),
+            [(0x1000, "a", vec!["b", "c"])]
+        );
+        assert_eq!(assigned(&assign(candidates, 0)), [(0x1000, "a", vec![])]);
+    }
+
+    #[test]
+    fn ties_do_not_depend_on_input_order() {
+        let mut candidates = vec![
+            candidate(0x2000, "foo", 50),
+            candidate(0x1000, "foo", 50),
+            candidate(0x1000, "bar", 50),
+            candidate(0x2000, "baz", 50),
+        ];
+        let expected = [(0x1000, "bar", vec!["foo"]), (0x2000, "baz", vec!["foo"])];
+        assert_eq!(assigned(&assign(candidates.clone(), 5)), expected);
+        candidates.reverse();
+        assert_eq!(assigned(&assign(candidates, 5)), expected);
+    }
+}
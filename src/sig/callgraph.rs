@@ -0,0 +1,339 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use log::debug;
+use rayon::prelude::*;
+
+use crate::functions_utils::search::Function;
+use crate::sig::comparaison::{Method, Symbol};
+use crate::sig::database::SignatureSet;
+use crate::sig::sig_generation::disassemble_func;
+
+/// Functions called by each target function, by RVA. One entry per call instruction, `None` for
+/// indirect calls.
+#[derive(Default)]
+pub struct TargetCallGraph {
+    calls: HashMap<u32, Vec<Option<u32>>>,
+}
+
+impl TargetCallGraph {
+    pub fn new(functions: &[Function]) -> TargetCallGraph {
+        let calls = functions
+            .par_iter()
+            .map(|f| {
+                let (_, call_sites) = disassemble_func(f.data, f.rva as u64, false);
+                let callees = call_sites
+                    .iter()
+                    .map(|call| call.target.and_then(|target| u32::try_from(target).ok()))
+                    .collect();
+                (f.rva, callees)
+            })
+            .collect();
+
+        TargetCallGraph { calls }
+    }
+}
+
+/// Functions called by each library function, by name. A name may have been built several times,
+/// with different call lists.
+#[derive(Default)]
+pub struct LibraryCallGraph {
+    calls: HashMap<String, Vec<Vec<Option<String>>>>,
+}
+
+impl LibraryCallGraph {
+    pub fn add(&mut self, set: &SignatureSet) {
+        for sig in set.signatures.iter().filter(|sig| !sig.calls.is_empty()) {
+            let calls = self.calls.entry(sig.name.clone()).or_default();
+            if !calls.contains(&sig.calls) {
+                calls.push(sig.calls.clone());
+            }
+        }
+    }
+}
+
+/// Weight of the score of a caller given to the callees named from its calls, so that names
+/// inferred further from a direct match are less trusted.
+pub const CALL_WEIGHT: f64 = 0.9;
+
+/// Call sites of a matched function in the target and in the library.
+struct AlignedCalls<'a> {
+    target: &'a [Option<u32>],
+    library: &'a [Option<String>],
+    /// Callees already named like the library function's callees.
+    agree: usize,
+    /// Callees already named differently.
+    disagree: usize,
+}
+
+impl AlignedCalls<'_> {
+    fn balance(&self) -> isize {
+        self.agree as isize - self.disagree as isize
+    }
+}
+
+/// Calls of `symbol` in the target, paired with the calls of the library function it was matched
+/// with. Returns `None` when no build of the library function makes as many calls, since call
+/// sites can then not be lined up.
+fn aligned_calls<'a>(
+    symbol: &Symbol,
+    named: &BTreeMap<u32, Symbol>,
+    target: &'a TargetCallGraph,
+    library: &'a LibraryCallGraph,
+) -> Option<AlignedCalls<'a>> {
+    let target_calls = target.calls.get(&symbol.rva)?;
+
+    let mut best = None;
+    for library_calls in library.calls.get(&symbol.name)? {
+        if library_calls.len() != target_calls.len() {
+            continue;
+        }
+        let (mut agree, mut disagree) = (0, 0);
+        for (callee, name) in target_calls.iter().zip(library_calls) {
+            if let (Some(callee), Some(name)) = (callee.and_then(|c| named.get(&c)), name) {
+                if &callee.name == name {
+                    agree += 1;
+                } else {
+                    disagree += 1;
+                }
+            }
+        }
+        let aligned = AlignedCalls {
+            target: target_calls,
+            library: library_calls,
+            agree,
+            disagree,
+        };
+        if best
+            .as_ref()
+            .is_none_or(|b: &AlignedCalls| aligned.balance() > b.balance())
+        {
+            best = Some(aligned);
+        }
+    }
+
+    best
+}
+
+/// Uses calls to check and extend `symbols`: if target function `A` was named `foo` and `foo`
+/// calls `bar` at its third call site, `A`'s third callee is named `bar`, with `A`'s score weighted
+/// by `CALL_WEIGHT`.
+///
+/// Matches whose callees were mostly given other names than the ones their library function calls
+/// are dropped first. Names are then propagated from callers to callees until nothing changes, each
+/// library name being given to at most one address, as long as their weighted score exceeds
+/// `threshold`: names stop spreading once they are too far from a direct match.
+pub fn propagate(
+    symbols: Vec<Symbol>,
+    target: &TargetCallGraph,
+    library: &LibraryCallGraph,
+    threshold: u32,
+) -> Vec<Symbol> {
+    let mut named: BTreeMap<u32, Symbol> = symbols.into_iter().map(|s| (s.rva, s)).collect();
+
+    let rejected: Vec<u32> = named
+        .values()
+        .filter(|s| {
+            aligned_calls(s, &named, target, library)
+                .is_some_and(|aligned| aligned.disagree > aligned.agree)
+        })
+        .map(|s| s.rva)
+        .collect();
+    for rva in rejected {
+        debug!("Calls of {:x} contradict {}", rva, named[&rva].name);
+        named.remove(&rva);
+    }
+
+    let mut used_names: HashSet<String> = named.values().map(|s| s.name.clone()).collect();
+    let mut queue: VecDeque<u32> = named.keys().copied().collect();
+    let mut propagated = 0;
+    while let Some(rva) = queue.pop_front() {
+        let caller = named[&rva].clone();
+        let calls = match aligned_calls(&caller, &named, target, library) {
+            Some(calls) => calls,
+            None => continue,
+        };
+
+        for (callee, name) in calls.target.iter().zip(calls.library) {
+            if let (Some(callee), Some(name)) = (callee, name) {
+                let score = (caller.score as f64 * CALL_WEIGHT).round() as u32;
+                if named.contains_key(callee) || used_names.contains(name) || score <= threshold {
+                    continue;
+                }
+                used_names.insert(name.clone());
+                named.insert(
+                    *callee,
+                    Symbol {
+                        name: name.clone(),
+                        rva: *callee,
+                        score,
+                        method: Method::Call { called_from: rva },
+                        // The build the call was seen in
+                        origin: caller.origin.clone(),
+                        demangled: None,
+                        alternatives: vec![],
+                    },
+                );
+                queue.push_back(*callee);
+                propagated += 1;
+            }
+        }
+    }
+    debug!("{} names propagated through calls", propagated);
+
+    named.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(rva: u32, name: &str, score: u32) -> Symbol {
+        Symbol {
+            name: name.to_string(),
+            rva,
+            score,
+            method: Method::Pattern,
+            origin: None,
+            demangled: None,
+            alternatives: vec![],
+        }
+    }
+
+    fn target(calls: &[(u32, &[Option<u32>])]) -> TargetCallGraph {
+        TargetCallGraph {
+            calls: calls.iter().map(|(rva, c)| (*rva, c.to_vec())).collect(),
+        }
+    }
+
+    fn library(calls: &[(&str, &[Option<&str>])]) -> LibraryCallGraph {
+        let mut graph = LibraryCallGraph::default();
+        for (name, c) in calls {
+            graph
+                .calls
+                .entry(name.to_string())
+                .or_default()
+                .push(c.iter().map(|c| c.map(str::to_string)).collect());
+        }
+        graph
+    }
+
+    fn named(symbols: &[Symbol]) -> BTreeMap<u32, Symbol> {
+        symbols.iter().map(|s| (s.rva, s.clone())).collect()
+    }
+
+    #[test]
+    fn calls_are_aligned_with_the_closest_build() {
+        let target = target(&[(0x1000, &[Some(0x2000), Some(0x3000)])]);
+        let library = library(&[
+            ("a", &[Some("b"), Some("c")]),
+            ("a", &[Some("c"), Some("b")]),
+            ("a", &[Some("b"), Some("c"), Some("d")]),
+        ]);
+        let a = symbol(0x1000, "a", 100);
+        let named = named(&[
+            a.clone(),
+            symbol(0x2000, "c", 100),
+            symbol(0x3000, "b", 100),
+        ]);
+
+        let aligned = aligned_calls(&a, &named, &target, &library).unwrap();
+        assert_eq!(
+            aligned.library,
+            [Some("c".to_string()), Some("b".to_string())]
+        );
+        assert_eq!((aligned.agree, aligned.disagree), (2, 0));
+
+        // No build makes as many calls
+        let target = self::target(&[(0x1000, &[Some(0x2000)])]);
+        assert!(aligned_calls(&a, &named, &target, &library).is_none());
+    }
+
+    #[test]
+    fn names_are_propagated_with_a_lower_score() {
+        let target = target(&[
+            (0x1000, &[Some(0x2000), None, Some(0x3000)]),
+            (0x3000, &[Some(0x4000)]),
+        ]);
+        let library = library(&[
+            ("a", &[Some("b"), Some("x"), Some("c")]),
+            ("c", &[Some("d")]),
+        ]);
+
+        let symbols = propagate(vec![symbol(0x1000, "a", 100)], &target, &library, 25);
+        let found: Vec<(u32, &str, u32, Method)> = symbols
+            .iter()
+            .map(|s| (s.rva, s.name.as_str(), s.score, s.method))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (0x1000, "a", 100, Method::Pattern),
+                (
+                    0x2000,
+                    "b",
+                    90,
+                    Method::Call {
+                        called_from: 0x1000
+                    }
+                ),
+                (
+                    0x3000,
+                    "c",
+                    90,
+                    Method::Call {
+                        called_from: 0x1000
+                    }
+                ),
+                (
+                    0x4000,
+                    "d",
+                    81,
+                    Method::Call {
+                        called_from: 0x3000
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn propagation_stops_at_the_threshold() {
+        let target = target(&[(0x1000, &[Some(0x2000)]), (0x2000, &[Some(0x3000)])]);
+        let library = library(&[("a", &[Some("b")]), ("b", &[Some("c")])]);
+
+        // 90 for `b`, 81 for `c`
+        let names: Vec<String> = propagate(vec![symbol(0x1000, "a", 100)], &target, &library, 85)
+            .into_iter()
+            .map(|s| s.name)
+            .collect();
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    #[test]
+    fn contradicted_matches_are_dropped() {
+        let target = target(&[(0x1000, &[Some(0x2000), Some(0x3000), Some(0x4000)])]);
+        let library = library(&[("a", &[Some("b"), Some("c"), Some("d")])]);
+        let symbols = vec![
+            symbol(0x1000, "a", 100),
+            symbol(0x2000, "x", 100),
+            symbol(0x3000, "y", 100),
+        ];
+
+        // Two callees named differently than `a`'s, none alike: `a` is dropped, and nothing is
+        // propagated from it
+        let names: Vec<String> = propagate(symbols.clone(), &target, &library, 25)
+            .into_iter()
+            .map(|s| s.name)
+            .collect();
+        assert_eq!(names, ["x", "y"]);
+
+        // As many agreeing callees as disagreeing ones: `a` is kept
+        let mut symbols = symbols;
+        symbols[2].name = "c".to_string();
+        let names: Vec<String> = propagate(symbols, &target, &library, 25)
+            .into_iter()
+            .map(|s| s.name)
+            .collect();
+        assert_eq!(names, ["a", "x", "c", "d"]);
+    }
+}
@@ -0,0 +1,436 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+use iced_x86::{Decoder, DecoderOptions, FlowControl, Instruction, Mnemonic, OpKind};
+
+use crate::sig::sig_generation::HashFn;
+
+/// Number of Weisfeiler-Lehman refinements of block labels.
+const WL_ITERATIONS: usize = 2;
+
+/// Coarse instruction classes, so that the histogram does not depend on the exact instructions
+/// picked by the compiler.
+#[derive(Clone, Copy)]
+enum InstrClass {
+    Move,
+    Arithmetic,
+    Logic,
+    Compare,
+    Stack,
+    Branch,
+    Call,
+    Return,
+    Vector,
+    Other,
+}
+
+const CLASS_COUNT: usize = 10;
+
+fn classify(instruction: &Instruction) -> InstrClass {
+    match instruction.flow_control() {
+        FlowControl::Call | FlowControl::IndirectCall => return InstrClass::Call,
+        FlowControl::Return => return InstrClass::Return,
+        FlowControl::ConditionalBranch
+        | FlowControl::UnconditionalBranch
+        | FlowControl::IndirectBranch => return InstrClass::Branch,
+        _ => {}
+    }
+    let vector = (0..instruction.op_count()).any(|i| {
+        instruction.op_kind(i) == OpKind::Register && {
+            let register = instruction.op_register(i);
+            register.is_xmm() || register.is_ymm() || register.is_zmm()
+        }
+    });
+    if vector {
+        return InstrClass::Vector;
+    }
+
+    match instruction.mnemonic() {
+        Mnemonic::Mov
+        | Mnemonic::Movzx
+        | Mnemonic::Movsx
+        | Mnemonic::Movsxd
+        | Mnemonic::Lea
+        | Mnemonic::Xchg
+        | Mnemonic::Cmovo
+        | Mnemonic::Cmovno
+        | Mnemonic::Cmovb
+        | Mnemonic::Cmovae
+        | Mnemonic::Cmove
+        | Mnemonic::Cmovne
+        | Mnemonic::Cmovbe
+        | Mnemonic::Cmova
+        | Mnemonic::Cmovs
+        | Mnemonic::Cmovns
+        | Mnemonic::Cmovp
+        | Mnemonic::Cmovnp
+        | Mnemonic::Cmovl
+        | Mnemonic::Cmovge
+        | Mnemonic::Cmovle
+        | Mnemonic::Cmovg => InstrClass::Move,
+        Mnemonic::Add
+        | Mnemonic::Adc
+        | Mnemonic::Sub
+        | Mnemonic::Sbb
+        | Mnemonic::Inc
+        | Mnemonic::Dec
+        | Mnemonic::Neg
+        | Mnemonic::Mul
+        | Mnemonic::Imul
+        | Mnemonic::Div
+        | Mnemonic::Idiv => InstrClass::Arithmetic,
+        Mnemonic::And
+        | Mnemonic::Or
+        | Mnemonic::Xor
+        | Mnemonic::Not
+        | Mnemonic::Shl
+        | Mnemonic::Shr
+        | Mnemonic::Sar
+        | Mnemonic::Rol
+        | Mnemonic::Ror
+        | Mnemonic::Bt
+        | Mnemonic::Bts
+        | Mnemonic::Btr => InstrClass::Logic,
+        Mnemonic::Cmp | Mnemonic::Test => InstrClass::Compare,
+        Mnemonic::Push | Mnemonic::Pop => InstrClass::Stack,
+        _ => InstrClass::Other,
+    }
+}
+
+/// FNV-1a, whose output does not change across Rust versions, unlike `DefaultHasher`.
+fn fnv1a(values: impl IntoIterator<Item = u32>) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for value in values {
+        for b in value.to_le_bytes() {
+            hash ^= b as u32;
+            hash = hash.wrapping_mul(0x01000193);
+        }
+    }
+
+    hash
+}
+
+/// Instructions of `bytes`, up to two consecutive `ud2` or `int3` like `hash_single_func`.
+fn decode(bytes: &[u8]) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    let mut fn_end = 0;
+    for instruction in Decoder::new(64, bytes, DecoderOptions::NONE).iter() {
+        match instruction.mnemonic() {
+            Mnemonic::Ud2 | Mnemonic::Int3 => fn_end += 1,
+            _ => fn_end = 0,
+        }
+        if fn_end == 2 {
+            instructions.pop();
+            break;
+        }
+        instructions.push(instruction);
+    }
+
+    instructions
+}
+
+/// Structural summary of a function's control-flow graph. It does not depend on register
+/// allocation nor on code layout, unlike ssdeep.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CfgHash {
+    pub blocks: u32,
+    pub edges: u32,
+    /// Number of instructions of each class.
+    pub classes: [u32; CLASS_COUNT],
+    /// Hash of the mnemonics of each basic block, sorted.
+    pub block_hashes: Vec<u32>,
+    /// Weisfeiler-Lehman labels of every block after each iteration, sorted.
+    pub graph_hashes: Vec<u32>,
+}
+
+impl CfgHash {
+    pub fn new(bytes: &[u8]) -> CfgHash {
+        let instructions = decode(bytes);
+        let end = instructions.last().map(|i| i.next_ip()).unwrap_or(0);
+
+        let mut leaders = BTreeSet::from([0]);
+        for instruction in &instructions {
+            match instruction.flow_control() {
+                FlowControl::Next | FlowControl::Call | FlowControl::IndirectCall => {}
+                _ => {
+                    leaders.insert(instruction.next_ip());
+                    if instruction.op0_kind() == OpKind::NearBranch64 {
+                        leaders.insert(instruction.near_branch_target());
+                    }
+                }
+            }
+        }
+
+        // Split instructions in blocks, leaders in the middle of an instruction are ignored
+        let mut blocks: Vec<Vec<&Instruction>> = vec![];
+        let mut block_of = HashMap::<u64, usize>::new();
+        for instruction in &instructions {
+            if blocks.is_empty() || leaders.contains(&instruction.ip()) {
+                block_of.insert(instruction.ip(), blocks.len());
+                blocks.push(vec![]);
+            }
+            if let Some(block) = blocks.last_mut() {
+                block.push(instruction);
+            }
+        }
+
+        let mut successors: Vec<Vec<usize>> = vec![vec![]; blocks.len()];
+        for (i, block) in blocks.iter().enumerate() {
+            let last = match block.last() {
+                Some(last) => last,
+                None => continue,
+            };
+            let fallthrough = Some(i + 1).filter(|next| *next < blocks.len());
+            let target = match last.op0_kind() {
+                OpKind::NearBranch64 if last.near_branch_target() < end => {
+                    block_of.get(&last.near_branch_target()).copied()
+                }
+                _ => None,
+            };
+            let next: Vec<usize> = match last.flow_control() {
+                FlowControl::ConditionalBranch => target.into_iter().chain(fallthrough).collect(),
+                FlowControl::UnconditionalBranch => target.into_iter().collect(),
+                FlowControl::IndirectBranch
+                | FlowControl::Return
+                | FlowControl::Exception
+                | FlowControl::Interrupt => vec![],
+                _ => fallthrough.into_iter().collect(),
+            };
+            successors[i] = next;
+            successors[i].sort();
+            successors[i].dedup();
+        }
+        let mut predecessors: Vec<Vec<usize>> = vec![vec![]; blocks.len()];
+        for (i, next) in successors.iter().enumerate() {
+            for n in next {
+                predecessors[*n].push(i);
+            }
+        }
+
+        let mut classes = [0; CLASS_COUNT];
+        for instruction in &instructions {
+            classes[classify(instruction) as usize] += 1;
+        }
+
+        let mut labels: Vec<u32> = blocks
+            .iter()
+            .map(|block| fnv1a(block.iter().map(|i| i.mnemonic() as u32)))
+            .collect();
+        let mut block_hashes = labels.clone();
+        block_hashes.sort();
+
+        let mut graph_hashes = vec![];
+        for _ in 0..WL_ITERATIONS {
+            labels = (0..blocks.len())
+                .map(|i| {
+                    let mut next: Vec<u32> = successors[i].iter().map(|n| labels[*n]).collect();
+                    let mut previous: Vec<u32> =
+                        predecessors[i].iter().map(|p| labels[*p]).collect();
+                    next.sort();
+                    previous.sort();
+                    fnv1a(
+                        [labels[i]]
+                            .into_iter()
+                            .chain(next)
+                            .chain([u32::MAX])
+                            .chain(previous),
+                    )
+                })
+                .collect();
+            graph_hashes.extend(&labels);
+        }
+        graph_hashes.sort();
+
+        CfgHash {
+            blocks: blocks.len() as u32,
+            edges: successors.iter().map(|s| s.len() as u32).sum(),
+            classes,
+            block_hashes,
+            graph_hashes,
+        }
+    }
+
+    /// Similarity with `other`, from 0 to 100.
+    pub fn similarity(&self, other: &CfgHash) -> u32 {
+        let size = (ratio(self.blocks, other.blocks) + ratio(self.edges, other.edges)) / 2.0;
+        let (min, max) = self
+            .classes
+            .iter()
+            .zip(&other.classes)
+            .fold((0, 0), |(min, max), (a, b)| {
+                (min + a.min(b), max + a.max(b))
+            });
+        let classes = ratio(min, max);
+        let blocks = jaccard(&self.block_hashes, &other.block_hashes);
+        let graph = jaccard(&self.graph_hashes, &other.graph_hashes);
+
+        (100.0 * (0.1 * size + 0.2 * classes + 0.3 * blocks + 0.4 * graph)).round() as u32
+    }
+}
+
+fn ratio(a: u32, b: u32) -> f64 {
+    match a.max(b) {
+        0 => 1.0,
+        max => a.min(b) as f64 / max as f64,
+    }
+}
+
+/// Jaccard index of two sorted multisets.
+fn jaccard(a: &[u32], b: &[u32]) -> f64 {
+    let (mut i, mut j, mut common) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                common += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+
+    ratio(common, (a.len() + b.len() - common as usize) as u32)
+}
+
+impl HashFn<CfgHash> for CfgHash {
+    fn hash(bytes: &Vec<u8>) -> CfgHash {
+        CfgHash::new(bytes)
+    }
+
+    fn compare_hash(&self, with: &impl HashFn<CfgHash>) -> i32 {
+        self.similarity(with.get_hash()) as i32
+    }
+
+    fn get_hash(&self) -> &CfgHash {
+        self
+    }
+}
+
+fn join(values: &[u32]) -> String {
+    values
+        .iter()
+        .map(|v| format!("{:x}", v))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn split(s: &str) -> Result<Vec<u32>, String> {
+    s.split(',')
+        .filter(|v| !v.is_empty())
+        .map(|v| u32::from_str_radix(v, 16).map_err(|e| format!("Invalid value {:?}: {}", v, e)))
+        .collect()
+}
+
+/// `blocks:edges:classes:block hashes:graph hashes`, lists being comma separated and in hex.
+impl fmt::Display for CfgHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:x}:{:x}:{}:{}:{}",
+            self.blocks,
+            self.edges,
+            join(&self.classes),
+            join(&self.block_hashes),
+            join(&self.graph_hashes)
+        )
+    }
+}
+
+impl FromStr for CfgHash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() != 5 {
+            return Err(format!("Invalid CFG hash {:?}", s));
+        }
+        let count = |v: &str| {
+            u32::from_str_radix(v, 16).map_err(|e| format!("Invalid count {:?}: {}", v, e))
+        };
+        let classes = split(parts[2])?
+            .try_into()
+            .map_err(|_| format!("Invalid instruction classes {:?}", parts[2]))?;
+
+        Ok(CfgHash {
+            blocks: count(parts[0])?,
+            edges: count(parts[1])?,
+            classes,
+            block_hashes: split(parts[3])?,
+            graph_hashes: split(parts[4])?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `while rcx != 0 { rax += rcx; rcx -= 1 }`
+    const LOOP: [u8; 14] = [
+        0x48, 0x85, 0xc9, // test rcx, rcx
+        0x74, 0x08, // je ret
+        0x48, 0x01, 0xc8, // add rax, rcx
+        0x48, 0xff, 0xc9, // dec rcx
+        0x75, 0xf8, // jne add
+        0xc3, // ret
+    ];
+
+    /// `LOOP` with rdx and r8 instead of rcx and rax.
+    const RENAMED_LOOP: [u8; 14] = [
+        0x48, 0x85, 0xd2, // test rdx, rdx
+        0x74, 0x08, // je ret
+        0x49, 0x01, 0xd0, // add r8, rdx
+        0x48, 0xff, 0xca, // dec rdx
+        0x75, 0xf8, // jne add
+        0xc3, // ret
+    ];
+
+    /// Straight vector code.
+    const VECTOR: [u8; 10] = [
+        0x0f, 0x28, 0x01, // movaps xmm0, [rcx]
+        0x0f, 0x58, 0xc1, // addps xmm0, xmm1
+        0x0f, 0x29, 0x02, // movaps [rdx], xmm0
+        0xc3, // ret
+    ];
+
+    #[test]
+    fn blocks_and_edges_are_found() {
+        let hash = CfgHash::new(&LOOP);
+        assert_eq!(hash.blocks, 3);
+        // Both conditional branches have two successors, ret none
+        assert_eq!(hash.edges, 4);
+        assert_eq!(hash.graph_hashes.len(), 3 * WL_ITERATIONS);
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for bytes in [&LOOP[..], &VECTOR, &[]] {
+            let hash = CfgHash::new(bytes);
+            assert_eq!(hash.to_string().parse::<CfgHash>().unwrap(), hash);
+        }
+        assert!("3:4".parse::<CfgHash>().is_err());
+        assert!("3:4:1,2:0:0".parse::<CfgHash>().is_err());
+        assert!("3:4:1,2,3,4,5,6,7,8,9,a:zz:0".parse::<CfgHash>().is_err());
+    }
+
+    #[test]
+    fn similarity_of_identical_and_unrelated_functions() {
+        let hash = CfgHash::new(&LOOP);
+        assert_eq!(hash.similarity(&hash), 100);
+
+        let unrelated = CfgHash::new(&VECTOR);
+        assert!(hash.similarity(&unrelated) < 25);
+        assert_eq!(hash.similarity(&unrelated), unrelated.similarity(&hash));
+    }
+
+    #[test]
+    fn register_renaming_does_not_change_the_hash() {
+        assert_ne!(LOOP, RENAMED_LOOP);
+        let hash = CfgHash::new(&LOOP);
+        let renamed = CfgHash::new(&RENAMED_LOOP);
+        assert_eq!(renamed, hash);
+        assert_eq!(renamed.similarity(&hash), 100);
+    }
+}
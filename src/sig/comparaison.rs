@@ -1,59 +1,160 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
-use fuzzyhash::FuzzyHash;
 use log::{debug, error, info, log_enabled, Level};
 use serde::{Deserialize, Serialize};
 
-use super::sig_generation::FuzzyFunc;
+use super::database::SignatureKey;
+use super::index::SignatureIndex;
+use super::sig_generation::{Algorithm, FuzzyFunc};
+use crate::compilation::fallback::Fallback;
 use crate::{functions_utils::search::Function, sig::sig_generation::hash_functions};
 use indicatif::ProgressBar;
+use rayon::prelude::*;
 
-#[derive(Serialize, Deserialize)]
+/// Score a match must exceed, unless configured otherwise.
+pub const DEFAULT_THRESHOLD: u32 = 25;
+
+/// Library build a name comes from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    #[serde(rename = "crate")]
+    pub krate: String,
+    pub version: String,
+    pub profile: String,
+    /// Strategy that was needed to build the crate, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<Fallback>,
+}
+
+impl Origin {
+    pub fn new(key: &SignatureKey, fallback: Option<Fallback>) -> Origin {
+        Origin {
+            krate: key.krate.clone(),
+            version: key.version.clone(),
+            profile: key.profile.clone(),
+            fallback,
+        }
+    }
+
+    /// Scores of names coming from builds that needed a fallback are lowered, they are less
+    /// trustworthy.
+    pub fn weigh(origin: Option<&Origin>, score: u32) -> u32 {
+        match origin.and_then(|o| o.fallback) {
+            Some(fallback) => (score as f64 * fallback.weight()).round() as u32,
+            None => score,
+        }
+    }
+}
+
+/// How a name was found.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Method {
+    /// Hashes of the target and library functions compared with `algorithm`.
+    Hash { algorithm: Algorithm },
+    /// FLIRT-style pattern of a single library function, see `pattern::Pattern`.
+    Pattern,
+    /// Callee of the function at `called_from`, named beforehand, at the same call site as in the
+    /// library.
+    Call { called_from: u32 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Symbol {
-    name: String,
+    /// Mangled, as in the library.
+    pub name: String,
+    /// `name` demangled in the style picked for the output, absent when names are left mangled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub demangled: Option<String>,
     // pa: u32,
-    rva: u32,
-    score: u32,
+    pub rva: u32,
+    /// From 0 to 100.
+    pub score: u32,
+    pub method: Method,
+    /// Absent for signatures that do not come from a known build.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+    /// Other names that matched this address, best first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<Alternative>,
 }
 
-fn compare_sigs(from: &Vec<FuzzyFunc>, with: &HashMap<String, String>) -> Vec<Symbol> {
-    let mut symbols = vec![];
-    // env_logger::init();
-    let bar = ProgressBar::new(from.len() as u64);
-    for f in from {
-        bar.inc(1);
-
-        for (f_name, hash) in with {
-            // if let Some(function_name) = &f_dll.name {
-            // if f_dll.hash.hash.to_string().len() < 10 {
-            // println!("PA {:08x}  - {} {}", f.pa, function_name, f_dll.hash.hash.diff(&f.hash.hash, false));
-
-            if let Ok(val) = FuzzyHash::compare(hash.to_owned(), f.hash.hash.to_string()) {
-                if val > 25 {
-                    debug!(
-                        "RVA {:08x} - val {} - {} ({} {})",
-                        f.rva, val, &f_name, &f.hash.hash, &hash
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Alternative {
+    pub name: String,
+    pub score: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+}
+
+/// Best match for `f` among the `candidates` of `with`, one per library function name.
+fn best_matches(
+    f: &FuzzyFunc,
+    with: &[FuzzyFunc],
+    candidates: Vec<usize>,
+    threshold: u32,
+) -> Vec<Symbol> {
+    // The same function may be known under several build profiles, keep the best one.
+    let mut best_matches = BTreeMap::<&String, Symbol>::new();
+
+    for lib_f in candidates.into_iter().map(|i| &with[i]) {
+        let f_name = match &lib_f.name {
+            Some(name) => name,
+            None => continue,
+        };
+
+        if let Some(val) = f.hash.compare(&lib_f.hash) {
+            // Weighed first, the threshold applies to the reported score
+            let val = Origin::weigh(lib_f.origin.as_ref(), val);
+            if val > threshold {
+                debug!(
+                    "RVA {:08x} - val {} - {} ({} {})",
+                    f.rva, val, &f_name, &f.hash, &lib_f.hash
+                );
+                if best_matches.get(f_name).is_none_or(|s| s.score < val) {
+                    best_matches.insert(
+                        f_name,
+                        Symbol {
+                            name: f_name.clone(),
+                            // pa: f.pa,
+                            rva: f.rva,
+                            score: val,
+                            method: Method::Hash {
+                                algorithm: lib_f.hash.algorithm(),
+                            },
+                            origin: lib_f.origin.clone(),
+                            demangled: None,
+                            alternatives: vec![],
+                        },
                     );
-                    symbols.push(Symbol {
-                        name: f_name.clone(),
-                        // pa: f.pa,
-                        rva: f.rva,
-                        score: val,
-                    });
                 }
-            } else {
-                // println!("PA {:08x}  - {} - val {}", f.pa, function_name, "ERR COMPARE");
-                // break;
             }
-            // }
-            // }
         }
     }
 
+    best_matches.into_values().collect()
+}
+
+/// Target functions are matched in parallel, symbols are returned in the order of `from`.
+fn compare_sigs(from: &[FuzzyFunc], with: &[FuzzyFunc], threshold: u32) -> Vec<Symbol> {
+    let index = SignatureIndex::new(with);
+    let bar = ProgressBar::new(from.len() as u64);
+    let symbols: Vec<Vec<Symbol>> = from
+        .par_iter()
+        .map(|f| {
+            let symbols = best_matches(f, with, index.candidates(&f.hash), threshold);
+            bar.inc(1);
+            symbols
+        })
+        .collect();
+
     bar.finish();
-    symbols
+    symbols.into_iter().flatten().collect()
 }
 
-pub fn compare(from: &Vec<FuzzyFunc>, with: &HashMap<String, String>) -> Vec<Symbol> {
-    compare_sigs(&from, &with)
+/// Compares target functions `from` with named library functions `with`.
+/// Returns every pair scoring above `threshold`, use `assignment::assign` to pick one name per
+/// address.
+pub fn compare(from: &[FuzzyFunc], with: &[FuzzyFunc], threshold: u32) -> Vec<Symbol> {
+    compare_sigs(from, with, threshold)
 }
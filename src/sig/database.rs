@@ -0,0 +1,406 @@
+use fuzzyhash::FuzzyHash;
+use log::{debug, info};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+
+use crate::compilation::fallback::Fallback;
+use crate::functions_utils::search::Function;
+use crate::sig::comparaison::Origin;
+use crate::sig::pattern::Pattern;
+use crate::sig::sig_generation::{disassemble_func, Algorithm, FuzzyFunc, Hash, MIN_FUNC_SZ};
+use crate::sig::tlsh::TlshHash;
+use crate::ArianeError;
+
+/// Version of the on-disk format, bumped whenever a change would make older databases unreadable
+/// or their signatures incomparable (e.g. a change in how functions are normalized).
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// Everything that changes the code generated for a crate.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SignatureKey {
+    pub krate: String,
+    pub version: String,
+    /// Sorted, so the order features were inferred in does not matter.
+    pub features: Vec<String>,
+    pub rustc: String,
+    pub target: String,
+    pub profile: String,
+}
+
+impl SignatureKey {
+    pub fn new(
+        krate: &str,
+        version: &str,
+        features: &[String],
+        rustc: &str,
+        target: &str,
+        profile: &str,
+    ) -> SignatureKey {
+        let mut features = features.to_vec();
+        features.sort();
+        features.dedup();
+
+        SignatureKey {
+            krate: krate.to_string(),
+            version: version.to_string(),
+            features,
+            rustc: rustc.to_string(),
+            target: target.to_string(),
+            profile: profile.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SignatureKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{} [{}] rustc {} {} ({})",
+            self.krate,
+            self.version,
+            self.features.join(","),
+            self.rustc,
+            self.target,
+            self.profile
+        )
+    }
+}
+
+/// A named library function.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Signature {
+    pub name: String,
+    /// ssdeep hash of the normalized function.
+    pub hash: String,
+    /// Hashes computed with the other algorithms.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hashes: BTreeMap<Algorithm, String>,
+    /// Size of the function, in bytes.
+    pub size: usize,
+    /// Normalized bytes, hex encoded: relative operands are removed, calls and jumps are reduced
+    /// to their opcode.
+    pub masked: String,
+    /// FLIRT-style pattern of the raw bytes, see `Pattern`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// Functions called, one entry per call instruction. `None` when the callee is unknown, e.g.
+    /// for indirect calls.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub calls: Vec<Option<String>>,
+}
+
+impl Signature {
+    /// Returns `None` for unnamed functions, and functions too small to be told apart.
+    /// Functions too small to be hashed are kept if they have a pattern.
+    pub fn from_function(function: &Function) -> Option<Signature> {
+        let name = function.name.clone()?;
+        let (masked, call_sites) = disassemble_func(function.data, 0, false);
+        let pattern = Pattern::from_function(function).map(|p| p.to_string());
+        if masked.len() < MIN_FUNC_SZ.into() && pattern.is_none() {
+            return None;
+        }
+        // Calls through the GOT are indirect, but name their callee in a relocation as well
+        let calls = call_sites
+            .iter()
+            .map(|call| {
+                let i = function
+                    .relocations
+                    .binary_search_by_key(&call.offset, |(offset, _)| *offset)
+                    .ok()?;
+                Some(function.relocations[i].1.clone()).filter(|name| !name.is_empty())
+            })
+            .collect();
+
+        let hashes = [Algorithm::Cfg, Algorithm::Tlsh]
+            .into_iter()
+            .filter_map(|algorithm| {
+                let hash = Hash::new(algorithm, function.data, &masked)?;
+                Some((algorithm, hash.to_string()))
+            })
+            .collect();
+
+        Some(Signature {
+            name,
+            hash: FuzzyHash::new(&masked).to_string(),
+            hashes,
+            size: function.data.len(),
+            masked: hex::encode(&masked),
+            pattern,
+            calls,
+        })
+    }
+
+    /// Returns `None` for functions too small to be hashed.
+    pub fn hash(&self, algorithm: Algorithm) -> Option<Hash> {
+        if self.masked.len() / 2 < MIN_FUNC_SZ.into() {
+            return None;
+        }
+
+        match algorithm {
+            Algorithm::Ssdeep => Hash::parse(algorithm, &self.hash),
+            // Only needs the normalized bytes, so it can be computed for signatures stored without it
+            Algorithm::Tlsh if !self.hashes.contains_key(&algorithm) => {
+                let masked = hex::decode(&self.masked).ok()?;
+                TlshHash::new(&masked).map(Hash::Tlsh)
+            }
+            _ => Hash::parse(algorithm, self.hashes.get(&algorithm)?),
+        }
+    }
+}
+
+/// Signatures of one build of a crate.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignatureSet {
+    pub key: SignatureKey,
+    /// Strategy that was needed to build the crate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<Fallback>,
+    pub signatures: Vec<Signature>,
+}
+
+impl SignatureSet {
+    pub fn from_functions(
+        key: SignatureKey,
+        fallback: Option<Fallback>,
+        functions: &[Function],
+    ) -> SignatureSet {
+        SignatureSet {
+            key,
+            fallback: fallback.filter(|f| *f != Fallback::None),
+            signatures: functions
+                .par_iter()
+                .filter_map(Signature::from_function)
+                .collect(),
+        }
+    }
+
+    /// Signatures ready to be compared with target functions hashed with `algorithm`.
+    pub fn fuzzy_funcs(&self, algorithm: Algorithm) -> Vec<FuzzyFunc> {
+        self.signatures
+            .iter()
+            .filter_map(|sig| {
+                Some(FuzzyFunc {
+                    rva: 0,
+                    hash: sig.hash(algorithm)?,
+                    name: Some(sig.name.clone()),
+                    origin: Some(Origin::new(&self.key, self.fallback)),
+                })
+            })
+            .collect()
+    }
+}
+
+/// On-disk collection of signatures, so a crate built once is never built again.
+/// It is a single JSON file, that can be shared and merged.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignatureDatabase {
+    pub schema_version: u32,
+    pub sets: Vec<SignatureSet>,
+}
+
+impl Default for SignatureDatabase {
+    fn default() -> Self {
+        SignatureDatabase {
+            schema_version: SCHEMA_VERSION,
+            sets: vec![],
+        }
+    }
+}
+
+impl SignatureDatabase {
+    /// Opens the database at `path`, or returns an empty one if it does not exist yet.
+    pub fn open(path: &Path) -> Result<SignatureDatabase, ArianeError> {
+        if !path.exists() {
+            debug!("No signature database at {:?}, starting a new one", path);
+            return Ok(SignatureDatabase::default());
+        }
+
+        #[derive(Deserialize)]
+        struct Versioned {
+            schema_version: u32,
+        }
+
+        // Checked first, older databases may not even parse
+        let content = std::fs::read(path)?;
+        let schema_version = serde_json::from_slice::<Versioned>(&content)?.schema_version;
+        if schema_version != SCHEMA_VERSION {
+            return Err(ArianeError::Parse(format!(
+                "signature database schema version {}, expected {}",
+                schema_version, SCHEMA_VERSION
+            )));
+        }
+        let db: SignatureDatabase = serde_json::from_slice(&content)?;
+        info!("Loaded {} signature sets from {:?}", db.sets.len(), path);
+
+        Ok(db)
+    }
+
+    /// Writes the database to a temporary file first, so an interrupted save does not corrupt it.
+    pub fn save(&self, path: &Path) -> Result<(), ArianeError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp_path = path.with_extension("tmp");
+        let mut f = std::fs::File::create(&tmp_path)?;
+        f.write_all(serde_json::to_string(&self)?.as_bytes())?;
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, key: &SignatureKey) -> Option<&SignatureSet> {
+        self.sets.iter().find(|set| &set.key == key)
+    }
+
+    /// Adds the sets of `other` this database does not have yet. Returns how many were added.
+    pub fn merge(&mut self, other: SignatureDatabase) -> usize {
+        let mut added = 0;
+        for set in other.sets {
+            if self.get(&set.key).is_none() {
+                self.sets.push(set);
+                added += 1;
+            }
+        }
+
+        added
+    }
+
+    /// Adds `set`, replacing any set with the same key.
+    pub fn insert(&mut self, set: SignatureSet) {
+        match self.sets.iter_mut().find(|s| s.key == set.key) {
+            Some(existing) => *existing = set,
+            None => self.sets.push(set),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Empty directory for the files of `test`.
+    fn test_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ariane-db-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn set(krate: &str, profile: &str, names: &[&str]) -> SignatureSet {
+        SignatureSet {
+            key: SignatureKey::new(
+                krate,
+                "1.0.0",
+                &["std".to_string()],
+                "1.72.0",
+                "x86_64-unknown-linux-gnu",
+                profile,
+            ),
+            fallback: None,
+            signatures: names
+                .iter()
+                .map(|name| Signature {
+                    name: name.to_string(),
+                    hash: "3:abc:def".to_string(),
+                    hashes: BTreeMap::new(),
+                    size: 16,
+                    masked: "c3".to_string(),
+                    pattern: None,
+                    calls: vec![],
+                })
+                .collect(),
+        }
+    }
+
+    fn names(db: &SignatureDatabase) -> Vec<(String, Vec<String>)> {
+        db.sets
+            .iter()
+            .map(|set| {
+                (
+                    set.key.to_string(),
+                    set.signatures.iter().map(|s| s.name.clone()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn save_and_open_round_trip() {
+        let path = test_dir("round-trip").join("sigs.json");
+        assert!(SignatureDatabase::open(&path).unwrap().sets.is_empty());
+
+        let mut db = SignatureDatabase::default();
+        db.insert(set("regex", "opt-level=3", &["a", "b"]));
+        db.insert(set("regex", "opt-level=s", &["c"]));
+        db.save(&path).unwrap();
+
+        let opened = SignatureDatabase::open(&path).unwrap();
+        assert_eq!(names(&opened), names(&db));
+        assert!(opened.get(&db.sets[1].key).is_some());
+    }
+
+    #[test]
+    fn other_schema_versions_are_rejected() {
+        let path = test_dir("schema").join("sigs.json");
+        let content = format!(
+            r#"{{"schema_version": {}, "sets": [{{"krate": "regex"}}]}}"#,
+            SCHEMA_VERSION - 1
+        );
+        std::fs::write(&path, content).unwrap();
+
+        match SignatureDatabase::open(&path) {
+            Err(ArianeError::Parse(e)) => assert!(e.contains("schema version"), "{}", e),
+            other => panic!("{:?}", other.map(|db| db.schema_version)),
+        }
+    }
+
+    #[test]
+    fn merge_only_adds_missing_sets() {
+        let mut db = SignatureDatabase::default();
+        db.insert(set("regex", "opt-level=3", &["a"]));
+
+        let mut other = SignatureDatabase::default();
+        other.insert(set("regex", "opt-level=3", &["replaced"]));
+        other.insert(set("memchr", "opt-level=3", &["b"]));
+
+        assert_eq!(db.merge(other.clone()), 1);
+        assert_eq!(
+            names(&db),
+            [
+                (db.sets[0].key.to_string(), vec!["a".to_string()]),
+                (other.sets[1].key.to_string(), vec!["b".to_string()]),
+            ]
+        );
+        assert_eq!(db.merge(other), 0);
+
+        // Unlike `insert`
+        db.insert(set("regex", "opt-level=3", &["replaced"]));
+        assert_eq!(db.sets.len(), 2);
+        assert_eq!(db.sets[0].signatures[0].name, "replaced");
+    }
+
+    #[test]
+    fn save_replaces_the_database_through_a_temporary_file() {
+        let dir = test_dir("atomic");
+        let path = dir.join("sigs.json");
+        let mut db = SignatureDatabase::default();
+        db.insert(set("regex", "opt-level=3", &["a"]));
+        db.save(&path).unwrap();
+
+        db.insert(set("memchr", "opt-level=3", &["b"]));
+        db.save(&path).unwrap();
+        assert_eq!(SignatureDatabase::open(&path).unwrap().sets.len(), 2);
+        assert!(!path.with_extension("tmp").exists());
+
+        // A save that cannot be written leaves the previous database untouched
+        std::fs::create_dir(path.with_extension("tmp")).unwrap();
+        db.insert(set("serde", "opt-level=3", &["c"]));
+        assert!(db.save(&path).is_err());
+        assert_eq!(SignatureDatabase::open(&path).unwrap().sets.len(), 2);
+    }
+}
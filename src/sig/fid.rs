@@ -0,0 +1,274 @@
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+
+use iced_x86::{Decoder, DecoderOptions, Mnemonic};
+
+use crate::functions_utils::search::Function;
+use crate::ArianeError;
+
+/// Version of the interchange format read by `tools/ghidra/ImportArianeFid.py`.
+pub const FID_FORMAT_VERSION: u32 = 2;
+
+/// Ghidra only hashes functions with at least this many instructions.
+const MIN_CODE_UNITS: u32 = 4;
+
+/// Ghidra language and bitness of `target`'s functions, 64-bit x86 unless it is a 32-bit x86
+/// target.
+fn language(target: &str) -> (&'static str, u32) {
+    match target.split('-').next() {
+        Some("i386" | "i586" | "i686" | "x86") => ("x86:LE:32:default", 32),
+        _ => ("x86:LE:64:default", 64),
+    }
+}
+
+/// Number of instructions of a function, as Ghidra counts code units. Returns `None` for functions
+/// Ghidra would consider too short to be identified.
+fn code_unit_size(bytes: &[u8], bitness: u32) -> Option<u32> {
+    let mut code_unit_size = 0;
+    let mut decoder = Decoder::new(bitness, bytes, DecoderOptions::NONE);
+    let mut fn_end = 0;
+    while decoder.can_decode() {
+        match decoder.decode().mnemonic() {
+            Mnemonic::Ud2 | Mnemonic::Int3 => fn_end += 1,
+            _ => fn_end = 0,
+        }
+        if fn_end == 2 {
+            break;
+        }
+        code_unit_size += 1;
+    }
+
+    (code_unit_size >= MIN_CODE_UNITS).then_some(code_unit_size)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FidRelocation {
+    pub offset: u32,
+    pub name: String,
+}
+
+/// A function of a library. Ghidra's full and specific hashes depend on its processor
+/// specification, so they are not exported: the importer computes them from `bytes`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FidFunction {
+    pub name: String,
+    /// Number of instructions, for the importer to report functions Ghidra decodes differently.
+    pub code_unit_size: u32,
+    /// Raw bytes, hex encoded.
+    pub bytes: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relocations: Vec<FidRelocation>,
+}
+
+impl FidFunction {
+    /// Returns `None` for unnamed functions and functions too short to be identified.
+    pub fn from_function(function: &Function, bitness: u32) -> Option<FidFunction> {
+        Some(FidFunction {
+            name: function.name.clone()?,
+            code_unit_size: code_unit_size(function.data, bitness)?,
+            bytes: hex::encode(function.data),
+            relocations: function
+                .relocations
+                .iter()
+                .filter(|(_, name)| !name.is_empty())
+                .map(|(offset, name)| FidRelocation {
+                    offset: *offset,
+                    name: name.clone(),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// A Function ID library: one build of a crate.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FidLibrary {
+    /// Crate name.
+    pub family: String,
+    pub version: String,
+    /// What distinguishes builds of the same crate version: toolchain, target, profile, features.
+    pub variant: String,
+    pub language: String,
+    /// `windows` or `gcc`, depending on the target.
+    pub compiler_spec: String,
+    pub functions: Vec<FidFunction>,
+}
+
+impl FidLibrary {
+    pub fn new(
+        family: &str,
+        version: &str,
+        variant: &str,
+        target: &str,
+        functions: &[Function],
+    ) -> FidLibrary {
+        let (language, bitness) = language(target);
+        FidLibrary {
+            family: family.to_string(),
+            version: version.to_string(),
+            variant: variant.to_string(),
+            language: language.to_string(),
+            compiler_spec: match target.contains("windows") {
+                true => "windows".to_string(),
+                false => "gcc".to_string(),
+            },
+            functions: functions
+                .iter()
+                .filter_map(|f| FidFunction::from_function(f, bitness))
+                .collect(),
+        }
+    }
+}
+
+/// Content of a `.fid.json` file, holding every build of a crate version.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FidExport {
+    pub format_version: u32,
+    pub libraries: Vec<FidLibrary>,
+}
+
+impl Default for FidExport {
+    fn default() -> Self {
+        FidExport {
+            format_version: FID_FORMAT_VERSION,
+            libraries: vec![],
+        }
+    }
+}
+
+impl FidExport {
+    /// Parses an export written by this version of Ariane.
+    pub fn from_slice(content: &[u8]) -> Result<FidExport, ArianeError> {
+        #[derive(Deserialize)]
+        struct Versioned {
+            format_version: Option<u32>,
+        }
+
+        match serde_json::from_slice::<Versioned>(content)?.format_version {
+            Some(FID_FORMAT_VERSION) => Ok(serde_json::from_slice(content)?),
+            Some(version) => Err(ArianeError::Parse(format!(
+                "Function ID export format version {} is not supported, expected {}",
+                version, FID_FORMAT_VERSION
+            ))),
+            None => Err(ArianeError::Parse(
+                "Not an Ariane Function ID export".to_string(),
+            )),
+        }
+    }
+}
+
+/// Adds `library` to the export at `path`, replacing a library with the same variant.
+/// Returns how many functions were written. An existing file that cannot be read back is left
+/// untouched, rather than losing the libraries it holds.
+pub fn write_fid(path: &Path, library: FidLibrary) -> Result<usize, ArianeError> {
+    let mut export = match std::fs::read(path) {
+        Ok(content) => FidExport::from_slice(&content)
+            .map_err(|e| ArianeError::Parse(format!("{:?}: {}", path, e)))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => FidExport::default(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let written = library.functions.len();
+    export.libraries.retain(|l| {
+        (&l.family, &l.version, &l.variant) != (&library.family, &library.version, &library.variant)
+    });
+    export.libraries.push(library);
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut f = std::fs::File::create(path)?;
+    f.write_all(serde_json::to_string(&export)?.as_bytes())?;
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions_utils::search::FunctionType;
+
+    /// `lea rax, [rip + 0x1000]; call 0x2000; mov eax, 1; mov rax, [rbx + 8]; ret`
+    const FUNCTION: [u8; 22] = [
+        0x48, 0x8d, 0x05, 0x00, 0x10, 0x00, 0x00, 0xe8, 0x00, 0x20, 0x00, 0x00, 0xb8, 0x01, 0x00,
+        0x00, 0x00, 0x48, 0x8b, 0x43, 0x08, 0xc3,
+    ];
+
+    #[test]
+    fn instructions_are_counted() {
+        assert_eq!(code_unit_size(&FUNCTION, 64), Some(5));
+        // REX prefixes are `dec eax` in 32-bit code
+        assert_eq!(code_unit_size(&FUNCTION, 32), Some(7));
+    }
+
+    #[test]
+    fn short_functions_are_not_exported() {
+        // mov eax, 1; nop; ret
+        assert_eq!(code_unit_size(&[0xb8, 1, 0, 0, 0, 0x90, 0xc3], 64), None);
+    }
+
+    #[test]
+    fn language_follows_the_target() {
+        let function = Function {
+            data: &FUNCTION,
+            name: Some("f".to_string()),
+            rva: 0x1000,
+            fn_type: FunctionType::Exe,
+            relocations: vec![],
+        };
+        let functions = [function];
+        let library = |target| FidLibrary::new("regex", "1.9.4", "opt-level=3", target, &functions);
+
+        let x64 = library("x86_64-pc-windows-msvc");
+        assert_eq!(x64.language, "x86:LE:64:default");
+        assert_eq!(x64.compiler_spec, "windows");
+        assert_eq!(x64.functions[0].code_unit_size, 5);
+
+        let x86 = library("i686-unknown-linux-gnu");
+        assert_eq!(x86.language, "x86:LE:32:default");
+        assert_eq!(x86.compiler_spec, "gcc");
+        assert_eq!(x86.functions[0].code_unit_size, 7);
+    }
+
+    fn library(variant: &str) -> FidLibrary {
+        FidLibrary {
+            family: "regex".to_string(),
+            version: "1.9.4".to_string(),
+            variant: variant.to_string(),
+            language: "x86:LE:64:default".to_string(),
+            compiler_spec: "gcc".to_string(),
+            functions: vec![],
+        }
+    }
+
+    #[test]
+    fn unreadable_exports_are_not_replaced() {
+        let path = std::env::temp_dir().join(format!("ariane-fid-{}.fid.json", std::process::id()));
+        for content in [
+            "not json",
+            r#"{"format_version": 1, "libraries": []}"#,
+            "{}",
+        ] {
+            std::fs::write(&path, content).unwrap();
+            assert!(matches!(
+                write_fid(&path, library("opt-level=3")),
+                Err(ArianeError::Parse(_))
+            ));
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), content);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+        write_fid(&path, library("opt-level=3")).unwrap();
+        write_fid(&path, library("opt-level=s")).unwrap();
+        write_fid(&path, library("opt-level=3")).unwrap();
+        let export = FidExport::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        let variants: Vec<&str> = export
+            .libraries
+            .iter()
+            .map(|l| l.variant.as_str())
+            .collect();
+        assert_eq!(variants, ["opt-level=s", "opt-level=3"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::Path;
+
+use crate::functions_utils::search::Function;
+use crate::sig::pattern::{variable_bytes, Pattern, PATTERN_LENGTH};
+use crate::ArianeError;
+
+/// Marks the end of a `.pat` file.
+const PAT_END: &str = "---";
+
+/// `.pat` line of `function`, as expected by `sigmake`: its pattern padded to 32 bytes, its name,
+/// the names it references with their offsets, and the bytes following the CRC.
+/// Returns `None` for unnamed functions and functions too short to get a pattern.
+pub fn pat_line(function: &Function) -> Option<String> {
+    let name = function.name.as_ref()?;
+    let pattern = Pattern::from_function(function)?;
+    let variable = variable_bytes(function);
+
+    let mut line = String::new();
+    for i in 0..PATTERN_LENGTH {
+        match pattern.leading.get(i) {
+            Some(Some(b)) => write!(line, "{:02X}", b).ok()?,
+            _ => line.push_str(".."),
+        }
+    }
+    write!(
+        line,
+        " {:02X} {:04X} {:04X} :0000 {}",
+        pattern.crc_length, pattern.crc, pattern.length, name
+    )
+    .ok()?;
+
+    for (offset, reference) in &function.relocations {
+        if !reference.is_empty() {
+            write!(line, " ^{:04X} {}", offset, reference).ok()?;
+        }
+    }
+
+    let tail_start = pattern.leading.len() + pattern.crc_length as usize;
+    if tail_start < function.data.len() {
+        line.push(' ');
+        for (b, variable) in function.data.iter().zip(&variable).skip(tail_start) {
+            match variable {
+                false => write!(line, "{:02X}", b).ok()?,
+                true => line.push_str(".."),
+            }
+        }
+    }
+
+    Some(line)
+}
+
+/// Writes the `.pat` lines of `functions` to `path`, keeping the lines already there, e.g. from
+/// another build profile. Returns how many lines were added.
+pub fn write_pat(path: &Path, functions: &[Function]) -> Result<usize, ArianeError> {
+    let mut lines: Vec<String> = match std::fs::read_to_string(path) {
+        Ok(content) => content
+            .lines()
+            .filter(|line| *line != PAT_END)
+            .map(str::to_string)
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(e) => return Err(e.into()),
+    };
+    let mut known: HashSet<String> = lines.iter().cloned().collect();
+
+    let before = lines.len();
+    for line in functions.iter().filter_map(pat_line) {
+        if known.insert(line.clone()) {
+            lines.push(line);
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut f = std::fs::File::create(path)?;
+    for line in &lines {
+        writeln!(f, "{}", line)?;
+    }
+    writeln!(f, "{}", PAT_END)?;
+
+    Ok(lines.len() - before)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions_utils::search::FunctionType;
+    use crate::sig::pattern::crc16;
+    use regex::Regex;
+
+    /// `.pat` line grammar: 32 leading bytes, CRC length, CRC, function length, public name,
+    /// referenced names, then tail bytes.
+    const PAT_LINE: &str = r"^([0-9A-F]{2}|\.\.){32} [0-9A-F]{2} [0-9A-F]{4} [0-9A-F]{4} :0000 \S+( \^[0-9A-F]{4} \S+)*( ([0-9A-F]{2}|\.\.)+)?$";
+
+    fn function<'a>(data: &'a [u8], name: &str, relocations: &[(u32, &str)]) -> Function<'a> {
+        Function {
+            data,
+            name: Some(name.to_string()),
+            rva: 0x1000,
+            fn_type: FunctionType::Exe,
+            relocations: relocations
+                .iter()
+                .map(|(offset, name)| (*offset, name.to_string()))
+                .collect(),
+        }
+    }
+
+    fn long_function_data() -> Vec<u8> {
+        let mut data: Vec<u8> = (0..0x40).collect();
+        data[4] = 0xe8;
+        data
+    }
+
+    #[test]
+    fn lines_follow_the_pat_grammar() {
+        let data = long_function_data();
+        let line = pat_line(&function(&data, "long", &[(5, "callee"), (40, "")])).unwrap();
+        assert!(Regex::new(PAT_LINE).unwrap().is_match(&line), "{}", line);
+
+        let leading = "00010203E8........090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F";
+        // Bytes 32 to 40 are covered by the CRC, the relocation at 40 ends it. Relocations to
+        // unnamed targets are wildcards but are not referenced.
+        let expected = format!(
+            "{} 08 {:04X} 0040 :0000 long ^0005 callee ........2C2D2E2F303132333435363738393A3B3C3D3E3F",
+            leading,
+            crc16(&data[32..40])
+        );
+        assert_eq!(line, expected);
+    }
+
+    #[test]
+    fn short_functions_are_padded() {
+        let data = [0x55, 0x48, 0x89, 0xe5, 0x31, 0xc0, 0x5d, 0xc3, 0x90, 0x90];
+        let line = pat_line(&function(&data, "short", &[])).unwrap();
+        assert!(Regex::new(PAT_LINE).unwrap().is_match(&line), "{}", line);
+        assert_eq!(
+            line,
+            format!(
+                "554889E531C05DC39090{} 00 0000 000A :0000 short",
+                "..".repeat(22)
+            )
+        );
+
+        assert_eq!(pat_line(&function(&data[..5], "tiny", &[])), None);
+        let unnamed = Function {
+            name: None,
+            ..function(&data, "", &[])
+        };
+        assert_eq!(pat_line(&unnamed), None);
+    }
+
+    #[test]
+    fn lines_are_merged_into_existing_files() {
+        let path = std::env::temp_dir().join(format!("ariane-pat-{}.pat", std::process::id()));
+        let existing = format!("554889E5{} 00 0000 0010 :0000 other", "..".repeat(28));
+        std::fs::write(&path, format!("{}\n{}\n", existing, PAT_END)).unwrap();
+
+        let long = long_function_data();
+        let short = [0x55, 0x48, 0x89, 0xe5, 0x31, 0xc0, 0x5d, 0xc3];
+        let functions = [
+            function(&long, "long", &[(5, "callee")]),
+            function(&short, "short", &[]),
+        ];
+        assert_eq!(write_pat(&path, &functions).unwrap(), 2);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], existing);
+        assert!(Regex::new(PAT_LINE).unwrap().is_match(&existing));
+        assert_eq!(lines[1], pat_line(&functions[0]).unwrap());
+        assert_eq!(lines[2], pat_line(&functions[1]).unwrap());
+        assert_eq!(lines[3], PAT_END);
+
+        // Lines already there, e.g. from another build, are not duplicated
+        assert_eq!(write_pat(&path, &functions[1..]).unwrap(), 0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), content);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+use fuzzyhash::FuzzyHash;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::sig::sig_generation::{FuzzyFunc, Hash};
+use crate::sig::tlsh::MAX_DISTANCE;
+
+/// Length of the substring two ssdeep chunks must share to get a non-zero score.
+const ROLLING_WINDOW: usize = 7;
+
+/// A parsed ssdeep hash: `block_size:chunk:double_chunk`.
+struct ParsedHash {
+    block_size: u32,
+    chunk: Vec<u8>,
+    double_chunk: Vec<u8>,
+}
+
+impl ParsedHash {
+    fn parse(hash: &FuzzyHash) -> Option<ParsedHash> {
+        let hash = hash.to_string();
+        let mut parts = hash.split(':');
+        let block_size = parts.next()?.parse().ok()?;
+
+        Some(ParsedHash {
+            block_size,
+            chunk: eliminate_sequences(parts.next()?.as_bytes()),
+            double_chunk: eliminate_sequences(parts.next()?.as_bytes()),
+        })
+    }
+
+    /// Each chunk along with the block size it was computed with.
+    fn chunks(&self) -> [(u32, &[u8]); 2] {
+        [
+            (self.block_size, &self.chunk),
+            (self.block_size * 2, &self.double_chunk),
+        ]
+    }
+}
+
+/// Same normalization as `fuzzyhash` applies before comparing chunks, quirks included, so that
+/// the index finds exactly the pairs `FuzzyHash::compare` would score.
+fn eliminate_sequences(input: &[u8]) -> Vec<u8> {
+    if input.len() < 3 {
+        return input.to_vec();
+    }
+
+    let mut result = input[..3].to_vec();
+    for i in 3..input.len() {
+        let current = input[result.len()];
+        if current != input[i - 1] || current != input[i - 2] || current != input[i - 3] {
+            result.push(input[i]);
+        }
+    }
+
+    result
+}
+
+/// 7-grams of `chunk`, packed in an integer.
+fn chunk_grams(chunk: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    chunk
+        .windows(ROLLING_WINDOW)
+        .map(|gram| gram.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64))
+}
+
+/// Index over library signatures, returning for a target hash only the signatures
+/// `Hash::compare` can give a non-zero score to.
+///
+/// ssdeep only compares chunks computed with the same block size, and scores 0 unless they share
+/// a 7 character substring, or are identical. Chunks are therefore indexed by their block size and
+/// each of their 7-grams, and by their whole content.
+///
+/// CFG hashes score above 0 whatever their size, so every CFG signature is a candidate. TLSH
+/// hashes are indexed by their encoded length, whose difference alone can exceed `MAX_DISTANCE`.
+pub struct SignatureIndex {
+    grams: HashMap<(u32, u64), Vec<usize>>,
+    exact: HashMap<(u32, Vec<u8>), Vec<usize>>,
+    cfg: Vec<usize>,
+    lengths: BTreeMap<u8, Vec<usize>>,
+}
+
+impl SignatureIndex {
+    pub fn new(signatures: &[FuzzyFunc]) -> SignatureIndex {
+        let mut grams = HashMap::<(u32, u64), Vec<usize>>::new();
+        let mut exact = HashMap::<(u32, Vec<u8>), Vec<usize>>::new();
+        let mut cfg = vec![];
+        let mut lengths = BTreeMap::<u8, Vec<usize>>::new();
+
+        for (i, sig) in signatures.iter().enumerate() {
+            let hash = match &sig.hash {
+                Hash::Ssdeep(hash) => ParsedHash::parse(hash),
+                Hash::Cfg(_) => {
+                    cfg.push(i);
+                    continue;
+                }
+                Hash::Tlsh(hash) => {
+                    lengths.entry(hash.l_value).or_default().push(i);
+                    continue;
+                }
+            };
+            let hash = match hash {
+                Some(hash) => hash,
+                None => continue,
+            };
+            for (block_size, chunk) in hash.chunks() {
+                for gram in chunk_grams(chunk) {
+                    let entries = grams.entry((block_size, gram)).or_default();
+                    // A chunk may contain the same 7-gram several times
+                    if entries.last() != Some(&i) {
+                        entries.push(i);
+                    }
+                }
+            }
+            exact
+                .entry((hash.block_size, hash.chunk))
+                .or_default()
+                .push(i);
+        }
+
+        SignatureIndex {
+            grams,
+            exact,
+            cfg,
+            lengths,
+        }
+    }
+
+    /// Indices of the signatures that may match `hash`, in ascending order.
+    pub fn candidates(&self, hash: &Hash) -> Vec<usize> {
+        let hash = match hash {
+            Hash::Ssdeep(hash) => ParsedHash::parse(hash),
+            Hash::Cfg(_) => return self.cfg.clone(),
+            Hash::Tlsh(hash) => {
+                // A length difference above 1 costs 12 each
+                let max_diff = (MAX_DISTANCE / 12) as u8;
+                let mut candidates: Vec<usize> = (0..=2 * max_diff as u16)
+                    .map(|d| hash.l_value.wrapping_sub(max_diff).wrapping_add(d as u8))
+                    .filter_map(|l_value| self.lengths.get(&l_value))
+                    .flatten()
+                    .copied()
+                    .collect();
+                candidates.sort_unstable();
+                candidates.dedup();
+                return candidates;
+            }
+        };
+        let hash = match hash {
+            Some(hash) => hash,
+            None => return vec![],
+        };
+
+        let mut candidates = vec![];
+        for (block_size, chunk) in hash.chunks() {
+            for gram in chunk_grams(chunk) {
+                if let Some(entries) = self.grams.get(&(block_size, gram)) {
+                    candidates.extend(entries);
+                }
+            }
+        }
+        if let Some(entries) = self.exact.get(&(hash.block_size, hash.chunk)) {
+            candidates.extend(entries);
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        candidates
+    }
+}
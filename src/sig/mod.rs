@@ -1,2 +1,11 @@
+pub mod assignment;
+pub mod callgraph;
+pub mod cfg;
 pub mod comparaison;
+pub mod database;
+pub mod fid;
+pub mod flirt;
+pub mod index;
+pub mod pattern;
 pub mod sig_generation;
+pub mod tlsh;
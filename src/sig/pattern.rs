@@ -0,0 +1,379 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+use log::debug;
+
+use crate::functions_utils::search::Function;
+use crate::sig::comparaison::{Method, Origin, Symbol};
+use crate::sig::database::SignatureSet;
+
+/// Number of leading bytes of a pattern.
+pub const PATTERN_LENGTH: usize = 32;
+
+/// Functions shorter than this match too many unrelated functions to get a pattern.
+pub const MIN_PATTERN_LENGTH: usize = 6;
+
+/// Maximum number of bytes after the leading bytes covered by the CRC.
+const MAX_CRC_LENGTH: usize = 255;
+
+/// Size of the operand patched by a relocation.
+const RELOCATION_SIZE: usize = 4;
+
+/// CRC16 used by FLIRT (reversed CCITT polynomial), bytes of the result swapped.
+pub fn crc16(data: &[u8]) -> u16 {
+    if data.is_empty() {
+        return 0;
+    }
+
+    let mut crc: u16 = 0xffff;
+    for b in data {
+        let mut b = *b;
+        for _ in 0..8 {
+            crc = if (crc ^ b as u16) & 1 != 0 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            };
+            b >>= 1;
+        }
+    }
+
+    (!crc).swap_bytes()
+}
+
+/// Whether each byte of `function` is patched by a relocation.
+pub fn variable_bytes(function: &Function) -> Vec<bool> {
+    let data = function.data;
+    let mut variable = vec![false; data.len()];
+    for (offset, _) in &function.relocations {
+        let start = (*offset as usize).min(data.len());
+        let end = (start + RELOCATION_SIZE).min(data.len());
+        variable[start..end].fill(true);
+    }
+
+    variable
+}
+
+/// Exact signature of a function, as in FLIRT: its leading bytes, bytes patched by relocations
+/// being wildcards, a CRC of the bytes that follow up to the next relocation, and its length.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Pattern {
+    /// `None` for wildcards. Shorter than `PATTERN_LENGTH` for short functions.
+    pub leading: Vec<Option<u8>>,
+    pub crc_length: u8,
+    pub crc: u16,
+    pub length: u32,
+}
+
+impl Pattern {
+    /// Returns `None` for functions shorter than `MIN_PATTERN_LENGTH`.
+    pub fn from_function(function: &Function) -> Option<Pattern> {
+        let data = function.data;
+        if data.len() < MIN_PATTERN_LENGTH {
+            return None;
+        }
+
+        let variable = variable_bytes(function);
+        let leading_length = data.len().min(PATTERN_LENGTH);
+        let leading = data[..leading_length]
+            .iter()
+            .zip(&variable)
+            .map(|(b, variable)| (!variable).then_some(*b))
+            .collect();
+        let crc_length = variable[leading_length..]
+            .iter()
+            .take(MAX_CRC_LENGTH)
+            .take_while(|variable| !**variable)
+            .count();
+
+        Some(Pattern {
+            leading,
+            crc_length: crc_length as u8,
+            crc: crc16(&data[leading_length..leading_length + crc_length]),
+            length: data.len() as u32,
+        })
+    }
+
+    /// Whether `data` matches this pattern.
+    pub fn matches(&self, data: &[u8]) -> bool {
+        let crc_start = self.leading.len();
+        let crc_end = crc_start + self.crc_length as usize;
+        data.len() == self.length as usize
+            && crc_end <= data.len()
+            && self
+                .leading
+                .iter()
+                .zip(data)
+                .all(|(p, b)| p.is_none_or(|p| p == *b))
+            && crc16(&data[crc_start..crc_end]) == self.crc
+    }
+}
+
+/// FLIRT `.pat` notation: leading bytes in hex, `..` for wildcards, then the CRC length, the CRC
+/// and the function length.
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in &self.leading {
+            match b {
+                Some(b) => write!(f, "{:02X}", b)?,
+                None => write!(f, "..")?,
+            }
+        }
+        write!(
+            f,
+            " {:02X} {:04X} {:04X}",
+            self.crc_length, self.crc, self.length
+        )
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid pattern {:?}", s);
+        let parts: Vec<&str> = s.split(' ').collect();
+        if parts.len() != 4 || !parts[0].len().is_multiple_of(2) || !parts[0].is_ascii() {
+            return Err(invalid());
+        }
+
+        let mut leading = vec![];
+        for i in (0..parts[0].len()).step_by(2) {
+            leading.push(match &parts[0][i..i + 2] {
+                ".." => None,
+                b => Some(u8::from_str_radix(b, 16).map_err(|_| invalid())?),
+            });
+        }
+
+        Ok(Pattern {
+            leading,
+            crc_length: u8::from_str_radix(parts[1], 16).map_err(|_| invalid())?,
+            crc: u16::from_str_radix(parts[2], 16).map_err(|_| invalid())?,
+            length: u32::from_str_radix(parts[3], 16).map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// A library function's pattern.
+struct Entry {
+    pattern: Pattern,
+    name: String,
+    origin: Origin,
+}
+
+/// Library patterns, by function length.
+#[derive(Default)]
+pub struct PatternMatcher {
+    patterns: HashMap<u32, Vec<Entry>>,
+}
+
+impl PatternMatcher {
+    pub fn add(&mut self, set: &SignatureSet) {
+        for sig in &set.signatures {
+            let pattern = match sig.pattern.as_ref().and_then(|p| p.parse::<Pattern>().ok()) {
+                Some(pattern) => pattern,
+                None => continue,
+            };
+            let entries = self.patterns.entry(pattern.length).or_default();
+            if !entries
+                .iter()
+                .any(|e| e.pattern == pattern && e.name == sig.name)
+            {
+                entries.push(Entry {
+                    pattern,
+                    name: sig.name.clone(),
+                    origin: Origin::new(&set.key, set.fallback),
+                });
+            }
+        }
+    }
+
+    /// Target functions matching the pattern of exactly one library function, with a score of 100
+    /// unless it was built with a fallback, in which case it must still exceed `threshold`.
+    /// Functions matching the patterns of several functions are left out, as FLIRT does with
+    /// collisions.
+    pub fn matches(&self, functions: &[Function], threshold: u32) -> Vec<Symbol> {
+        let mut symbols = vec![];
+        let mut collisions = 0;
+        for f in functions {
+            let entries = match self.patterns.get(&(f.data.len() as u32)) {
+                Some(entries) => entries,
+                None => continue,
+            };
+            let matching: Vec<&Entry> = entries
+                .iter()
+                .filter(|e| e.pattern.matches(f.data))
+                .collect();
+            let names: BTreeSet<&str> = matching.iter().map(|e| e.name.as_str()).collect();
+            match names.len() {
+                0 => {}
+                1 => {
+                    let score = Origin::weigh(Some(&matching[0].origin), 100);
+                    if score > threshold {
+                        symbols.push(Symbol {
+                            name: matching[0].name.clone(),
+                            rva: f.rva,
+                            score,
+                            method: Method::Pattern,
+                            origin: Some(matching[0].origin.clone()),
+                            demangled: None,
+                            alternatives: vec![],
+                        });
+                    }
+                }
+                _ => collisions += 1,
+            }
+        }
+        debug!(
+            "{} functions matched by pattern, {} collisions",
+            symbols.len(),
+            collisions
+        );
+
+        symbols
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::functions_utils::search::FunctionType;
+    use crate::sig::database::{Signature, SignatureKey};
+
+    /// 40 bytes `00 01 .. 27`, relocations patching bytes 2 to 5 and 36 to 39.
+    fn function(data: &[u8]) -> Function<'_> {
+        Function {
+            data,
+            name: Some("f".to_string()),
+            rva: 0,
+            fn_type: FunctionType::Lib,
+            relocations: vec![(2, "a".to_string()), (36, "b".to_string())],
+        }
+    }
+
+    fn data() -> Vec<u8> {
+        (0..40).collect()
+    }
+
+    #[test]
+    fn crc16_is_flirt_crc() {
+        // FLIRT's CRC is CRC-16/X-25, whose check value is 0x906E, with its bytes swapped
+        assert_eq!(crc16(b"123456789"), 0x6e90);
+        // Patterns without CRC bytes have a CRC of 0000
+        assert_eq!(crc16(&[]), 0);
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let data = data();
+        let pattern = Pattern::from_function(&function(&data)).unwrap();
+        assert_eq!(
+            pattern.to_string(),
+            format!(
+                "0001........060708090A0B0C0D0E0F101112131415161718191A1B1C1D1E1F 04 {:04X} 0028",
+                crc16(&[0x20, 0x21, 0x22, 0x23])
+            )
+        );
+        assert_eq!(pattern.to_string().parse::<Pattern>().unwrap(), pattern);
+
+        for invalid in ["", "0001 00 0000", "000 00 0000 0006", "00ZZ 00 0000 0006"] {
+            assert!(invalid.parse::<Pattern>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn relocated_bytes_are_wildcards() {
+        let data = data();
+        let pattern = Pattern::from_function(&function(&data)).unwrap();
+        assert!(pattern.matches(&data));
+
+        let mut relocated = data.clone();
+        relocated[2..6].copy_from_slice(&[0xff; 4]);
+        relocated[36..40].copy_from_slice(&[0xff; 4]);
+        assert!(pattern.matches(&relocated));
+
+        // Leading byte, then byte covered by the CRC
+        for i in [0, 33] {
+            let mut other = data.clone();
+            other[i] ^= 0xff;
+            assert!(!pattern.matches(&other), "byte {}", i);
+        }
+    }
+
+    #[test]
+    fn length_must_match() {
+        let data = data();
+        let pattern = Pattern::from_function(&function(&data)).unwrap();
+        assert!(!pattern.matches(&data[..39]));
+        let mut longer = data.clone();
+        longer.push(0xc3);
+        assert!(!pattern.matches(&longer));
+
+        assert!(Pattern::from_function(&function(&data[..MIN_PATTERN_LENGTH - 1])).is_none());
+    }
+
+    fn signature_set(krate: &str, functions: &[(&str, &[u8])]) -> SignatureSet {
+        SignatureSet {
+            key: SignatureKey::new(
+                krate,
+                "1.0.0",
+                &[],
+                "1.72.0",
+                "x86_64-unknown-linux-gnu",
+                "",
+            ),
+            fallback: None,
+            signatures: functions
+                .iter()
+                .map(|(name, data)| Signature {
+                    name: name.to_string(),
+                    hash: String::new(),
+                    hashes: Default::default(),
+                    size: data.len(),
+                    masked: String::new(),
+                    pattern: Pattern::from_function(&function(data)).map(|p| p.to_string()),
+                    calls: vec![],
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn functions_matching_several_names_are_dropped() {
+        let shared = data();
+        let unique: Vec<u8> = (100..140).collect();
+
+        let mut matcher = PatternMatcher::default();
+        matcher.add(&signature_set(
+            "a",
+            &[("shared_a", &shared), ("unique", &unique)],
+        ));
+        matcher.add(&signature_set("b", &[("shared_b", &shared)]));
+        // The same name found in another build is not a collision
+        matcher.add(&signature_set("c", &[("unique", &unique)]));
+
+        let target = [
+            Function {
+                rva: 0x1000,
+                fn_type: FunctionType::Exe,
+                relocations: vec![],
+                name: None,
+                data: &shared,
+            },
+            Function {
+                rva: 0x2000,
+                fn_type: FunctionType::Exe,
+                relocations: vec![],
+                name: None,
+                data: &unique,
+            },
+        ];
+        let symbols = matcher.matches(&target, 25);
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols[0].name, "unique");
+        assert_eq!(symbols[0].rva, 0x2000);
+        assert_eq!(symbols[0].score, 100);
+        assert_eq!(symbols[0].method, Method::Pattern);
+    }
+}
@@ -1,9 +1,15 @@
-use std::fmt::Display;
+use std::fmt::{self, Display};
+use std::str::FromStr;
 
 use fuzzyhash::FuzzyHash;
 use iced_x86::{Decoder, DecoderOptions, Formatter, Instruction, MasmFormatter, Mnemonic, OpKind};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::{functions_utils::search::Function, utils::export::find_fn_name};
+use crate::functions_utils::search::Function;
+use crate::sig::cfg::CfgHash;
+use crate::sig::comparaison::Origin;
+use crate::sig::tlsh::TlshHash;
 
 pub trait HashFn<T> {
     fn hash(bytes: &Vec<u8>) -> T;
@@ -11,10 +17,98 @@ pub trait HashFn<T> {
     fn get_hash(&self) -> &T;
 }
 
-pub struct Hash {
-    // tlsh
-    // pub hash: tlsh2::Tlsh128_1,
-    pub hash: FuzzyHash,
+/// Algorithm used to hash and compare functions.
+#[derive(
+    Serialize, Deserialize, Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    /// ssdeep over the normalized bytes of the function.
+    #[default]
+    Ssdeep,
+    /// Structure of the function's control-flow graph, see `CfgHash`.
+    Cfg,
+    /// TLSH over the normalized bytes of the function.
+    Tlsh,
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Algorithm::Ssdeep => write!(f, "ssdeep"),
+            Algorithm::Cfg => write!(f, "cfg"),
+            Algorithm::Tlsh => write!(f, "tlsh"),
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ssdeep" => Ok(Algorithm::Ssdeep),
+            "cfg" => Ok(Algorithm::Cfg),
+            "tlsh" => Ok(Algorithm::Tlsh),
+            other => Err(format!("Unknown algorithm {:?}", other)),
+        }
+    }
+}
+
+pub enum Hash {
+    Ssdeep(FuzzyHash),
+    Cfg(CfgHash),
+    Tlsh(TlshHash),
+}
+
+impl Hash {
+    /// Hashes a function with `algorithm`. `normalized` are the bytes of the function as returned by
+    /// `hash_single_func`. Returns `None` if the function cannot be hashed with this algorithm.
+    pub fn new(algorithm: Algorithm, bytes: &[u8], normalized: &[u8]) -> Option<Hash> {
+        match algorithm {
+            Algorithm::Ssdeep => Some(Hash::Ssdeep(FuzzyHash::new(normalized))),
+            Algorithm::Cfg => Some(Hash::Cfg(CfgHash::new(bytes))),
+            Algorithm::Tlsh => TlshHash::new(normalized).map(Hash::Tlsh),
+        }
+    }
+
+    /// Parses a hash previously formatted with `to_string`.
+    pub fn parse(algorithm: Algorithm, hash: &str) -> Option<Hash> {
+        match algorithm {
+            Algorithm::Ssdeep => Some(Hash::Ssdeep(FuzzyHash::from(hash))),
+            Algorithm::Cfg => hash.parse().ok().map(Hash::Cfg),
+            Algorithm::Tlsh => hash.parse().ok().map(Hash::Tlsh),
+        }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            Hash::Ssdeep(_) => Algorithm::Ssdeep,
+            Hash::Cfg(_) => Algorithm::Cfg,
+            Hash::Tlsh(_) => Algorithm::Tlsh,
+        }
+    }
+
+    /// Similarity with `other`, from 0 to 100. `None` if the hashes were computed with different
+    /// algorithms or are invalid.
+    pub fn compare(&self, other: &Hash) -> Option<u32> {
+        match (self, other) {
+            (Hash::Ssdeep(a), Hash::Ssdeep(b)) => a.compare_to(b),
+            (Hash::Cfg(a), Hash::Cfg(b)) => Some(a.compare_hash(b) as u32),
+            (Hash::Tlsh(a), Hash::Tlsh(b)) => Some(a.compare_hash(b) as u32),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Hash::Ssdeep(hash) => write!(f, "{}", hash),
+            Hash::Cfg(hash) => write!(f, "{}", hash),
+            Hash::Tlsh(hash) => write!(f, "{}", hash),
+        }
+    }
 }
 
 pub struct FuzzyFunc {
@@ -22,35 +116,38 @@ pub struct FuzzyFunc {
     pub rva: u32,
     pub hash: Hash,
     pub name: Option<String>,
+    /// Build of the reference crate this signature comes from, if any.
+    pub origin: Option<Origin>,
 }
 
 impl Display for FuzzyFunc {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(_name) = &self.name {
-            write!(f, "{:#} {:?}", self.hash.hash, self.name)?;
+            write!(f, "{:#} {:?}", self.hash, self.name)?;
         } else {
-            write!(f, "{:#} ", self.hash.hash)?;
+            write!(f, "{:#} ", self.hash)?;
         }
 
         Ok(())
     }
 }
 
-impl HashFn<FuzzyHash> for FuzzyFunc {
+impl HashFn<FuzzyHash> for FuzzyHash {
     fn hash(bytes: &Vec<u8>) -> FuzzyHash {
-        FuzzyHash::new(&bytes)
+        FuzzyHash::new(bytes)
     }
 
     fn compare_hash(&self, with: &impl HashFn<FuzzyHash>) -> i32 {
-        self.hash.hash.compare_to(with.get_hash()).unwrap() as i64 as i32
+        self.compare_to(with.get_hash()).unwrap_or(0) as i64 as i32
     }
 
     fn get_hash(&self) -> &FuzzyHash {
-        &self.hash.hash
+        self
     }
 }
 
-const MIN_FUNC_SZ: u8 = 20;
+/// Functions shorter than this once normalized are not hashed.
+pub const MIN_FUNC_SZ: u8 = 20;
 
 /// Uses ssdeep to hash the function.
 /// Given bytes gets disassembled to avoid anything that has a relative offset to get hashed, e.g:
@@ -59,9 +156,26 @@ const MIN_FUNC_SZ: u8 = 20;
 /// ```
 /// This function also stop hashing as soon as it encounters two consecutive `ud2` or `int3`.
 pub fn hash_single_func(bytes: &[u8], verbose: bool) -> Vec<u8> {
+    disassemble_func(bytes, 0, verbose).0
+}
+
+/// A call instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallSite {
+    /// Offset of the last 4 bytes of the instruction in the function, where a call to another
+    /// object file gets relocated.
+    pub offset: u32,
+    /// Address called, for direct calls.
+    pub target: Option<u64>,
+}
+
+/// Normalizes the function like `hash_single_func`, and also returns the calls it makes,
+/// in order. `ip` is the address of the function, used to compute call targets.
+pub fn disassemble_func(bytes: &[u8], ip: u64, verbose: bool) -> (Vec<u8>, Vec<CallSite>) {
     let mut result = vec![];
+    let mut calls = vec![];
     // println!("HASHING A SINGLE FN");
-    let mut decoder = Decoder::new(64, bytes, DecoderOptions::NONE);
+    let mut decoder = Decoder::with_ip(64, bytes, ip, DecoderOptions::NONE);
     let mut formatter = MasmFormatter::new();
     let mut output = String::new();
 
@@ -76,6 +190,14 @@ pub fn hash_single_func(bytes: &[u8], verbose: bool) -> Vec<u8> {
         match instruction.mnemonic() {
             Mnemonic::Call => {
                 result.push(0xe8);
+                let end = instruction.next_ip() - ip;
+                calls.push(CallSite {
+                    offset: end.saturating_sub(4) as u32,
+                    target: match instruction.op0_kind() {
+                        OpKind::NearBranch64 => Some(instruction.near_branch64()),
+                        _ => None,
+                    },
+                });
                 continue;
             }
             Mnemonic::Jmp => {
@@ -99,7 +221,7 @@ pub fn hash_single_func(bytes: &[u8], verbose: bool) -> Vec<u8> {
         if fn_end == 2 {
             break;
         }
-        let start_index = (instruction.ip()) as usize;
+        let start_index = (instruction.ip() - ip) as usize;
         let instr_bytes = &bytes[start_index..start_index + instruction.len()];
         for b in instr_bytes.iter() {
             if verbose {
@@ -119,38 +241,27 @@ pub fn hash_single_func(bytes: &[u8], verbose: bool) -> Vec<u8> {
     if verbose {
         println!("{}", hex::encode(&result));
     }
-    result
+    (result, calls)
 }
 
-pub fn hash_functions(functions: &Vec<Function>) -> Vec<FuzzyFunc> {
-    let mut result = vec![];
-
-    for f in functions {
-        // if f.end_pa < f.start_pa || f.end_pa - f.start_pa < MIN_FUNC_SZ.into() {
-        //     continue;
-        // }
-
-        // end_pa MUST be next exported func if it has one
-        let data = hash_single_func(f.data, false);
+/// Hashes `functions` with `algorithm` in parallel, results are in the same order as `functions`.
+pub fn hash_functions(functions: &[Function], algorithm: Algorithm) -> Vec<FuzzyFunc> {
+    functions
+        .par_iter()
+        .filter_map(|f| {
+            // end_pa MUST be next exported func if it has one
+            let data = hash_single_func(f.data, false);
 
-        if data.len() < MIN_FUNC_SZ.into() {
-            continue;
-        }
-
-        let hash = FuzzyHash::new(&data);
-
-        // let fn_name = match f.name.clone() {
-        //     Some(name) => Some(name),
-        //     None => find_fn_name(f.start_pa, file_bytes),
-        // };
-        result.push(FuzzyFunc {
-            // pa: f.start_pa,
-            rva: f.rva,
-            // data: f.data,
-            hash: Hash { hash: hash },
-            name: f.name.clone(),
-        });
-    }
+            if data.len() < MIN_FUNC_SZ.into() {
+                return None;
+            }
 
-    result
+            Some(FuzzyFunc {
+                rva: f.rva,
+                hash: Hash::new(algorithm, f.data, &data)?,
+                name: f.name.clone(),
+                origin: None,
+            })
+        })
+        .collect()
 }
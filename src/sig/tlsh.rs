@@ -0,0 +1,336 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::sig::sig_generation::HashFn;
+
+/// TLSH needs at least this many bytes to produce a meaningful hash.
+pub const MIN_DATA_LENGTH: usize = 50;
+
+/// Distance at and above which two hashes are considered unrelated, i.e. get a score of 0.
+pub const MAX_DISTANCE: u32 = 200;
+
+const BUCKETS: usize = 128;
+const CODE_SIZE: usize = BUCKETS / 4;
+
+/// Pearson permutation table from the TLSH reference implementation.
+const V_TABLE: [u8; 256] = [
+    1, 87, 49, 12, 176, 178, 102, 166, 121, 193, 6, 84, 249, 230, 44, 163, 14, 197, 213, 181, 161,
+    85, 218, 80, 64, 239, 24, 226, 236, 142, 38, 200, 110, 177, 104, 103, 141, 253, 255, 50, 77,
+    101, 81, 18, 45, 96, 31, 222, 25, 107, 190, 70, 86, 237, 240, 34, 72, 242, 20, 214, 244, 227,
+    149, 235, 97, 234, 57, 22, 60, 250, 82, 175, 208, 5, 127, 199, 111, 62, 135, 248, 174, 169,
+    211, 58, 66, 154, 106, 195, 245, 171, 17, 187, 182, 179, 0, 243, 132, 56, 148, 75, 128, 133,
+    158, 100, 130, 126, 91, 13, 153, 246, 216, 219, 119, 68, 223, 78, 83, 88, 201, 99, 122, 11, 92,
+    32, 136, 114, 52, 10, 138, 30, 48, 183, 156, 35, 61, 26, 143, 74, 251, 94, 129, 162, 63, 152,
+    170, 7, 115, 167, 241, 206, 3, 150, 55, 59, 151, 220, 90, 53, 23, 131, 125, 173, 15, 238, 79,
+    95, 89, 16, 105, 137, 225, 224, 217, 160, 37, 123, 118, 73, 2, 157, 46, 116, 9, 145, 134, 228,
+    207, 212, 202, 215, 69, 229, 27, 188, 67, 124, 168, 252, 42, 4, 29, 108, 21, 247, 19, 205, 39,
+    203, 233, 40, 186, 147, 198, 192, 155, 33, 164, 191, 98, 204, 165, 180, 117, 76, 140, 36, 210,
+    172, 41, 54, 159, 8, 185, 232, 113, 196, 231, 47, 146, 120, 51, 65, 28, 144, 254, 221, 93, 189,
+    194, 139, 112, 43, 71, 109, 184, 209,
+];
+
+fn b_mapping(salt: u8, i: u8, j: u8, k: u8) -> u8 {
+    let mut h = V_TABLE[salt as usize];
+    h = V_TABLE[(h ^ i) as usize];
+    h = V_TABLE[(h ^ j) as usize];
+    V_TABLE[(h ^ k) as usize]
+}
+
+/// Logarithmic encoding of the data length.
+fn l_capturing(len: usize) -> u8 {
+    let len = len as f64;
+    let l = if len <= 656.0 {
+        len.ln() / 1.5f64.ln()
+    } else if len <= 3199.0 {
+        len.ln() / 1.3f64.ln() - 8.72777
+    } else {
+        len.ln() / 1.1f64.ln() - 62.5472
+    };
+
+    (l.floor() as u32 & 0xff) as u8
+}
+
+/// Distance between `x` and `y` on a circle of size `r`.
+fn mod_diff(x: u8, y: u8, r: u32) -> u32 {
+    let (x, y) = (x as u32, y as u32);
+    let (dl, dr) = if y > x {
+        (y - x, x + r - y)
+    } else {
+        (x - y, y + r - x)
+    };
+
+    dl.min(dr)
+}
+
+/// TLSH locality sensitive hash, with 128 buckets and a 1 byte checksum.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlshHash {
+    pub checksum: u8,
+    /// Encoded length of the data.
+    pub l_value: u8,
+    pub q1_ratio: u8,
+    pub q2_ratio: u8,
+    /// 2 bits per bucket, telling which quartile its count is in.
+    pub code: [u8; CODE_SIZE],
+}
+
+impl TlshHash {
+    /// Returns `None` if `data` is shorter than `MIN_DATA_LENGTH` or not diverse enough.
+    pub fn new(data: &[u8]) -> Option<TlshHash> {
+        if data.len() < MIN_DATA_LENGTH {
+            return None;
+        }
+
+        let mut buckets = [0u32; 256];
+        let mut checksum = 0;
+        for w in data.windows(5) {
+            let (b0, b1, b2, b3, b4) = (w[4], w[3], w[2], w[1], w[0]);
+            checksum = b_mapping(0, b0, b1, checksum);
+            buckets[b_mapping(2, b0, b1, b2) as usize] += 1;
+            buckets[b_mapping(3, b0, b1, b3) as usize] += 1;
+            buckets[b_mapping(5, b0, b2, b3) as usize] += 1;
+            buckets[b_mapping(7, b0, b2, b4) as usize] += 1;
+            buckets[b_mapping(11, b0, b1, b4) as usize] += 1;
+            buckets[b_mapping(13, b0, b3, b4) as usize] += 1;
+        }
+        let buckets = &buckets[..BUCKETS];
+
+        // Like the reference implementation, hashes of data using less than half of the buckets
+        // are rejected
+        if buckets.iter().filter(|b| **b != 0).count() <= BUCKETS / 2 {
+            return None;
+        }
+
+        let mut sorted = buckets.to_vec();
+        sorted.sort_unstable();
+        let (q1, q2, q3) = (
+            sorted[BUCKETS / 4 - 1],
+            sorted[BUCKETS / 2 - 1],
+            sorted[3 * BUCKETS / 4 - 1],
+        );
+        if q3 == 0 {
+            return None;
+        }
+
+        let mut code = [0u8; CODE_SIZE];
+        for (i, c) in code.iter_mut().enumerate() {
+            for j in 0..4 {
+                let k = buckets[4 * i + j];
+                let quartile = if q3 < k {
+                    3
+                } else if q2 < k {
+                    2
+                } else if q1 < k {
+                    1
+                } else {
+                    0
+                };
+                *c |= quartile << (j * 2);
+            }
+        }
+
+        Some(TlshHash {
+            checksum,
+            l_value: l_capturing(data.len()),
+            q1_ratio: ((q1 as u64 * 100 / q3 as u64) % 16) as u8,
+            q2_ratio: ((q2 as u64 * 100 / q3 as u64) % 16) as u8,
+            code,
+        })
+    }
+
+    /// TLSH distance, including the length difference. 0 for identical hashes, usually above 200 for
+    /// unrelated data.
+    pub fn distance(&self, other: &TlshHash) -> u32 {
+        let mut diff = match mod_diff(self.l_value, other.l_value, 256) {
+            0 => 0,
+            1 => 1,
+            l_diff => l_diff * 12,
+        };
+        for (a, b) in [
+            (self.q1_ratio, other.q1_ratio),
+            (self.q2_ratio, other.q2_ratio),
+        ] {
+            diff += match mod_diff(a, b, 16) {
+                q_diff if q_diff <= 1 => q_diff,
+                q_diff => (q_diff - 1) * 12,
+            };
+        }
+        if self.checksum != other.checksum {
+            diff += 1;
+        }
+        for (a, b) in self.code.iter().zip(&other.code) {
+            for j in 0..4 {
+                let (x, y) = ((a >> (j * 2)) & 3, (b >> (j * 2)) & 3);
+                diff += match x.abs_diff(y) {
+                    3 => 6,
+                    d => d as u32,
+                };
+            }
+        }
+
+        diff
+    }
+
+    /// Similarity with `other`, from 0 to 100, decreasing linearly with the distance up to
+    /// `MAX_DISTANCE`.
+    pub fn similarity(&self, other: &TlshHash) -> u32 {
+        let distance = self.distance(other).min(MAX_DISTANCE);
+        (100 * (MAX_DISTANCE - distance) + MAX_DISTANCE / 2) / MAX_DISTANCE
+    }
+}
+
+impl HashFn<TlshHash> for TlshHash {
+    /// Data too short to be hashed gets an empty hash.
+    fn hash(bytes: &Vec<u8>) -> TlshHash {
+        TlshHash::new(bytes).unwrap_or_default()
+    }
+
+    fn compare_hash(&self, with: &impl HashFn<TlshHash>) -> i32 {
+        self.similarity(with.get_hash()) as i32
+    }
+
+    fn get_hash(&self) -> &TlshHash {
+        self
+    }
+}
+
+fn swap_nibbles(b: u8) -> u8 {
+    b.rotate_left(4)
+}
+
+/// Hex encoded, with the `T1` prefix and the byte order of the reference implementation.
+impl fmt::Display for TlshHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "T1{:02X}{:02X}{:02X}",
+            swap_nibbles(self.checksum),
+            swap_nibbles(self.l_value),
+            self.q1_ratio << 4 | self.q2_ratio
+        )?;
+        for b in self.code.iter().rev() {
+            write!(f, "{:02X}", b)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for TlshHash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = s
+            .strip_prefix("T1")
+            .and_then(|hex_hash| hex::decode(hex_hash).ok())
+            .filter(|bytes| bytes.len() == 3 + CODE_SIZE)
+            .ok_or_else(|| format!("Invalid TLSH hash {:?}", s))?;
+
+        let mut code = [0u8; CODE_SIZE];
+        for (c, b) in code.iter_mut().zip(bytes[3..].iter().rev()) {
+            *c = *b;
+        }
+
+        Ok(TlshHash {
+            checksum: swap_nibbles(bytes[0]),
+            l_value: swap_nibbles(bytes[1]),
+            q1_ratio: bytes[2] >> 4,
+            q2_ratio: bytes[2] & 0xf,
+            code,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pseudo-random bytes, from the `rand` LCG of the C standard.
+    fn lcg(len: usize, seed: u32) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345) & 0x7fff_ffff;
+                (x >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn text() -> Vec<u8> {
+        let mut text = b"The quick brown fox jumps over the lazy dog. ".repeat(4);
+        text.extend_from_slice(b"Pack my box with five dozen liquor jugs!");
+        text
+    }
+
+    fn hash(data: &[u8]) -> TlshHash {
+        TlshHash::new(data).unwrap()
+    }
+
+    // Digests and distances of this implementation for the inputs above (128 buckets, 1 byte
+    // checksum). They only guard against regressions: they have not been checked against py-tlsh,
+    // `tools/tlsh_vectors.py` prints its values for the same inputs
+    const TEXT: &str = "T197D09E8F712C17E465CF1C8C43CED9B2E3CDCA14623265116570A0832D4C3759CE8591";
+    const TEXT_EDITED: &str =
+        "T175D09E89692C17E461CF14C443CED9B2E6CDCA18E21285716571B183AD4C7759CA81E5";
+    const RANDOM: &str = "T182F07E42106635D0DA2A8CF30C4F9032C03EB28628EF6D98F1B0A00B05AC7C8B34023E";
+    const RANDOM_EDITED: &str =
+        "T1C9F07E42106728E0DA2A4CF30C4F9032C03EB28628EF6C68F0B0900B05AC788B34023E";
+    const LONG: &str = "T173815D2364D5C6BD34D8E9FC13793F15A429BA1E6761841F849A5E04E23FD0FCA671A0";
+
+    fn random_edited() -> Vec<u8> {
+        let mut data = lcg(512, 42);
+        data[100..110].fill(0);
+        data
+    }
+
+    #[test]
+    fn digests_are_stable() {
+        assert_eq!(hash(&text()).to_string(), TEXT);
+        let edited = String::from_utf8(text()).unwrap().replace("lazy", "idle");
+        assert_eq!(hash(edited.as_bytes()).to_string(), TEXT_EDITED);
+        assert_eq!(hash(&lcg(512, 42)).to_string(), RANDOM);
+        assert_eq!(hash(&random_edited()).to_string(), RANDOM_EDITED);
+        // Longer than 3199 bytes, the last range of the length encoding
+        assert_eq!(hash(&lcg(4000, 7)).to_string(), LONG);
+    }
+
+    #[test]
+    fn distances_are_stable() {
+        let distance = |a: &str, b: &str| {
+            a.parse::<TlshHash>()
+                .unwrap()
+                .distance(&b.parse::<TlshHash>().unwrap())
+        };
+        assert_eq!(distance(TEXT, TEXT), 0);
+        assert_eq!(distance(TEXT, TEXT_EDITED), 37);
+        assert_eq!(distance(RANDOM, RANDOM_EDITED), 13);
+        assert_eq!(distance(TEXT, RANDOM), 240);
+        assert_eq!(distance(RANDOM, LONG), 381);
+        assert_eq!(distance(TEXT_EDITED, TEXT), distance(TEXT, TEXT_EDITED));
+    }
+
+    #[test]
+    fn similarity_scales_distance() {
+        let text = hash(&text());
+        assert_eq!(text.similarity(&text), 100);
+        assert_eq!(text.similarity(&RANDOM.parse().unwrap()), 0);
+        // Distance 13
+        let random: TlshHash = RANDOM.parse().unwrap();
+        assert_eq!(random.similarity(&RANDOM_EDITED.parse().unwrap()), 94);
+    }
+
+    #[test]
+    fn short_or_uniform_data_is_not_hashed() {
+        assert!(TlshHash::new(&lcg(MIN_DATA_LENGTH - 1, 1)).is_none());
+        assert!(TlshHash::new(&[b'a'; 100]).is_none());
+    }
+
+    #[test]
+    fn digest_round_trips() {
+        let parsed: TlshHash = LONG.parse().unwrap();
+        assert_eq!(parsed, hash(&lcg(4000, 7)));
+        assert_eq!(parsed.to_string(), LONG);
+        assert!("T1ABCD".parse::<TlshHash>().is_err());
+        assert!(LONG[2..].parse::<TlshHash>().is_err());
+    }
+}
@@ -1,2 +1,3 @@
-pub mod export;
+pub mod patch;
+pub mod pdb_writer;
 pub mod toml_utils;
@@ -0,0 +1,550 @@
+use goblin::container::{Container, Ctx};
+use goblin::elf::section_header::{SectionHeader, SHT_STRTAB, SHT_SYMTAB};
+use goblin::elf::sym::{Sym, STB_GLOBAL, STT_FUNC};
+use goblin::elf::Elf;
+use goblin::pe::PE;
+use goblin::Object;
+use scroll::ctx::IntoCtx;
+use std::io::{Error, ErrorKind};
+
+/// A function to add to the symbol table of an executable.
+pub struct NamedFunction<'a> {
+    pub name: &'a str,
+    pub rva: u32,
+    /// 0 when unknown.
+    pub size: u32,
+}
+
+/// Copy of `bytes` whose symbol table also holds `functions`: a COFF symbol table for PE files,
+/// `.symtab` for ELF files. Symbols already in the executable are kept.
+pub fn add_symbols(bytes: &[u8], functions: &[NamedFunction]) -> Result<Vec<u8>, Error> {
+    match Object::parse(bytes) {
+        Ok(Object::PE(pe)) => add_coff_symbols(bytes, &pe, functions),
+        Ok(Object::Elf(elf)) => add_elf_symbols(bytes, &elf, functions),
+        Ok(_) => Err(Error::new(
+            ErrorKind::Unsupported,
+            "Only PE and ELF executables can be patched",
+        )),
+        Err(e) => Err(Error::new(ErrorKind::InvalidData, e)),
+    }
+}
+
+const COFF_SYMBOL_SIZE: usize = 18;
+/// `IMAGE_SYM_DTYPE_FUNCTION << 4`
+const COFF_FUNCTION_TYPE: u16 = 0x20;
+const COFF_EXTERNAL_CLASS: u8 = 2;
+
+fn add_coff_symbols(bytes: &[u8], pe: &PE, functions: &[NamedFunction]) -> Result<Vec<u8>, Error> {
+    let invalid = |what: &str| Error::new(ErrorKind::InvalidData, what.to_string());
+    let coff = pe.header.coff_header;
+    // PointerToSymbolTable, right after the machine, number of sections and timestamp
+    let coff_offset = pe.header.dos_header.pe_pointer as usize + 4;
+    let symbol_table_field = coff_offset + 8;
+
+    // Existing symbols and string table, kept as is
+    let mut records = vec![];
+    let mut strings = vec![];
+    if coff.pointer_to_symbol_table != 0 && coff.number_of_symbol_table != 0 {
+        let start = coff.pointer_to_symbol_table as usize;
+        let strings_start = start + coff.number_of_symbol_table as usize * COFF_SYMBOL_SIZE;
+        records.extend_from_slice(
+            bytes
+                .get(start..strings_start)
+                .ok_or_else(|| invalid("Symbol table out of bounds"))?,
+        );
+        let strings_size = bytes
+            .get(strings_start..strings_start + 4)
+            .map(|size| u32::from_le_bytes(size.try_into().unwrap()) as usize)
+            .ok_or_else(|| invalid("String table out of bounds"))?;
+        strings.extend_from_slice(
+            bytes
+                .get(strings_start + 4..strings_start + strings_size.max(4))
+                .ok_or_else(|| invalid("String table out of bounds"))?,
+        );
+    }
+
+    let mut added = 0;
+    for function in functions {
+        let section = match pe.sections.iter().position(|s| {
+            let size = s.virtual_size.max(s.size_of_raw_data);
+            s.virtual_address
+                .checked_add(size)
+                .is_some_and(|end| (s.virtual_address..end).contains(&function.rva))
+        }) {
+            Some(section) => section,
+            None => continue,
+        };
+
+        let mut name = [0u8; 8];
+        if function.name.len() <= name.len() {
+            name[..function.name.len()].copy_from_slice(function.name.as_bytes());
+        } else {
+            // Offsets in the string table count its size field
+            name[4..].copy_from_slice(&(strings.len() as u32 + 4).to_le_bytes());
+            strings.extend_from_slice(function.name.as_bytes());
+            strings.push(0);
+        }
+        records.extend_from_slice(&name);
+        records.extend_from_slice(
+            &(function.rva - pe.sections[section].virtual_address).to_le_bytes(),
+        );
+        records.extend_from_slice(&(section as u16 + 1).to_le_bytes());
+        records.extend_from_slice(&COFF_FUNCTION_TYPE.to_le_bytes());
+        records.push(COFF_EXTERNAL_CLASS);
+        records.push(0);
+        added += 1;
+    }
+    if added < functions.len() {
+        log::debug!(
+            "{} functions outside of any section",
+            functions.len() - added
+        );
+    }
+
+    let mut patched = bytes.to_vec();
+    let symbol_table = patched.len() as u32;
+    patched.extend_from_slice(&records);
+    patched.extend_from_slice(&(strings.len() as u32 + 4).to_le_bytes());
+    patched.extend_from_slice(&strings);
+
+    let symbol_count = (records.len() / COFF_SYMBOL_SIZE) as u32;
+    patched[symbol_table_field..symbol_table_field + 4]
+        .copy_from_slice(&symbol_table.to_le_bytes());
+    patched[symbol_table_field + 4..symbol_table_field + 8]
+        .copy_from_slice(&symbol_count.to_le_bytes());
+
+    Ok(patched)
+}
+
+/// What RVAs of an ELF file are relative to: the page of its first loaded segment.
+pub fn elf_image_base(elf: &Elf) -> u64 {
+    elf.program_headers
+        .iter()
+        .filter(|ph| ph.p_type == goblin::elf::program_header::PT_LOAD)
+        .map(|ph| ph.p_vaddr & !(ph.p_align.max(1) - 1))
+        .min()
+        .unwrap_or(0)
+}
+
+fn section_data<'a>(bytes: &'a [u8], section: &SectionHeader) -> Result<&'a [u8], Error> {
+    let start = section.sh_offset as usize;
+    bytes
+        .get(start..start + section.sh_size as usize)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Section out of bounds"))
+}
+
+/// Appends `data` to `out`, aligned to `align`, and returns its offset.
+fn append_aligned(out: &mut Vec<u8>, data: &[u8], align: usize) -> u64 {
+    out.resize(out.len().next_multiple_of(align), 0);
+    let offset = out.len() as u64;
+    out.extend_from_slice(data);
+    offset
+}
+
+/// Appends `name` to a string table and returns its offset.
+fn add_string(table: &mut Vec<u8>, name: &str) -> usize {
+    let offset = table.len();
+    table.extend_from_slice(name.as_bytes());
+    table.push(0);
+    offset
+}
+
+fn add_elf_symbols(bytes: &[u8], elf: &Elf, functions: &[NamedFunction]) -> Result<Vec<u8>, Error> {
+    let container = if elf.is_64 {
+        Container::Big
+    } else {
+        Container::Little
+    };
+    let ctx = Ctx::new(
+        container,
+        goblin::container::Endian::from(elf.little_endian),
+    );
+    let align = if elf.is_64 { 8 } else { 4 };
+    let sym_size = Sym::size(container);
+
+    let mut sections = elf.section_headers.clone();
+    if sections.is_empty() {
+        sections.push(SectionHeader::default());
+    }
+
+    // Section names, created if the executable has none
+    let mut shstrtab = match sections.get(elf.header.e_shstrndx as usize) {
+        Some(section) if elf.header.e_shstrndx != 0 => section_data(bytes, section)?.to_vec(),
+        _ => vec![0],
+    };
+    let shstrndx = match elf.header.e_shstrndx {
+        0 => {
+            sections.push(SectionHeader {
+                sh_name: add_string(&mut shstrtab, ".shstrtab"),
+                sh_type: SHT_STRTAB,
+                sh_addralign: 1,
+                ..Default::default()
+            });
+            sections.len() - 1
+        }
+        shstrndx => shstrndx as usize,
+    };
+
+    // Existing symbols are kept, new ones being global they can simply be appended
+    let (symtab, strtab) = match sections.iter().position(|s| s.sh_type == SHT_SYMTAB) {
+        Some(symtab) => (symtab, sections[symtab].sh_link as usize),
+        None => {
+            sections.push(SectionHeader {
+                sh_name: add_string(&mut shstrtab, ".strtab"),
+                sh_type: SHT_STRTAB,
+                sh_addralign: 1,
+                ..Default::default()
+            });
+            sections.push(SectionHeader {
+                sh_name: add_string(&mut shstrtab, ".symtab"),
+                sh_type: SHT_SYMTAB,
+                sh_link: sections.len() as u32 - 1,
+                // Only the null symbol is local
+                sh_info: 1,
+                sh_entsize: sym_size as u64,
+                sh_addralign: align as u64,
+                ..Default::default()
+            });
+            (sections.len() - 1, sections.len() - 2)
+        }
+    };
+    let mut symbols = match elf.section_headers.get(symtab) {
+        Some(section) => section_data(bytes, section)?.to_vec(),
+        None => vec![0; sym_size],
+    };
+    // Symbol names may share the section name table, which then also gets the new names
+    let shared_strings = strtab == shstrndx;
+    let mut strings = match elf.section_headers.get(strtab) {
+        Some(section) => section_data(bytes, section)?.to_vec(),
+        None => vec![0],
+    };
+
+    let image_base = elf_image_base(elf);
+    for function in functions {
+        let address = image_base + function.rva as u64;
+        let section = sections.iter().position(|s| {
+            s.is_alloc() && s.sh_type != goblin::elf::section_header::SHT_NOBITS && {
+                (s.sh_addr..s.sh_addr + s.sh_size).contains(&address)
+            }
+        });
+        let sym = Sym {
+            st_name: add_string(&mut strings, function.name),
+            st_info: (STB_GLOBAL << 4) | STT_FUNC,
+            st_other: 0,
+            st_shndx: section.unwrap_or(goblin::elf::section_header::SHN_ABS as usize),
+            st_value: address,
+            st_size: function.size as u64,
+        };
+        let mut entry = vec![0; sym_size];
+        sym.into_ctx(&mut entry, ctx);
+        symbols.extend_from_slice(&entry);
+    }
+
+    let mut patched = bytes.to_vec();
+    sections[symtab].sh_offset = append_aligned(&mut patched, &symbols, align);
+    sections[symtab].sh_size = symbols.len() as u64;
+    if shared_strings {
+        shstrtab = strings;
+    } else {
+        sections[strtab].sh_offset = append_aligned(&mut patched, &strings, 1);
+        sections[strtab].sh_size = strings.len() as u64;
+    }
+    sections[shstrndx].sh_offset = append_aligned(&mut patched, &shstrtab, 1);
+    sections[shstrndx].sh_size = shstrtab.len() as u64;
+
+    let shdr_size = SectionHeader::size(ctx);
+    let mut headers = vec![0; shdr_size * sections.len()];
+    for (i, section) in sections.iter().enumerate() {
+        section.clone().into_ctx(&mut headers[i * shdr_size..], ctx);
+    }
+
+    let mut header = elf.header;
+    header.e_shoff = append_aligned(&mut patched, &headers, align);
+    header.e_shnum = sections.len() as u16;
+    header.e_shentsize = shdr_size as u16;
+    header.e_shstrndx = shstrndx as u16;
+    header.into_ctx(&mut patched, ctx);
+
+    Ok(patched)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::utils::pdb_writer::tests::minimal_pe;
+    use goblin::container::Endian;
+    use goblin::elf::header::{Header, ET_EXEC};
+    use goblin::elf::program_header::{ProgramHeader, PT_LOAD};
+    use goblin::elf::section_header::{SHF_ALLOC, SHF_EXECINSTR, SHT_PROGBITS};
+    use scroll::Pwrite;
+
+    /// (name, section number, value) of the COFF symbols of `bytes`.
+    fn coff_symbols(bytes: &[u8]) -> Vec<(String, i16, u32)> {
+        let coff = PE::parse(bytes).unwrap().header.coff_header;
+        let strings = coff.strings(bytes).unwrap();
+        coff.symbols(bytes)
+            .unwrap()
+            .iter()
+            .map(|(_, _, symbol)| {
+                (
+                    symbol.name(&strings).unwrap().to_string(),
+                    symbol.section_number,
+                    symbol.value,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn coff_symbols_round_trip() {
+        let functions = [
+            NamedFunction {
+                name: "main",
+                rva: 0x1000,
+                size: 0,
+            },
+            NamedFunction {
+                name: "exactly8",
+                rva: 0x1010,
+                size: 0,
+            },
+            NamedFunction {
+                name: "_ZN4core3fmt5write17h0123456789abcdefE",
+                rva: 0x2020,
+                size: 0x40,
+            },
+            NamedFunction {
+                name: "outside",
+                rva: 0x8000,
+                size: 0,
+            },
+        ];
+        let patched = add_symbols(&minimal_pe(), &functions).unwrap();
+        assert_eq!(
+            coff_symbols(&patched),
+            [
+                ("main".to_string(), 1, 0),
+                ("exactly8".to_string(), 1, 0x10),
+                (functions[2].name.to_string(), 2, 0x20),
+            ]
+        );
+
+        // Symbols of the first pass, and their string table, are kept
+        let function = NamedFunction {
+            name: "_ZN3std2rt10lang_start17h0123456789abcdefE",
+            rva: 0x1100,
+            size: 0,
+        };
+        let patched = add_symbols(&patched, std::slice::from_ref(&function)).unwrap();
+        let symbols = coff_symbols(&patched);
+        assert_eq!(symbols.len(), 4);
+        assert_eq!(symbols[2].0, functions[2].name);
+        assert_eq!(symbols[3], (function.name.to_string(), 1, 0x100));
+    }
+
+    /// Where symbol names of `minimal_elf` are stored.
+    #[derive(Clone, Copy)]
+    pub(crate) enum ElfSymbols {
+        None,
+        /// In a `.strtab` section.
+        OwnStrtab,
+        /// In `.shstrtab`, along with section names.
+        SharedWithSectionNames,
+    }
+
+    /// ELF64 executable loaded at 0x400000, with a `.text` section at 0x401000 and, unless
+    /// `symbols` is `None`, a `.symtab` holding `_start`.
+    pub(crate) fn minimal_elf(symbols: ElfSymbols) -> Vec<u8> {
+        minimal_elf_with_sections(symbols, &[])
+    }
+
+    /// `minimal_elf` with `extra` sections, given by name and size, after `.shstrtab`. Their data
+    /// is the start of the file.
+    pub(crate) fn minimal_elf_with_sections(symbols: ElfSymbols, extra: &[(&str, u64)]) -> Vec<u8> {
+        let ctx = Ctx::new(Container::Big, Endian::Little);
+        let sym_size = Sym::size(Container::Big);
+        let mut bytes = vec![0u8; 0x1100];
+
+        let mut shstrtab = vec![0];
+        let mut sections = vec![
+            SectionHeader::default(),
+            SectionHeader {
+                sh_name: add_string(&mut shstrtab, ".text"),
+                sh_type: SHT_PROGBITS,
+                sh_flags: (SHF_ALLOC | SHF_EXECINSTR) as u64,
+                sh_addr: 0x401000,
+                sh_offset: 0x1000,
+                sh_size: 0x100,
+                sh_addralign: 16,
+                ..Default::default()
+            },
+        ];
+
+        if !matches!(symbols, ElfSymbols::None) {
+            let mut strtab = vec![0];
+            let names = match symbols {
+                ElfSymbols::SharedWithSectionNames => &mut shstrtab,
+                _ => &mut strtab,
+            };
+            let start = Sym {
+                st_name: add_string(names, "_start"),
+                st_info: (STB_GLOBAL << 4) | STT_FUNC,
+                st_shndx: 1,
+                st_value: 0x401000,
+                st_size: 0x10,
+                ..Default::default()
+            };
+            let mut symtab = vec![0; 2 * sym_size];
+            start.into_ctx(&mut symtab[sym_size..], ctx);
+
+            // Either `.strtab` or `.shstrtab` comes right after `.symtab`
+            sections.push(SectionHeader {
+                sh_name: add_string(&mut shstrtab, ".symtab"),
+                sh_type: SHT_SYMTAB,
+                sh_link: 3,
+                sh_info: 1,
+                sh_entsize: sym_size as u64,
+                sh_addralign: 8,
+                sh_offset: append_aligned(&mut bytes, &symtab, 8),
+                sh_size: symtab.len() as u64,
+                ..Default::default()
+            });
+            if matches!(symbols, ElfSymbols::OwnStrtab) {
+                sections.push(SectionHeader {
+                    sh_name: add_string(&mut shstrtab, ".strtab"),
+                    sh_type: SHT_STRTAB,
+                    sh_addralign: 1,
+                    sh_offset: append_aligned(&mut bytes, &strtab, 1),
+                    sh_size: strtab.len() as u64,
+                    ..Default::default()
+                });
+            }
+        }
+
+        let name = add_string(&mut shstrtab, ".shstrtab");
+        let extra: Vec<SectionHeader> = extra
+            .iter()
+            .map(|(name, size)| SectionHeader {
+                sh_name: add_string(&mut shstrtab, name),
+                sh_type: SHT_PROGBITS,
+                sh_flags: SHF_ALLOC as u64,
+                sh_addralign: 1,
+                sh_size: *size,
+                ..Default::default()
+            })
+            .collect();
+        let shstrndx = sections.len();
+        sections.push(SectionHeader {
+            sh_name: name,
+            sh_type: SHT_STRTAB,
+            sh_addralign: 1,
+            sh_offset: append_aligned(&mut bytes, &shstrtab, 1),
+            sh_size: shstrtab.len() as u64,
+            ..Default::default()
+        });
+        sections.extend(extra);
+
+        let shdr_size = SectionHeader::size(ctx);
+        let mut headers = vec![0; shdr_size * sections.len()];
+        for (i, section) in sections.iter().enumerate() {
+            section.clone().into_ctx(&mut headers[i * shdr_size..], ctx);
+        }
+
+        let mut header = Header::new(ctx);
+        header.e_type = ET_EXEC;
+        header.e_machine = goblin::elf::header::EM_X86_64;
+        header.e_entry = 0x401000;
+        header.e_phoff = Header::size(ctx) as u64;
+        header.e_phnum = 1;
+        header.e_shoff = append_aligned(&mut bytes, &headers, 8);
+        header.e_shnum = sections.len() as u16;
+        header.e_shstrndx = shstrndx as u16;
+        header.into_ctx(&mut bytes, ctx);
+
+        let load = ProgramHeader {
+            p_type: PT_LOAD,
+            p_flags: 5,
+            p_vaddr: 0x400000,
+            p_paddr: 0x400000,
+            p_filesz: 0x1100,
+            p_memsz: 0x1100,
+            p_align: 0x1000,
+            ..Default::default()
+        };
+        bytes.pwrite_with(load, Header::size(ctx), ctx).unwrap();
+
+        bytes
+    }
+
+    /// (name, section index, address) of the `.symtab` symbols of `bytes`, and the names of
+    /// its sections.
+    fn elf_symbols(bytes: &[u8]) -> (Vec<(String, usize, u64)>, Vec<String>) {
+        let elf = Elf::parse(bytes).unwrap();
+        let symbols = elf
+            .syms
+            .iter()
+            .skip(1)
+            .map(|sym| {
+                let name = elf.strtab.get_at(sym.st_name).unwrap();
+                (name.to_string(), sym.st_shndx, sym.st_value)
+            })
+            .collect();
+        let sections = elf
+            .section_headers
+            .iter()
+            .map(|s| elf.shdr_strtab.get_at(s.sh_name).unwrap().to_string())
+            .collect();
+
+        (symbols, sections)
+    }
+
+    const ELF_FUNCTIONS: [NamedFunction; 2] = [
+        NamedFunction {
+            name: "main",
+            rva: 0x1020,
+            size: 0x30,
+        },
+        NamedFunction {
+            name: "_ZN4core3fmt5write17h0123456789abcdefE",
+            rva: 0x1080,
+            size: 0,
+        },
+    ];
+
+    #[test]
+    fn elf_symbols_are_added_to_a_new_symtab() {
+        let bytes = minimal_elf(ElfSymbols::None);
+        assert!(Elf::parse(&bytes).unwrap().syms.is_empty());
+
+        let patched = add_symbols(&bytes, &ELF_FUNCTIONS).unwrap();
+        let (symbols, sections) = elf_symbols(&patched);
+        assert_eq!(
+            symbols,
+            [
+                ("main".to_string(), 1, 0x401020),
+                (ELF_FUNCTIONS[1].name.to_string(), 1, 0x401080),
+            ]
+        );
+        assert_eq!(sections, ["", ".text", ".shstrtab", ".strtab", ".symtab"]);
+    }
+
+    #[test]
+    fn elf_symbols_are_appended_to_the_existing_symtab() {
+        for layout in [ElfSymbols::OwnStrtab, ElfSymbols::SharedWithSectionNames] {
+            let bytes = minimal_elf(layout);
+            let (_, sections_before) = elf_symbols(&bytes);
+
+            let patched = add_symbols(&bytes, &ELF_FUNCTIONS).unwrap();
+            let (symbols, sections) = elf_symbols(&patched);
+            assert_eq!(
+                symbols,
+                [
+                    ("_start".to_string(), 1, 0x401000),
+                    ("main".to_string(), 1, 0x401020),
+                    (ELF_FUNCTIONS[1].name.to_string(), 1, 0x401080),
+                ]
+            );
+            assert_eq!(sections, sections_before);
+        }
+    }
+}
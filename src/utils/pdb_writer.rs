@@ -0,0 +1,787 @@
+use goblin::pe::PE;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+
+use crate::functions_utils::search::rva_to_pa;
+use crate::utils::patch::NamedFunction;
+
+/// What ties a PDB to an executable: the GUID and age of its CodeView debug directory entry.
+#[derive(Debug, Clone)]
+pub struct PdbIdentity {
+    pub guid: [u8; 16],
+    pub age: u32,
+    /// PDB path recorded in the executable, which debuggers look for.
+    pub path: String,
+}
+
+impl PdbIdentity {
+    /// Identity derived from the content of an executable without CodeView entry, so that
+    /// recovering symbols of the same file twice gives the same GUID.
+    pub fn from_content(bytes: &[u8], path: &str) -> PdbIdentity {
+        let digest = Sha256::digest(bytes);
+        let mut guid = [0u8; 16];
+        guid.copy_from_slice(&digest[..16]);
+        // Version 4, variant 1, like a random UUID
+        guid[7] = (guid[7] & 0x0f) | 0x40;
+        guid[8] = (guid[8] & 0x3f) | 0x80;
+
+        PdbIdentity {
+            guid,
+            age: 1,
+            path: path.to_string(),
+        }
+    }
+
+    /// `RSDS` CodeView record pointing to this PDB.
+    fn codeview_record(&self) -> Vec<u8> {
+        let mut record = b"RSDS".to_vec();
+        record.extend_from_slice(&self.guid);
+        record.extend_from_slice(&self.age.to_le_bytes());
+        record.extend_from_slice(self.path.as_bytes());
+        record.push(0);
+        record
+    }
+}
+
+const IMAGE_DEBUG_TYPE_CODEVIEW: u32 = 2;
+const DEBUG_DIRECTORY_ENTRY_SIZE: usize = 28;
+const DEBUG_DATA_DIRECTORY: usize = 6;
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn invalid(what: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, what.to_string())
+}
+
+fn parse_pe(bytes: &[u8]) -> Result<PE<'_>, Error> {
+    PE::parse(bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Offsets of the fields of a PE header that are needed to patch it.
+struct PeLayout {
+    optional_header: usize,
+    is_64: bool,
+    section_table: usize,
+    section_count: usize,
+}
+
+impl PeLayout {
+    fn new(bytes: &[u8], pe: &PE) -> Result<PeLayout, Error> {
+        let coff = pe.header.dos_header.pe_pointer as usize + 4;
+        let optional_header = coff + 20;
+        let optional_header_size =
+            read_u16(bytes, coff + 16).ok_or_else(|| invalid("Truncated COFF header"))? as usize;
+        Ok(PeLayout {
+            optional_header,
+            is_64: pe.is_64,
+            section_table: optional_header + optional_header_size,
+            section_count: pe.sections.len(),
+        })
+    }
+
+    fn data_directory(&self, index: usize) -> usize {
+        let data_directories = if self.is_64 { 112 } else { 96 };
+        self.optional_header + data_directories + index * 8
+    }
+}
+
+/// Entries of the debug directory of `pe`, as raw `IMAGE_DEBUG_DIRECTORY` structures.
+fn debug_directory<'a>(bytes: &'a [u8], pe: &PE) -> Result<Vec<&'a [u8]>, Error> {
+    let layout = PeLayout::new(bytes, pe)?;
+    let directory = layout.data_directory(DEBUG_DATA_DIRECTORY);
+    let (rva, size) = match (read_u32(bytes, directory), read_u32(bytes, directory + 4)) {
+        (Some(rva), Some(size)) if rva != 0 && size != 0 => (rva, size as usize),
+        _ => return Ok(vec![]),
+    };
+    let start = rva_to_pa(pe, rva).ok_or_else(|| invalid("Debug directory out of sections"))?;
+    let entries = bytes
+        .get(start as usize..start as usize + size)
+        .ok_or_else(|| invalid("Debug directory out of bounds"))?;
+
+    Ok(entries.chunks_exact(DEBUG_DIRECTORY_ENTRY_SIZE).collect())
+}
+
+/// Identity of the PDB `bytes` refers to, if it has a CodeView debug directory entry.
+pub fn codeview_identity(bytes: &[u8]) -> Result<Option<PdbIdentity>, Error> {
+    let pe = parse_pe(bytes)?;
+    for entry in debug_directory(bytes, &pe)? {
+        if read_u32(entry, 12) != Some(IMAGE_DEBUG_TYPE_CODEVIEW) {
+            continue;
+        }
+        let (size, offset) = (read_u32(entry, 16), read_u32(entry, 24));
+        let record = match (size, offset) {
+            (Some(size), Some(offset)) => match offset.checked_add(size) {
+                Some(end) => bytes.get(offset as usize..end as usize),
+                None => None,
+            },
+            _ => None,
+        };
+        let record = match record {
+            Some(record) if record.len() >= 24 && record.starts_with(b"RSDS") => record,
+            _ => continue,
+        };
+        let path = record[24..].split(|b| *b == 0).next().unwrap_or_default();
+        return Ok(Some(PdbIdentity {
+            guid: record[4..20].try_into().unwrap(),
+            age: read_u32(record, 20).unwrap(),
+            path: String::from_utf8_lossy(path).to_string(),
+        }));
+    }
+
+    Ok(None)
+}
+
+fn align(value: usize, alignment: usize) -> usize {
+    value.next_multiple_of(alignment.max(1))
+}
+
+const SECTION_HEADER_SIZE: usize = 40;
+/// `IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ`
+const DEBUG_SECTION_CHARACTERISTICS: u32 = 0x4000_0040;
+
+/// Copy of `bytes` with a new section holding its debug directory and a CodeView entry pointing
+/// to the PDB described by `identity`. Other debug directory entries are kept.
+pub fn add_codeview_entry(bytes: &[u8], identity: &PdbIdentity) -> Result<Vec<u8>, Error> {
+    let pe = parse_pe(bytes)?;
+    let layout = PeLayout::new(bytes, &pe)?;
+    let section_alignment = read_u32(bytes, layout.optional_header + 32).unwrap_or(0x1000);
+    let file_alignment = read_u32(bytes, layout.optional_header + 36).unwrap_or(0x200);
+    let size_of_headers = read_u32(bytes, layout.optional_header + 60).unwrap_or(0) as usize;
+
+    let header = layout.section_table + layout.section_count * SECTION_HEADER_SIZE;
+    let header_room = bytes.get(header..header + SECTION_HEADER_SIZE);
+    if header + SECTION_HEADER_SIZE > size_of_headers
+        || header_room.is_none_or(|room| room.iter().any(|b| *b != 0))
+    {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "No room for a new section header",
+        ));
+    }
+
+    let image_end = pe
+        .sections
+        .iter()
+        .map(|s| s.virtual_address as usize + s.virtual_size.max(s.size_of_raw_data) as usize)
+        .max()
+        .unwrap_or(size_of_headers);
+    let rva = align(image_end, section_alignment as usize);
+    let offset = align(bytes.len(), file_alignment as usize);
+
+    // Existing entries, then the CodeView one, then its record
+    let mut entries: Vec<Vec<u8>> = debug_directory(bytes, &pe)?
+        .into_iter()
+        .filter(|entry| read_u32(entry, 12) != Some(IMAGE_DEBUG_TYPE_CODEVIEW))
+        .map(|entry| entry.to_vec())
+        .collect();
+    let record = identity.codeview_record();
+    let record_position = (entries.len() + 1) * DEBUG_DIRECTORY_ENTRY_SIZE;
+    let mut codeview = vec![0u8; DEBUG_DIRECTORY_ENTRY_SIZE];
+    write_u32(&mut codeview, 4, pe.header.coff_header.time_date_stamp);
+    write_u32(&mut codeview, 12, IMAGE_DEBUG_TYPE_CODEVIEW);
+    write_u32(&mut codeview, 16, record.len() as u32);
+    write_u32(&mut codeview, 20, (rva + record_position) as u32);
+    write_u32(&mut codeview, 24, (offset + record_position) as u32);
+    entries.push(codeview);
+    let mut content = entries.concat();
+    content.extend_from_slice(&record);
+    let raw_size = align(content.len(), file_alignment as usize);
+
+    let mut patched = bytes.to_vec();
+    patched.resize(offset, 0);
+    patched.extend_from_slice(&content);
+    patched.resize(offset + raw_size, 0);
+
+    let mut section = vec![0u8; SECTION_HEADER_SIZE];
+    section[..8].copy_from_slice(b".ariane\0");
+    write_u32(&mut section, 8, content.len() as u32);
+    write_u32(&mut section, 12, rva as u32);
+    write_u32(&mut section, 16, raw_size as u32);
+    write_u32(&mut section, 20, offset as u32);
+    write_u32(&mut section, 36, DEBUG_SECTION_CHARACTERISTICS);
+    patched[header..header + SECTION_HEADER_SIZE].copy_from_slice(&section);
+
+    let coff = pe.header.dos_header.pe_pointer as usize + 4;
+    let section_count = (layout.section_count + 1) as u16;
+    patched[coff + 2..coff + 4].copy_from_slice(&section_count.to_le_bytes());
+    let size_of_image = align(rva + content.len(), section_alignment as usize);
+    write_u32(
+        &mut patched,
+        layout.optional_header + 56,
+        size_of_image as u32,
+    );
+    let directory = layout.data_directory(DEBUG_DATA_DIRECTORY);
+    write_u32(&mut patched, directory, rva as u32);
+    write_u32(&mut patched, directory + 4, record_position as u32);
+
+    Ok(patched)
+}
+
+const BLOCK_SIZE: usize = 4096;
+const MSF_MAGIC: &[u8; 32] = b"Microsoft C/C++ MSF 7.00\r\n\x1aDS\0\0\0";
+/// Free block maps take the second and third block of every `BLOCK_SIZE` blocks.
+const FREE_BLOCK_MAP: usize = 1;
+
+/// Multi-stream file, the container format of PDBs.
+fn write_msf(streams: &[Vec<u8>]) -> Vec<u8> {
+    let mut next_block = 3;
+    let mut allocate = || {
+        while next_block % BLOCK_SIZE == FREE_BLOCK_MAP || next_block % BLOCK_SIZE == 2 {
+            next_block += 1;
+        }
+        next_block += 1;
+        next_block - 1
+    };
+
+    let mut blocks: BTreeMap<usize, &[u8]> = BTreeMap::new();
+    let mut directory = vec![];
+    directory.extend_from_slice(&(streams.len() as u32).to_le_bytes());
+    for stream in streams {
+        directory.extend_from_slice(&(stream.len() as u32).to_le_bytes());
+    }
+    for stream in streams {
+        for chunk in stream.chunks(BLOCK_SIZE) {
+            let block = allocate();
+            blocks.insert(block, chunk);
+            directory.extend_from_slice(&(block as u32).to_le_bytes());
+        }
+    }
+
+    let mut directory_blocks = vec![];
+    for chunk in directory.chunks(BLOCK_SIZE) {
+        let block = allocate();
+        blocks.insert(block, chunk);
+        directory_blocks.extend_from_slice(&(block as u32).to_le_bytes());
+    }
+    let block_map = allocate();
+    blocks.insert(block_map, &directory_blocks);
+    let block_count = block_map + 1;
+
+    let mut msf = vec![0u8; block_count * BLOCK_SIZE];
+    for (block, data) in blocks {
+        msf[block * BLOCK_SIZE..block * BLOCK_SIZE + data.len()].copy_from_slice(data);
+    }
+
+    // Every block of the file is in use, the rest of the map is free
+    let map = &mut msf[FREE_BLOCK_MAP * BLOCK_SIZE..(FREE_BLOCK_MAP + 1) * BLOCK_SIZE];
+    for (i, b) in map.iter_mut().enumerate() {
+        for bit in 0..8 {
+            if i * 8 + bit >= block_count {
+                *b |= 1 << bit;
+            }
+        }
+    }
+
+    msf[..32].copy_from_slice(MSF_MAGIC);
+    write_u32(&mut msf, 32, BLOCK_SIZE as u32);
+    write_u32(&mut msf, 36, FREE_BLOCK_MAP as u32);
+    write_u32(&mut msf, 40, block_count as u32);
+    write_u32(&mut msf, 44, directory.len() as u32);
+    write_u32(&mut msf, 52, block_map as u32);
+
+    msf
+}
+
+/// Hash of names used by PDB hash tables.
+fn hash_string_v1(s: &[u8]) -> u32 {
+    let mut result: u32 = 0;
+    let mut chunks = s.chunks_exact(4);
+    for chunk in &mut chunks {
+        result ^= u32::from_le_bytes(chunk.try_into().unwrap());
+    }
+    let mut remainder = chunks.remainder();
+    if remainder.len() >= 2 {
+        result ^= u16::from_le_bytes([remainder[0], remainder[1]]) as u32;
+        remainder = &remainder[2..];
+    }
+    if let Some(b) = remainder.first() {
+        result ^= *b as u32;
+    }
+
+    result |= 0x20202020;
+    result ^= result >> 11;
+    result ^ (result >> 16)
+}
+
+const PDB_STREAM: usize = 1;
+const TPI_STREAM: usize = 2;
+const DBI_STREAM: usize = 3;
+const IPI_STREAM: usize = 4;
+const NAMES_STREAM: usize = 5;
+const SYMBOL_RECORDS_STREAM: usize = 6;
+const GLOBALS_STREAM: usize = 7;
+const PUBLICS_STREAM: usize = 8;
+const SECTION_HEADERS_STREAM: usize = 9;
+const STREAM_COUNT: usize = 10;
+
+const PDB_VERSION_VC70: u32 = 20000404;
+const PDB_FEATURE_VC140: u32 = 20140508;
+
+fn pdb_info_stream(identity: &PdbIdentity, timestamp: u32) -> Vec<u8> {
+    let mut stream = vec![];
+    stream.extend_from_slice(&PDB_VERSION_VC70.to_le_bytes());
+    stream.extend_from_slice(&timestamp.to_le_bytes());
+    stream.extend_from_slice(&identity.age.to_le_bytes());
+    stream.extend_from_slice(&identity.guid);
+
+    // Named streams: names, then a hash table of one entry mapping `/names` to its stream
+    let names = b"/names\0";
+    stream.extend_from_slice(&(names.len() as u32).to_le_bytes());
+    stream.extend_from_slice(names);
+    for value in [
+        1,                   // entries
+        1,                   // capacity
+        1,                   // present bit vector words
+        1,                   // present bit vector
+        0,                   // deleted bit vector words
+        0,                   // name offset
+        NAMES_STREAM as u32, // stream
+        PDB_FEATURE_VC140,
+    ] {
+        stream.extend_from_slice(&value.to_le_bytes());
+    }
+
+    stream
+}
+
+/// Empty string table.
+fn names_stream() -> Vec<u8> {
+    let mut stream = vec![];
+    for value in [0xeffeeffe_u32, 1, 1] {
+        stream.extend_from_slice(&value.to_le_bytes());
+    }
+    stream.push(0);
+    for value in [1_u32, 0, 0] {
+        stream.extend_from_slice(&value.to_le_bytes());
+    }
+    stream
+}
+
+/// Empty type stream, used for both TPI and IPI.
+fn type_stream() -> Vec<u8> {
+    let mut stream = vec![];
+    for value in [20040203_u32, 56, 0x1000, 0x1000, 0] {
+        stream.extend_from_slice(&value.to_le_bytes());
+    }
+    stream.extend_from_slice(&u16::MAX.to_le_bytes());
+    stream.extend_from_slice(&u16::MAX.to_le_bytes());
+    for value in [4_u32, 0x3ffff, 0, 0, 0, 0, 0, 0] {
+        stream.extend_from_slice(&value.to_le_bytes());
+    }
+    stream
+}
+
+/// `S_PUB32` records of `functions`, with their offsets in the stream.
+fn public_symbols<'a>(
+    pe: &PE,
+    functions: &[NamedFunction<'a>],
+) -> (Vec<u8>, Vec<PublicSymbol<'a>>) {
+    const S_PUB32: u16 = 0x110e;
+    /// `cvpsfCode | cvpsfFunction`
+    const FUNCTION_FLAGS: u32 = 3;
+
+    let mut stream = vec![];
+    let mut publics = vec![];
+    for function in functions {
+        let section = pe.sections.iter().position(|s| {
+            let size = s.virtual_size.max(s.size_of_raw_data);
+            s.virtual_address
+                .checked_add(size)
+                .is_some_and(|end| (s.virtual_address..end).contains(&function.rva))
+        });
+        let section = match section {
+            Some(section) => section,
+            None => continue,
+        };
+        let offset = function.rva - pe.sections[section].virtual_address;
+
+        let record_offset = stream.len();
+        let size = align(2 + 2 + 4 + 4 + 2 + function.name.len() + 1, 4);
+        stream.extend_from_slice(&((size - 2) as u16).to_le_bytes());
+        stream.extend_from_slice(&S_PUB32.to_le_bytes());
+        stream.extend_from_slice(&FUNCTION_FLAGS.to_le_bytes());
+        stream.extend_from_slice(&offset.to_le_bytes());
+        stream.extend_from_slice(&(section as u16 + 1).to_le_bytes());
+        stream.extend_from_slice(function.name.as_bytes());
+        stream.resize(record_offset + size, 0);
+
+        publics.push(PublicSymbol {
+            name: function.name.as_bytes(),
+            record_offset: record_offset as u32,
+            section: section as u16 + 1,
+            offset,
+        });
+    }
+
+    (stream, publics)
+}
+
+struct PublicSymbol<'a> {
+    name: &'a [u8],
+    record_offset: u32,
+    section: u16,
+    offset: u32,
+}
+
+const IPHR_HASH: usize = 4096;
+/// Size of a hash record in memory when the reference implementation loads the table.
+const HASH_RECORD_MEMORY_SIZE: u32 = 12;
+
+/// Order of symbols in a hash bucket expected by lookups: shorter names first, then
+/// case-insensitive.
+fn bucket_order(a: &PublicSymbol, b: &PublicSymbol) -> std::cmp::Ordering {
+    a.name
+        .len()
+        .cmp(&b.name.len())
+        .then_with(|| {
+            if a.name.is_ascii() && b.name.is_ascii() {
+                a.name
+                    .to_ascii_lowercase()
+                    .cmp(&b.name.to_ascii_lowercase())
+            } else {
+                a.name.cmp(b.name)
+            }
+        })
+        .then(a.record_offset.cmp(&b.record_offset))
+}
+
+/// GSI hash table of `symbols`, shared by the globals and publics streams.
+fn symbol_hash(symbols: &[PublicSymbol]) -> Vec<u8> {
+    let mut buckets: Vec<Vec<&PublicSymbol>> = vec![vec![]; IPHR_HASH];
+    for symbol in symbols {
+        buckets[hash_string_v1(symbol.name) as usize % IPHR_HASH].push(symbol);
+    }
+
+    let mut records = vec![];
+    let mut bitmap = vec![0u32; (IPHR_HASH + 32) / 32];
+    let mut starts = vec![];
+    let mut count = 0;
+    for (i, bucket) in buckets.iter_mut().enumerate() {
+        if bucket.is_empty() {
+            continue;
+        }
+        bucket.sort_by(|a, b| bucket_order(a, b));
+        bitmap[i / 32] |= 1 << (i % 32);
+        starts.push(count * HASH_RECORD_MEMORY_SIZE);
+        for symbol in bucket.iter() {
+            // Offsets are 1-based, the reference count is always 1
+            records.extend_from_slice(&(symbol.record_offset + 1).to_le_bytes());
+            records.extend_from_slice(&1u32.to_le_bytes());
+            count += 1;
+        }
+    }
+
+    let mut hash = vec![];
+    hash.extend_from_slice(&u32::MAX.to_le_bytes());
+    hash.extend_from_slice(&(0xeffe0000_u32 + 19990810).to_le_bytes());
+    hash.extend_from_slice(&(records.len() as u32).to_le_bytes());
+    hash.extend_from_slice(&(((bitmap.len() + starts.len()) * 4) as u32).to_le_bytes());
+    hash.extend_from_slice(&records);
+    for word in bitmap.iter().chain(&starts) {
+        hash.extend_from_slice(&word.to_le_bytes());
+    }
+    hash
+}
+
+fn publics_stream(publics: &[PublicSymbol]) -> Vec<u8> {
+    let hash = symbol_hash(publics);
+    let mut by_address: Vec<&PublicSymbol> = publics.iter().collect();
+    by_address.sort_by(|a, b| {
+        (a.section, a.offset)
+            .cmp(&(b.section, b.offset))
+            .then_with(|| a.name.cmp(b.name))
+    });
+
+    let mut stream = vec![];
+    stream.extend_from_slice(&(hash.len() as u32).to_le_bytes());
+    stream.extend_from_slice(&((by_address.len() * 4) as u32).to_le_bytes());
+    // No thunks
+    stream.extend_from_slice(&[0u8; 20]);
+    stream.extend_from_slice(&hash);
+    for symbol in by_address {
+        stream.extend_from_slice(&symbol.record_offset.to_le_bytes());
+    }
+    stream
+}
+
+/// Section map: each section is described by a segment of the same number, plus a last segment
+/// for absolute addresses.
+fn section_map(pe: &PE) -> Vec<u8> {
+    const READ: u16 = 1;
+    const WRITE: u16 = 2;
+    const EXECUTE: u16 = 4;
+    const ADDRESS_IS_32_BIT: u16 = 8;
+    const IS_SELECTOR: u16 = 0x100;
+    const IS_ABSOLUTE_ADDRESS: u16 = 0x200;
+
+    let count = pe.sections.len() as u16 + 1;
+    let mut map = vec![];
+    map.extend_from_slice(&count.to_le_bytes());
+    map.extend_from_slice(&count.to_le_bytes());
+    let mut entry = |flags: u16, frame: u16, length: u32| {
+        for value in [flags, 0, 0, frame, u16::MAX, u16::MAX] {
+            map.extend_from_slice(&value.to_le_bytes());
+        }
+        map.extend_from_slice(&0u32.to_le_bytes());
+        map.extend_from_slice(&length.to_le_bytes());
+    };
+    for (i, section) in pe.sections.iter().enumerate() {
+        let characteristics = section.characteristics;
+        let mut flags = ADDRESS_IS_32_BIT | IS_SELECTOR;
+        if characteristics & goblin::pe::section_table::IMAGE_SCN_MEM_READ != 0 {
+            flags |= READ;
+        }
+        if characteristics & goblin::pe::section_table::IMAGE_SCN_MEM_WRITE != 0 {
+            flags |= WRITE;
+        }
+        if characteristics & goblin::pe::section_table::IMAGE_SCN_MEM_EXECUTE != 0 {
+            flags |= EXECUTE;
+        }
+        entry(flags, i as u16 + 1, section.virtual_size);
+    }
+    entry(ADDRESS_IS_32_BIT | IS_ABSOLUTE_ADDRESS, count, u32::MAX);
+
+    map
+}
+
+fn dbi_stream(pe: &PE, identity: &PdbIdentity) -> Vec<u8> {
+    const DBI_VERSION_V70: u32 = 19990903;
+    /// New version format, 14.0
+    const BUILD_NUMBER: u16 = 0x8e00;
+    const SECTION_CONTRIBUTIONS_VER60: u32 = 0xeffe0000 + 19970605;
+
+    let section_contributions = SECTION_CONTRIBUTIONS_VER60.to_le_bytes().to_vec();
+    let section_map = section_map(pe);
+    // No modules, no source files
+    let file_info = vec![0u8; 4];
+    let mut debug_header = vec![];
+    for i in 0..11 {
+        let stream = match i {
+            5 => SECTION_HEADERS_STREAM as u16,
+            _ => u16::MAX,
+        };
+        debug_header.extend_from_slice(&stream.to_le_bytes());
+    }
+
+    let mut stream = vec![];
+    stream.extend_from_slice(&u32::MAX.to_le_bytes());
+    stream.extend_from_slice(&DBI_VERSION_V70.to_le_bytes());
+    stream.extend_from_slice(&identity.age.to_le_bytes());
+    for value in [
+        GLOBALS_STREAM as u16,
+        BUILD_NUMBER,
+        PUBLICS_STREAM as u16,
+        0,
+        SYMBOL_RECORDS_STREAM as u16,
+        0,
+    ] {
+        stream.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [
+        0,
+        section_contributions.len(),
+        section_map.len(),
+        file_info.len(),
+        0,
+        0,
+        debug_header.len(),
+        0,
+    ] {
+        stream.extend_from_slice(&(value as u32).to_le_bytes());
+    }
+    stream.extend_from_slice(&0u16.to_le_bytes());
+    stream.extend_from_slice(&pe.header.coff_header.machine.to_le_bytes());
+    stream.extend_from_slice(&0u32.to_le_bytes());
+
+    stream.extend_from_slice(&section_contributions);
+    stream.extend_from_slice(&section_map);
+    stream.extend_from_slice(&file_info);
+    stream.extend_from_slice(&debug_header);
+    stream
+}
+
+/// PDB of the PE file `bytes` with `functions` as public symbols, matching `identity`.
+///
+/// Only what debuggers need to name addresses is written: section headers and public symbols, no
+/// types nor modules.
+pub fn write_pdb(
+    bytes: &[u8],
+    identity: &PdbIdentity,
+    functions: &[NamedFunction],
+) -> Result<Vec<u8>, Error> {
+    let pe = parse_pe(bytes)?;
+    let layout = PeLayout::new(bytes, &pe)?;
+    let section_headers = bytes
+        .get(
+            layout.section_table..layout.section_table + layout.section_count * SECTION_HEADER_SIZE,
+        )
+        .ok_or_else(|| invalid("Section table out of bounds"))?;
+    let (symbol_records, publics) = public_symbols(&pe, functions);
+
+    let mut streams = vec![vec![]; STREAM_COUNT];
+    streams[PDB_STREAM] = pdb_info_stream(identity, pe.header.coff_header.time_date_stamp);
+    streams[TPI_STREAM] = type_stream();
+    streams[DBI_STREAM] = dbi_stream(&pe, identity);
+    streams[IPI_STREAM] = type_stream();
+    streams[NAMES_STREAM] = names_stream();
+    streams[SYMBOL_RECORDS_STREAM] = symbol_records;
+    streams[GLOBALS_STREAM] = symbol_hash(&[]);
+    streams[PUBLICS_STREAM] = publics_stream(&publics);
+    streams[SECTION_HEADERS_STREAM] = section_headers.to_vec();
+
+    Ok(write_msf(&streams))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use pdb::FallibleIterator;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    /// PE32+ with a `.text` and a `.rdata` section of 0x200 bytes each.
+    pub(crate) fn minimal_pe() -> Vec<u8> {
+        let mut pe = vec![0u8; 0x600];
+        pe[..2].copy_from_slice(b"MZ");
+        write_u32(&mut pe, 0x3c, 0x40);
+        pe[0x40..0x44].copy_from_slice(b"PE\0\0");
+
+        // COFF header: x86-64, 2 sections, executable image
+        let coff = 0x44;
+        pe[coff..coff + 2].copy_from_slice(&0x8664u16.to_le_bytes());
+        pe[coff + 2..coff + 4].copy_from_slice(&2u16.to_le_bytes());
+        pe[coff + 16..coff + 18].copy_from_slice(&0xf0u16.to_le_bytes());
+        pe[coff + 18..coff + 20].copy_from_slice(&0x22u16.to_le_bytes());
+
+        let optional = coff + 20;
+        pe[optional..optional + 2].copy_from_slice(&0x20bu16.to_le_bytes());
+        pe[optional + 24..optional + 32].copy_from_slice(&0x1_4000_0000u64.to_le_bytes());
+        write_u32(&mut pe, optional + 32, 0x1000); // section alignment
+        write_u32(&mut pe, optional + 36, 0x200); // file alignment
+        pe[optional + 48..optional + 50].copy_from_slice(&6u16.to_le_bytes());
+        write_u32(&mut pe, optional + 56, 0x3000); // size of image
+        write_u32(&mut pe, optional + 60, 0x200); // size of headers
+        pe[optional + 68..optional + 70].copy_from_slice(&3u16.to_le_bytes());
+        write_u32(&mut pe, optional + 108, 16); // data directories
+
+        for (i, (name, rva, characteristics)) in [
+            (b".text\0\0\0", 0x1000, 0x6000_0020u32),
+            (b".rdata\0\0", 0x2000, 0x4000_0040),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let header = optional + 0xf0 + i * SECTION_HEADER_SIZE;
+            pe[header..header + 8].copy_from_slice(*name);
+            write_u32(&mut pe, header + 8, 0x200);
+            write_u32(&mut pe, header + 12, *rva);
+            write_u32(&mut pe, header + 16, 0x200);
+            write_u32(&mut pe, header + 20, 0x200 * (i as u32 + 1));
+            write_u32(&mut pe, header + 36, *characteristics);
+        }
+
+        pe
+    }
+
+    #[test]
+    fn pdb_round_trips_through_pdb_crate() {
+        let bytes = minimal_pe();
+        let identity = PdbIdentity::from_content(&bytes, "sample.pdb");
+        let functions = [
+            NamedFunction {
+                name: "_ZN4core3fmt5write17h0123456789abcdefE",
+                rva: 0x1000,
+                size: 0x20,
+            },
+            NamedFunction {
+                name: "main",
+                rva: 0x1040,
+                size: 0,
+            },
+            NamedFunction {
+                name: "in_rdata",
+                rva: 0x2010,
+                size: 0,
+            },
+        ];
+
+        let written = write_pdb(&bytes, &identity, &functions).unwrap();
+        let mut pdb = pdb::PDB::open(Cursor::new(written)).unwrap();
+
+        let information = pdb.pdb_information().unwrap();
+        assert_eq!(information.guid.to_bytes_le(), identity.guid);
+        assert_eq!(information.age, identity.age);
+
+        let pe = PE::parse(&bytes).unwrap();
+        let sections = pdb.sections().unwrap().unwrap();
+        assert_eq!(sections.len(), pe.sections.len());
+        for (read, expected) in sections.iter().zip(&pe.sections) {
+            assert_eq!(read.name, expected.name);
+            assert_eq!(read.virtual_address, expected.virtual_address);
+            assert_eq!(read.virtual_size, expected.virtual_size);
+            assert_eq!(read.pointer_to_raw_data, expected.pointer_to_raw_data);
+        }
+
+        let address_map = pdb.address_map().unwrap();
+        let symbol_table = pdb.global_symbols().unwrap();
+        let mut symbols = symbol_table.iter();
+        let mut publics = HashMap::new();
+        while let Some(symbol) = symbols.next().unwrap() {
+            if let Ok(pdb::SymbolData::Public(public)) = symbol.parse() {
+                assert!(public.function);
+                let rva = public.offset.to_rva(&address_map).unwrap();
+                publics.insert(rva.0, public.name.to_string().to_string());
+            }
+        }
+        assert_eq!(publics.len(), functions.len());
+        for function in &functions {
+            assert_eq!(publics[&function.rva], function.name);
+        }
+    }
+
+    #[test]
+    fn codeview_entry_round_trips() {
+        let bytes = minimal_pe();
+        assert!(codeview_identity(&bytes).unwrap().is_none());
+
+        let identity = PdbIdentity::from_content(&bytes, "C:\\symbols\\sample.pdb");
+        let patched = add_codeview_entry(&bytes, &identity).unwrap();
+        let read = codeview_identity(&patched).unwrap().unwrap();
+        assert_eq!(read.guid, identity.guid);
+        assert_eq!(read.age, identity.age);
+        assert_eq!(read.path, identity.path);
+    }
+
+    #[test]
+    fn codeview_record_out_of_bounds_is_skipped() {
+        let bytes = minimal_pe();
+        let identity = PdbIdentity::from_content(&bytes, "sample.pdb");
+        let mut patched = add_codeview_entry(&bytes, &identity).unwrap();
+
+        let pe = PE::parse(&patched).unwrap();
+        let layout = PeLayout::new(&patched, &pe).unwrap();
+        let rva = read_u32(&patched, layout.data_directory(DEBUG_DATA_DIRECTORY)).unwrap();
+        let entry = rva_to_pa(&pe, rva).unwrap() as usize;
+        // Size and file offset of the record overflowing a u32
+        write_u32(&mut patched, entry + 16, 0x100);
+        write_u32(&mut patched, entry + 24, u32::MAX - 0x10);
+
+        // goblin may already reject the entry, but it must never be read
+        assert!(codeview_identity(&patched).ok().flatten().is_none());
+    }
+}
@@ -0,0 +1,141 @@
+# Imports functions exported by Ariane with --fid-dir into a Ghidra Function ID database.
+#
+# Headless:
+#   analyzeHeadless <project dir> <project name> -scriptPath tools/ghidra \
+#       -preScript ImportArianeFid.py <out.fidb> <crate-version.fid.json>...
+# From the script manager, the database and the exports are asked for.
+#
+# Ghidra's hashes depend on the processor specification, so exports only hold the bytes of the
+# functions: they are loaded into a scratch program and hashed by Ghidra itself. The number of
+# instructions Ariane decoded is only used to report differences.
+#@category FunctionID
+
+import json
+
+import jarray
+from java.io import File
+
+from ghidra.app.cmd.disassemble import DisassembleCommand
+from ghidra.app.cmd.function import CreateFunctionCmd
+from ghidra.feature.fid.db import FidFileManager
+from ghidra.feature.fid.service import FidService
+from ghidra.framework import Application
+from ghidra.program.database import ProgramDB
+from ghidra.program.model.lang import CompilerSpecID, LanguageID
+from ghidra.program.util import DefaultLanguageService
+
+FORMAT_VERSION = 2
+
+# Where functions are laid out in the scratch program
+BASE_ADDRESS = 0x10000
+ALIGNMENT = 16
+
+
+def open_fid_db(path):
+    manager = FidFileManager.getInstance()
+    fid_file = File(path)
+    if not fid_file.exists():
+        manager.createNewFidDatabase(fid_file)
+    for existing in manager.getUserAddedFiles():
+        if existing.getFile().getCanonicalPath() == fid_file.getCanonicalPath():
+            return existing.getFidDB(True)
+    return manager.addUserFidFile(fid_file).getFidDB(True)
+
+
+def to_java_bytes(hex_bytes):
+    return jarray.array([b - 256 if b > 127 else b for b in bytearray.fromhex(hex_bytes)], 'b')
+
+
+def load_functions(library, functions):
+    """Scratch program holding every function of the library, and their entry points."""
+    language = DefaultLanguageService.getLanguageService().getLanguage(
+        LanguageID(library['language']))
+    compiler_spec = language.getCompilerSpecByID(CompilerSpecID(library['compiler_spec']))
+    program = ProgramDB('ariane-fid', language, compiler_spec, this)
+    space = program.getAddressFactory().getDefaultAddressSpace()
+
+    entries = []
+    offset = BASE_ADDRESS
+    for function in functions:
+        entries.append(offset)
+        offset += len(function['bytes']) // 2
+        offset = (offset + ALIGNMENT - 1) // ALIGNMENT * ALIGNMENT
+
+    transaction = program.startTransaction('Load functions')
+    try:
+        memory = program.getMemory()
+        block = memory.createInitializedBlock(
+            '.text', space.getAddress(BASE_ADDRESS), offset - BASE_ADDRESS, 0, monitor, False)
+        block.setExecute(True)
+        for function, entry in zip(functions, entries):
+            address = space.getAddress(entry)
+            memory.setBytes(address, to_java_bytes(function['bytes']))
+            DisassembleCommand(address, None, True).applyTo(program, monitor)
+            CreateFunctionCmd(address).applyTo(program, monitor)
+    finally:
+        program.endTransaction(transaction, True)
+
+    return program, [space.getAddress(entry) for entry in entries]
+
+
+def import_library(fid_db, library):
+    functions = library['functions']
+    program, entries = load_functions(library, functions)
+    try:
+        language = program.getLanguage()
+        record = fid_db.createNewLibrary(
+            library['family'], library['version'], library['variant'],
+            Application.getApplicationVersion(), language.getLanguageID(),
+            language.getVersion(), language.getMinorVersion(),
+            program.getCompilerSpec().getCompilerSpecID())
+        domain_path = '/ariane/%s-%s' % (library['family'], library['version'])
+
+        service = FidService()
+        manager = program.getFunctionManager()
+        imported = skipped = different = 0
+        for function, entry in zip(functions, entries):
+            ghidra_function = manager.getFunctionAt(entry)
+            quad = service.hashFunction(ghidra_function) if ghidra_function else None
+            if quad is None:
+                skipped += 1
+                continue
+            if quad.getCodeUnitSize() != function['code_unit_size']:
+                different += 1
+            fid_db.createNewFunction(record, quad, function['name'], entry.getOffset(),
+                                     domain_path, True)
+            imported += 1
+    finally:
+        program.release(this)
+
+    println('%s %s (%s): %d functions imported, %d too short for Ghidra, %d decoded differently'
+            % (library['family'], library['version'], library['variant'], imported, skipped,
+               different))
+
+
+def run():
+    args = list(getScriptArgs())
+    if args:
+        if len(args) < 2:
+            printerr('Usage: ImportArianeFid.py <out.fidb> <export.fid.json>...')
+            return
+        fidb_path, exports = args[0], args[1:]
+    else:
+        fidb_path = askFile('Function ID database', 'Create or open').getAbsolutePath()
+        exports = [askFile('Ariane export (.fid.json)', 'Import').getAbsolutePath()]
+
+    fid_db = open_fid_db(fidb_path)
+    try:
+        for path in exports:
+            with open(path) as f:
+                export = json.load(f)
+            if export.get('format_version') != FORMAT_VERSION:
+                printerr('%s: unsupported format version %s' % (path, export.get('format_version')))
+                continue
+            for library in export['libraries']:
+                import_library(fid_db, library)
+        fid_db.saveDatabase('Imported Ariane functions', monitor)
+    finally:
+        fid_db.close()
+
+
+run()
@@ -0,0 +1,50 @@
+# Prints the TLSH digests and distances expected by the tests of src/sig/tlsh.rs, computed with
+# py-tlsh, the Python binding of the reference implementation (128 buckets, 1 byte checksum).
+#
+#   pip install py-tlsh
+#   python tools/tlsh_vectors.py
+#
+# The inputs must stay in sync with the test helpers.
+
+import tlsh
+
+
+def lcg(length, seed):
+    """Pseudo-random bytes, from the `rand` LCG of the C standard."""
+    x = seed
+    data = bytearray()
+    for _ in range(length):
+        x = (x * 1103515245 + 12345) & 0x7FFFFFFF
+        data.append((x >> 16) & 0xFF)
+    return bytes(data)
+
+
+def text():
+    return b"The quick brown fox jumps over the lazy dog. " * 4 + b"Pack my box with five dozen liquor jugs!"
+
+
+def random_edited():
+    data = bytearray(lcg(512, 42))
+    data[100:110] = bytes(10)
+    return bytes(data)
+
+
+digests = {
+    "TEXT": tlsh.hash(text()),
+    "TEXT_EDITED": tlsh.hash(text().replace(b"lazy", b"idle")),
+    "RANDOM": tlsh.hash(lcg(512, 42)),
+    "RANDOM_EDITED": tlsh.hash(random_edited()),
+    "LONG": tlsh.hash(lcg(4000, 7)),
+}
+for name, digest in digests.items():
+    print('const %s: &str = "%s";' % (name, digest))
+
+print()
+for a, b in [
+    ("TEXT", "TEXT"),
+    ("TEXT", "TEXT_EDITED"),
+    ("RANDOM", "RANDOM_EDITED"),
+    ("TEXT", "RANDOM"),
+    ("RANDOM", "LONG"),
+]:
+    print("distance(%s, %s) = %d" % (a, b, tlsh.diff(digests[a], digests[b])))